@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+/// (name, is a directory, modification time) of a directory entry
+pub type DirEntry = (String, bool, SystemTime);
+
+// how long Tab waits for a slow directory before showing what has been read so far
+const LIST_DEADLINE: Duration = Duration::from_millis(200);
+
+// the whole cache is dropped once it holds this many directories
+const MAX_CACHED_DIRS: usize = 64;
+
+// the entries examined at most in a directory, the rest are only counted
+const MAX_ENTRIES: usize = 50_000;
+
+// the threads reading the metadata of the entries of a directory
+const WORKERS: usize = 4;
+
+struct Listing {
+    /// the modification time of the directory when it was read
+    mtime: Option<SystemTime>,
+    entries: Vec<DirEntry>,
+    /// the number of the entries found, whose metadata is being read
+    found: usize,
+    /// the number of the entries found after MAX_ENTRIES
+    skipped: usize,
+    /// whether the directory has been read to the end
+    complete: bool,
+}
+
+impl Listing {
+    fn is_done(&self) -> bool {
+        self.complete && self.entries.len() == self.found
+    }
+
+    // the number of the entries found but not examined
+    fn unexamined(&self) -> usize {
+        self.found - self.entries.len() + self.skipped
+    }
+}
+
+static DIR_CACHE: Mutex<Option<HashMap<PathBuf, Arc<Mutex<Listing>>>>> = Mutex::new(None);
+
+/// Returns the entries of `dir` and the number of the ones not examined. Directories are read on
+/// worker threads and cached until they are modified; if reading takes too long, only the entries
+/// read so far are returned. At most `MAX_ENTRIES` entries are examined in a directory.
+pub fn list_dir(dir: &Path) -> Option<(Vec<DirEntry>, usize)> {
+    let mtime = std::fs::metadata(dir).ok()?.modified().ok();
+    let listing = cached_listing(dir, mtime)?;
+
+    let deadline = Instant::now() + LIST_DEADLINE;
+    loop {
+        {
+            let listing = listing.lock().unwrap();
+            if listing.is_done() || Instant::now() >= deadline {
+                return Some((listing.entries.clone(), listing.unexamined()));
+            }
+        }
+        std::thread::sleep(Duration::from_millis(5));
+    }
+}
+
+// finds the listing of `dir` or starts reading it
+fn cached_listing(dir: &Path, mtime: Option<SystemTime>) -> Option<Arc<Mutex<Listing>>> {
+    let mut cache = DIR_CACHE.lock().unwrap();
+    let cache = cache.get_or_insert_with(HashMap::new);
+
+    if let Some(listing) = cache.get(dir) {
+        // a listing being read is reused as well
+        if mtime.is_some() && listing.lock().unwrap().mtime == mtime {
+            return Some(listing.clone());
+        }
+    }
+
+    let entries = std::fs::read_dir(dir).ok()?;
+    let listing = Arc::new(Mutex::new(Listing {
+        mtime,
+        entries: Vec::new(),
+        found: 0,
+        skipped: 0,
+        complete: false,
+    }));
+
+    let shared = listing.clone();
+    std::thread::spawn(move || {
+        // the entries found are passed to the workers which read their metadata
+        let (sender, receiver) = mpsc::channel::<(String, std::fs::DirEntry)>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..WORKERS {
+            let receiver = receiver.clone();
+            let shared = shared.clone();
+            std::thread::spawn(move || loop {
+                let Ok((name, ent)) = receiver.lock().unwrap().recv() else {
+                    break;
+                };
+                let meta = ent.metadata().ok();
+                let is_dir = meta.as_ref().map(|m| m.is_dir()).unwrap_or(false);
+                let modified = meta.and_then(|m| m.modified().ok());
+                let entry = (name, is_dir, modified.unwrap_or(SystemTime::UNIX_EPOCH));
+                shared.lock().unwrap().entries.push(entry);
+            });
+        }
+
+        for ent in entries.filter_map(|ent| ent.ok()) {
+            let Some(name) = ent.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            let mut listing = shared.lock().unwrap();
+            if listing.found < MAX_ENTRIES {
+                listing.found += 1;
+                drop(listing);
+                let _ = sender.send((name, ent));
+            } else {
+                listing.skipped += 1;
+            }
+        }
+        shared.lock().unwrap().complete = true;
+    });
+
+    if cache.len() >= MAX_CACHED_DIRS {
+        cache.clear();
+    }
+    cache.insert(dir.to_owned(), listing.clone());
+    Some(listing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_listing() {
+        let mut dir = std::env::temp_dir();
+        dir.push("shell-test-dir-cache");
+        std::fs::create_dir(&dir).unwrap();
+        let _cleanup = crate::utils::Defer::new({
+            let dir = dir.clone();
+            move || {
+                let _ = std::fs::remove_dir_all(dir);
+            }
+        });
+
+        let names = |dir: &Path| -> Vec<String> {
+            let (entries, unexamined) = list_dir(dir).unwrap();
+            assert_eq!(unexamined, 0);
+            let mut names: Vec<_> = entries.into_iter().map(|(name, _, _)| name).collect();
+            names.sort();
+            names
+        };
+
+        std::fs::File::create(dir.join("foo")).unwrap();
+        assert_eq!(names(&dir), ["foo"]);
+
+        // modifying the directory invalidates the cache
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::create_dir(dir.join("bar")).unwrap();
+        assert_eq!(names(&dir), ["bar", "foo"]);
+
+        assert!(list_dir(&dir.join("nonexistent")).is_none());
+    }
+}
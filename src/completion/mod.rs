@@ -0,0 +1,1082 @@
+mod dir_cache;
+mod spec;
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+pub trait Complete {
+    /// Returns the words which can replace the last one of `words`, the best one first
+    fn candidates(&self, words: &[&str]) -> Vec<Candidate>;
+}
+
+/// A word to complete with, which may be explained in the completion menu
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Candidate {
+    pub text: String,
+    pub description: Option<String>,
+}
+
+impl Candidate {
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            description: None,
+        }
+    }
+
+    /// Parses a word given to `complete`, which may be described like "add:Add file contents"
+    pub fn parse(word: &str) -> Self {
+        match word.split_once(':') {
+            Some((text, description)) if !text.is_empty() => Self {
+                text: text.to_owned(),
+                description: Some(description.to_owned()),
+            },
+            _ => Self::new(word.to_owned()),
+        }
+    }
+}
+
+/// Completion for arguments of a command, registered by the `complete` builtin
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompletionRule {
+    Words(Vec<String>),
+    Files,
+    Commands,
+    External(Vec<String>),
+}
+
+impl std::fmt::Display for CompletionRule {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CompletionRule::Words(words) => write!(f, "words {}", words.join(" ")),
+            CompletionRule::Files => write!(f, "files"),
+            CompletionRule::Commands => write!(f, "commands"),
+            CompletionRule::External(command) => write!(f, "external {}", command.join(" ")),
+        }
+    }
+}
+
+pub struct CommandCompletion {
+    commands: StaticWordCompletion,
+    /// names of the commands to look up quickly
+    command_names: HashSet<String>,
+    rules: HashMap<String, Box<dyn Complete>>,
+    /// the commands whose rules are registered by the `complete` builtin
+    user_commands: HashSet<String>,
+    help_flags: HelpFlagCompletion,
+    fallback: Box<dyn Complete>,
+}
+
+impl CommandCompletion {
+    pub fn new(commands: Vec<Candidate>, fallback: Box<dyn Complete>) -> Self {
+        let mut rules: HashMap<String, Box<dyn Complete>> = HashMap::new();
+        rules.insert("kill".to_owned(), Box::new(ProcessCompletion::pids()));
+        rules.insert("pkill".to_owned(), Box::new(ProcessCompletion::names()));
+
+        Self {
+            command_names: commands.iter().map(|cand| cand.text.clone()).collect(),
+            commands: StaticWordCompletion::new(commands),
+            rules,
+            user_commands: HashSet::new(),
+            help_flags: HelpFlagCompletion::new(),
+            fallback,
+        }
+    }
+
+    /// Registers the rules of the `complete` builtin, dropping the ones removed since the last call
+    pub fn update_rules(&mut self, rules: &HashMap<String, CompletionRule>) {
+        for cmd in self.user_commands.drain() {
+            if !rules.contains_key(&cmd) {
+                self.rules.remove(&cmd);
+            }
+        }
+        for (cmd, rule) in rules {
+            let completion: Box<dyn Complete> = match rule {
+                CompletionRule::Words(items) => {
+                    let items = items.iter().map(|item| Candidate::parse(item)).collect();
+                    Box::new(StaticWordCompletion::new(items))
+                }
+                CompletionRule::Files => Box::new(FileCompletion::new()),
+                CompletionRule::Commands => {
+                    Box::new(StaticWordCompletion::new(self.commands.items.clone()))
+                }
+                CompletionRule::External(command) => {
+                    Box::new(ExternalCompletion::new(command.clone()))
+                }
+            };
+            self.add_completion(cmd.clone(), completion);
+        }
+        self.user_commands = rules.keys().cloned().collect();
+    }
+
+    pub fn update_commands(&mut self, new_commands: Vec<Candidate>) {
+        self.command_names = new_commands.iter().map(|cand| cand.text.clone()).collect();
+        self.commands = StaticWordCompletion::new(new_commands.clone());
+
+        // FIXME
+        self.rules.insert(
+            "sudo".to_owned(),
+            Box::new(StaticWordCompletion::new(new_commands)),
+        );
+    }
+
+    /// Replaces the job specs offered to job control builtins
+    pub fn update_jobs(&mut self, jobs: Vec<Candidate>) {
+        for builtin in ["fg", "disown"] {
+            let completion = StaticWordCompletion::new(jobs.clone());
+            self.rules.insert(builtin.to_owned(), Box::new(completion));
+        }
+    }
+
+    /// Registers the completion definitions found in `dir`
+    pub fn load_specs(&mut self, dir: &Path) {
+        for (cmd, spec) in spec::load_specs(dir) {
+            self.rules.insert(cmd, Box::new(spec));
+        }
+    }
+
+    pub fn add_completion(&mut self, cmd: String, completion: Box<dyn Complete>) {
+        self.rules.insert(cmd, completion);
+    }
+
+    /// Returns true if `cmd_name` is a known command or a path to an executable file
+    pub fn is_command(&self, cmd_name: &str) -> bool {
+        use std::os::unix::ffi::OsStringExt as _;
+        use std::os::unix::fs::PermissionsExt as _;
+
+        if !cmd_name.contains('/') {
+            return self.command_names.contains(cmd_name);
+        }
+        let path = std::ffi::OsString::from_vec(expand_tilde(cmd_name.as_bytes()));
+        match std::fs::metadata(path) {
+            Ok(meta) => meta.is_file() && meta.permissions().mode() & 0o111 != 0,
+            Err(_) => false,
+        }
+    }
+
+    // builtins and aliases are described as such
+    fn is_external(&self, cmd_name: &str) -> bool {
+        self.commands
+            .items
+            .iter()
+            .any(|cand| cand.text == cmd_name && cand.description.is_none())
+    }
+}
+
+impl Complete for CommandCompletion {
+    fn candidates(&self, words: &[&str]) -> Vec<Candidate> {
+        if words.len() <= 1 {
+            // for command name
+            let cand = self.commands.candidates(words);
+            if cand.is_empty() {
+                self.fallback.candidates(words)
+            } else {
+                cand
+            }
+        } else {
+            // for arguments
+            let cmd_name = words[0];
+            if let Some(comp) = self.rules.get(cmd_name) {
+                comp.candidates(words)
+            } else if words[words.len() - 1].starts_with('-') && self.is_external(cmd_name) {
+                self.help_flags.candidates(words)
+            } else {
+                self.fallback.candidates(words)
+            }
+        }
+    }
+}
+
+pub struct StaticWordCompletion {
+    items: Vec<Candidate>,
+}
+
+impl StaticWordCompletion {
+    pub fn new(items: Vec<Candidate>) -> Self {
+        Self { items }
+    }
+
+    #[allow(unused)]
+    pub fn from_words(words: Vec<String>) -> Self {
+        Self::new(words.into_iter().map(Candidate::new).collect())
+    }
+}
+
+impl Complete for StaticWordCompletion {
+    fn candidates(&self, words: &[&str]) -> Vec<Candidate> {
+        if let Some(word) = words.last() {
+            filter_matches(word, self.items.iter().cloned())
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Returns the items starting with `word` in alphabetical order.
+/// If there is no such item, the ones containing the characters of `word` in order are
+/// returned instead, ranked by how well they match.
+pub fn filter_matches(word: &str, items: impl Iterator<Item = Candidate>) -> Vec<Candidate> {
+    let mut prefixed = Vec::new();
+    let mut fuzzy = Vec::new();
+    for item in items {
+        if item.text.starts_with(word) {
+            prefixed.push(item);
+        } else if prefixed.is_empty() {
+            if let Some(score) = fuzzy_score(word, &item.text) {
+                fuzzy.push((score, item));
+            }
+        }
+    }
+
+    if !prefixed.is_empty() {
+        prefixed.sort_unstable();
+        prefixed.dedup();
+        return prefixed;
+    }
+
+    fuzzy.sort_unstable_by(|(sa, a), (sb, b)| sb.cmp(sa).then_with(|| a.cmp(b)));
+    fuzzy.dedup();
+    fuzzy.into_iter().map(|(_, item)| item).collect()
+}
+
+/// Returns the longest prefix shared by all the candidates
+pub fn common_prefix(candidates: &[Candidate]) -> String {
+    let Some((first, rest)) = candidates.split_first() else {
+        return String::new();
+    };
+
+    let mut len = first.text.len();
+    for cand in rest {
+        len = first
+            .text
+            .char_indices()
+            .zip(cand.text.chars())
+            .take_while(|((_, a), b)| a == b)
+            .map(|((i, a), _)| i + a.len_utf8())
+            .last()
+            .unwrap_or(0)
+            .min(len);
+    }
+    first.text[..len].to_owned()
+}
+
+// scores a subsequence match of `pattern` in `candidate`, preferring characters at word
+// boundaries and consecutive ones; the match is case-insensitive unless `pattern` has uppercase
+fn fuzzy_score(pattern: &str, candidate: &str) -> Option<i64> {
+    let ignore_case = !pattern.chars().any(char::is_uppercase);
+    let chars: Vec<char> = candidate.chars().collect();
+    let same = |a: char, b: char| {
+        if ignore_case {
+            a.to_lowercase().eq(b.to_lowercase())
+        } else {
+            a == b
+        }
+    };
+
+    let mut score = 0;
+    let mut pos = 0;
+    for p in pattern.chars() {
+        let i = (pos..chars.len()).find(|&i| same(chars[i], p))?;
+
+        let at_boundary = i == 0
+            || "-_./ ".contains(chars[i - 1])
+            || (chars[i - 1].is_lowercase() && chars[i].is_uppercase());
+        if at_boundary {
+            score += 10;
+        } else if i > 0 && i == pos && pos > 0 {
+            score += 5; // consecutive
+        }
+        score -= (i - pos) as i64;
+        pos = i + 1;
+    }
+
+    // shorter ones are closer to what is typed
+    score -= (chars.len() - pos) as i64 / 4;
+    Some(score)
+}
+
+use crate::core::expand_tilde;
+
+#[derive(Default)]
+pub struct FileCompletion(());
+
+impl FileCompletion {
+    pub fn new() -> Self {
+        Self(())
+    }
+
+    fn find(&self, partial: &str) -> Option<Vec<Candidate>> {
+        let mut path = if partial.starts_with('~') {
+            use std::ffi::OsString;
+            use std::os::unix::ffi::OsStringExt as _;
+
+            let expanded = expand_tilde(partial.as_bytes());
+            PathBuf::from(OsString::from_vec(expanded))
+        } else {
+            Path::new(partial).to_owned()
+        };
+
+        if path.is_relative() {
+            let mut tmp = std::env::current_dir().ok()?;
+            tmp.extend(path.as_path());
+            path = tmp;
+        }
+
+        let (dir, pat);
+        if partial.ends_with(std::path::MAIN_SEPARATOR) || partial.is_empty() {
+            dir = path.as_path();
+            pat = "";
+        } else {
+            dir = path.parent()?;
+            pat = path.file_name()?.to_str()?;
+        }
+
+        let (mut entries, unexamined) = dir_cache::list_dir(dir)?;
+        UNEXAMINED_FILES.store(unexamined, Ordering::Relaxed);
+        sort_files(&mut entries, SORT_BY_MTIME.load(Ordering::Relaxed));
+
+        // the directory part is kept as typed
+        let typed_dir = &partial[..partial
+            .rfind(std::path::MAIN_SEPARATOR)
+            .map_or(0, |i| i + 1)];
+
+        let names = entries
+            .iter()
+            .map(|(name, _, _)| Candidate::new(name.clone()));
+        let mut candidates: Vec<Candidate> = names
+            .clone()
+            .filter(|cand| cand.text.starts_with(pat))
+            .collect();
+        if candidates.is_empty() {
+            candidates = filter_matches(pat, names);
+        }
+
+        // append a slash if there is a single candidate
+        if let [cand] = candidates.as_mut_slice() {
+            if entries
+                .iter()
+                .any(|(ent, is_dir, _)| *ent == cand.text && *is_dir)
+            {
+                cand.text.push(std::path::MAIN_SEPARATOR);
+            }
+        }
+
+        for cand in candidates.iter_mut() {
+            cand.text = format!("{typed_dir}{}", Self::escape_special_characters(&cand.text));
+        }
+        Some(candidates)
+    }
+
+    pub fn escape_special_characters(candidate: &str) -> String {
+        // example:
+        //   "foo bar" --> "foo\ bar"
+        //   "foo@bar" --> "foo\@bar"
+
+        let mut buf = String::new();
+        for ch in candidate.chars() {
+            if let '\\' | ' ' | '\t' | '\n' | '@' | ';' | '&' | '|' | '$' | '(' | ')' | '[' | ']'
+            | '\'' | '\"' | '=' | '?' | '{' | '}' = ch
+            {
+                buf.push('\\');
+            }
+            buf.push(ch);
+        }
+        buf
+    }
+}
+
+/// Describes the file which the completed word `candidate` refers to with its type, size,
+/// modification time and the first line if it is a text file
+pub fn file_preview(candidate: &str) -> Option<String> {
+    use std::os::unix::ffi::OsStringExt as _;
+    use std::os::unix::fs::{FileTypeExt as _, MetadataExt as _, PermissionsExt as _};
+
+    // undo `escape_special_characters`
+    let mut unescaped = String::new();
+    let mut chars = candidate.chars();
+    while let Some(ch) = chars.next() {
+        if ch == '\\' {
+            unescaped.extend(chars.next());
+        } else {
+            unescaped.push(ch);
+        }
+    }
+    let path = PathBuf::from(std::ffi::OsString::from_vec(expand_tilde(
+        unescaped.as_bytes(),
+    )));
+
+    let meta = std::fs::symlink_metadata(&path).ok()?;
+    let file_type = meta.file_type();
+    let kind = if file_type.is_symlink() {
+        let target = std::fs::read_link(&path).ok()?;
+        format!("symlink to {}", target.display())
+    } else if file_type.is_dir() {
+        "directory".to_owned()
+    } else if file_type.is_fifo() {
+        "fifo".to_owned()
+    } else if file_type.is_socket() {
+        "socket".to_owned()
+    } else if file_type.is_block_device() || file_type.is_char_device() {
+        "device".to_owned()
+    } else if meta.permissions().mode() & 0o111 != 0 {
+        "executable".to_owned()
+    } else {
+        "file".to_owned()
+    };
+
+    let mut preview = format!(
+        "{kind}, {}, {}",
+        format_size(meta.len()),
+        format_time(meta.mtime())
+    );
+    if file_type.is_file() {
+        if let Some(line) = first_line(&path) {
+            preview.push_str(&format!(": {line}"));
+        }
+    }
+    Some(preview)
+}
+
+// formats a size like "512B", "1.5K" or "12M"
+fn format_size(size: u64) -> String {
+    let mut size = size as f64;
+    for unit in ["B", "K", "M", "G"] {
+        if size < 1024.0 {
+            return if unit == "B" || size >= 10.0 {
+                format!("{size:.0}{unit}")
+            } else {
+                format!("{size:.1}{unit}")
+            };
+        }
+        size /= 1024.0;
+    }
+    format!("{size:.0}T")
+}
+
+// formats seconds since the epoch like "2022-01-02 03:04" in the local time
+fn format_time(secs: i64) -> String {
+    let mut tm = unsafe { std::mem::zeroed::<nix::libc::tm>() };
+    unsafe {
+        nix::libc::localtime_r(&secs, &mut tm);
+    }
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        tm.tm_year + 1900,
+        tm.tm_mon + 1,
+        tm.tm_mday,
+        tm.tm_hour,
+        tm.tm_min
+    )
+}
+
+// returns the first line of a text file, or None if it looks binary
+fn first_line(path: &Path) -> Option<String> {
+    use std::io::Read as _;
+
+    let mut head = [0; 256];
+    let len = std::fs::File::open(path).ok()?.read(&mut head).ok()?;
+    let head = &head[..len];
+    if head.contains(&0) {
+        return None;
+    }
+    let line = head.split(|b| *b == b'\n').next()?;
+    // the last char may be cut in the middle
+    let text = match std::str::from_utf8(line) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&line[..err.valid_up_to()]).ok()?
+        }
+        Err(_) => return None,
+    };
+    Some(text.chars().filter(|ch| !ch.is_control()).collect())
+}
+
+static SORT_BY_MTIME: AtomicBool = AtomicBool::new(false);
+
+static UNEXAMINED_FILES: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of the files not examined by the last file completion in a huge or slow
+/// directory, and resets it
+pub fn take_unexamined_files() -> usize {
+    UNEXAMINED_FILES.swap(0, Ordering::Relaxed)
+}
+
+/// Makes file candidates ordered by modification time instead of alphabetically
+pub fn sort_files_by_mtime(enabled: bool) {
+    SORT_BY_MTIME.store(enabled, Ordering::Relaxed);
+}
+
+// directories come first and dotfiles last, then the newest one or alphabetically ignoring case
+fn sort_files(files: &mut [dir_cache::DirEntry], by_mtime: bool) {
+    files.sort_by(|(a, a_dir, a_time), (b, b_dir, b_time)| {
+        b_dir
+            .cmp(a_dir)
+            .then_with(|| a.starts_with('.').cmp(&b.starts_with('.')))
+            .then_with(|| {
+                if by_mtime {
+                    b_time.cmp(a_time)
+                } else {
+                    std::cmp::Ordering::Equal
+                }
+            })
+            .then_with(|| a.to_lowercase().cmp(&b.to_lowercase()))
+            .then_with(|| a.cmp(b))
+    });
+}
+
+impl Complete for FileCompletion {
+    fn candidates(&self, words: &[&str]) -> Vec<Candidate> {
+        if let Some(word) = words.last() {
+            self.find(word).unwrap_or_default()
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// Completion for flags of commands without a rule, parsed from the output of `cmd --help`.
+/// The result is cached for each command.
+#[derive(Default)]
+pub struct HelpFlagCompletion {
+    cache: RefCell<HashMap<String, Vec<Candidate>>>,
+}
+
+// commands run for completion are killed if they don't finish in time
+const HELPER_TIMEOUT: Duration = Duration::from_secs(1);
+
+// runs `command` without the terminal and returns its output
+pub(crate) fn run_helper(command: &mut std::process::Command) -> Option<std::process::Output> {
+    use std::os::unix::process::CommandExt as _;
+    use std::process::Stdio;
+
+    // in its own process group, the command can't read from or stop the terminal
+    let child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .ok()?;
+    let pgid = nix::unistd::Pid::from_raw(child.id() as i32);
+
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(child.wait_with_output());
+    });
+
+    match rx.recv_timeout(HELPER_TIMEOUT) {
+        Ok(Ok(output)) => Some(output),
+        _ => {
+            let group = nix::unistd::Pid::from_raw(-pgid.as_raw());
+            let _ = nix::sys::signal::kill(group, nix::sys::signal::Signal::SIGKILL);
+            None
+        }
+    }
+}
+
+impl HelpFlagCompletion {
+    pub fn new() -> Self {
+        Self {
+            cache: RefCell::new(HashMap::new()),
+        }
+    }
+
+    fn run_help(cmd_name: &str) -> Option<String> {
+        let output = run_helper(std::process::Command::new(cmd_name).arg("--help"))?;
+        // some commands print the usage to stderr
+        let mut help = output.stdout;
+        help.extend(output.stderr);
+        Some(String::from_utf8_lossy(&help).into_owned())
+    }
+}
+
+/// Extracts the flags listed in `help` along with their descriptions
+fn parse_help(help: &str) -> Vec<Candidate> {
+    let is_flag = |word: &str| {
+        let name = word.trim_start_matches('-');
+        word.starts_with('-')
+            && word.len() - name.len() <= 2
+            && name.starts_with(|c: char| c.is_ascii_alphanumeric())
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    };
+
+    let mut flags: Vec<Candidate> = Vec::new();
+    for line in help.lines() {
+        let line = line.trim_start();
+        if !line.starts_with('-') {
+            continue;
+        }
+
+        // e.g. "-o, --output=FILE   write to FILE"
+        let (spec, description) = match line.find("  ") {
+            Some(i) => (&line[..i], line[i..].trim()),
+            None => (line, ""),
+        };
+        for word in spec.split(|c: char| c == ',' || c.is_whitespace()) {
+            let flag = word.split(['=', '[']).next().unwrap_or_default();
+            if is_flag(flag) && flags.iter().all(|f| f.text != flag) {
+                flags.push(Candidate {
+                    text: flag.to_owned(),
+                    description: Some(description.to_owned()).filter(|d| !d.is_empty()),
+                });
+            }
+        }
+    }
+    flags
+}
+
+impl Complete for HelpFlagCompletion {
+    fn candidates(&self, words: &[&str]) -> Vec<Candidate> {
+        let (Some(cmd_name), Some(word)) = (words.first(), words.last()) else {
+            return Vec::new();
+        };
+
+        let mut cache = self.cache.borrow_mut();
+        let flags = cache.entry(cmd_name.to_string()).or_insert_with(|| {
+            Self::run_help(cmd_name)
+                .map(|help| parse_help(&help))
+                .unwrap_or_default()
+        });
+        filter_matches(word, flags.iter().cloned())
+    }
+}
+
+/// Completion by an external command, which is given the words as arguments and prints
+/// a candidate per line. Candidates may be described like "WORD<TAB>DESCRIPTION".
+pub struct ExternalCompletion {
+    command: Vec<String>,
+}
+
+impl ExternalCompletion {
+    pub fn new(command: Vec<String>) -> Self {
+        Self { command }
+    }
+}
+
+fn parse_external_output(output: &str) -> Vec<Candidate> {
+    output
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_once('\t') {
+            Some((text, description)) => Candidate {
+                text: text.to_owned(),
+                description: Some(description.to_owned()),
+            },
+            None => Candidate::new(line.to_owned()),
+        })
+        .collect()
+}
+
+impl Complete for ExternalCompletion {
+    fn candidates(&self, words: &[&str]) -> Vec<Candidate> {
+        let (Some((program, args)), Some(word)) = (self.command.split_first(), words.last()) else {
+            return Vec::new();
+        };
+
+        let mut command = std::process::Command::new(program);
+        command.args(args).args(words);
+        let Some(output) = run_helper(&mut command) else {
+            return Vec::new();
+        };
+        let items = parse_external_output(&String::from_utf8_lossy(&output.stdout));
+        filter_matches(word, items.into_iter())
+    }
+}
+
+/// Completion for running processes: PIDs for `kill`, and names for `pkill`.
+/// PIDs can also be found by typing the name of the process.
+pub struct ProcessCompletion {
+    by_name: bool,
+}
+
+impl ProcessCompletion {
+    pub fn pids() -> Self {
+        Self { by_name: false }
+    }
+
+    pub fn names() -> Self {
+        Self { by_name: true }
+    }
+}
+
+struct Process {
+    pid: u32,
+    name: String,
+    cmdline: String,
+}
+
+fn running_processes() -> Vec<Process> {
+    let Ok(entries) = std::fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+
+    entries
+        .filter_map(|ent| {
+            let ent = ent.ok()?;
+            let pid = ent.file_name().to_str()?.parse().ok()?;
+            let name = std::fs::read_to_string(ent.path().join("comm")).ok()?;
+            let name = name.trim_end().to_owned();
+            let cmdline = std::fs::read(ent.path().join("cmdline")).ok()?;
+            // kernel threads have no command line, so they are shown like `ps` does
+            let cmdline = format_cmdline(&cmdline).unwrap_or_else(|| format!("[{name}]"));
+            Some(Process { pid, name, cmdline })
+        })
+        .collect()
+}
+
+// arguments in /proc/<pid>/cmdline are separated by NUL
+fn format_cmdline(raw: &[u8]) -> Option<String> {
+    let args: Vec<_> = raw
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect();
+    if args.is_empty() {
+        None
+    } else {
+        Some(args.join(" "))
+    }
+}
+
+impl Complete for ProcessCompletion {
+    fn candidates(&self, words: &[&str]) -> Vec<Candidate> {
+        let Some(word) = words.last() else {
+            return Vec::new();
+        };
+        // signals and options are not completed
+        if word.starts_with('-') {
+            return Vec::new();
+        }
+
+        let procs = running_processes();
+        let describe = |text: String, proc: &Process| Candidate {
+            text,
+            description: Some(proc.cmdline.clone()),
+        };
+
+        if self.by_name {
+            let mut names: Vec<_> = procs.iter().map(|p| describe(p.name.clone(), p)).collect();
+            names.sort_unstable_by(|a, b| a.text.cmp(&b.text));
+            names.dedup_by(|a, b| a.text == b.text);
+            filter_matches(word, names.into_iter())
+        } else if word.chars().all(|c| c.is_ascii_digit()) {
+            let pids = procs.iter().map(|p| describe(p.pid.to_string(), p));
+            filter_matches(word, pids)
+        } else {
+            // the PIDs of the processes whose name matches
+            let names = procs.iter().map(|p| Candidate::new(p.name.clone()));
+            filter_matches(word, names)
+                .iter()
+                .flat_map(|name| procs.iter().filter(move |p| p.name == name.text))
+                .map(|p| describe(p.pid.to_string(), p))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::time::SystemTime;
+
+    macro_rules! set_eq {
+        ($a:expr, $b:expr) => {{
+            let a = HashSet::<String>::from_iter($a.into_iter().map(|c: Candidate| c.text));
+            let b = HashSet::<String>::from_iter($b);
+            assert_eq!(a, b);
+        }};
+    }
+
+    #[test]
+    fn static_word_completion() {
+        let comp = StaticWordCompletion::from_words(vec![
+            "foo".into(),
+            "foobar".into(),
+            "fox".into(),
+            "bar".into(),
+        ]);
+        set_eq!(
+            comp.candidates(&["fo"]).into_iter(),
+            vec!["foo".into(), "foobar".into(), "fox".into()] as Vec<String>
+        );
+        set_eq!(
+            comp.candidates(&["foo"]),
+            vec!["foo".into(), "foobar".into()] as Vec<String>
+        );
+        set_eq!(comp.candidates(&["bar"]), vec!["bar".into()] as Vec<String>);
+        set_eq!(comp.candidates(&["ba"]), vec!["bar".into()] as Vec<String>);
+
+        // containing space
+        let comp = StaticWordCompletion::from_words(vec!["foo bar".into()]);
+        set_eq!(
+            comp.candidates(&["fo"]),
+            vec!["foo bar".into()] as Vec<String>
+        );
+        set_eq!(
+            comp.candidates(&["foo b"]),
+            vec!["foo bar".into()] as Vec<String>
+        );
+
+        // empty
+        let comp = StaticWordCompletion::from_words(vec![]);
+        set_eq!(comp.candidates(&["foo"]), vec![] as Vec<String>);
+        set_eq!(comp.candidates(&["bar"]), vec![] as Vec<String>);
+    }
+
+    #[test]
+    fn fuzzy_completion() {
+        let items = [
+            "git-checkout-helper",
+            "gcc",
+            "graphicsconv",
+            "gzip",
+            "GitCommitOrganizer",
+        ];
+        let matched = |word| -> Vec<String> {
+            let items = items.iter().map(|item| Candidate::new(item.to_string()));
+            filter_matches(word, items)
+                .into_iter()
+                .map(|cand| cand.text)
+                .collect()
+        };
+
+        // prefix matches come first
+        assert_eq!(matched("gc"), vec!["gcc"]);
+        assert_eq!(
+            matched("gco"),
+            vec!["GitCommitOrganizer", "git-checkout-helper", "graphicsconv"]
+        );
+        assert_eq!(matched("GCO"), vec!["GitCommitOrganizer"]);
+        assert_eq!(matched("gx"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn longest_common_prefix() {
+        let cands = |words: &[&str]| -> Vec<Candidate> {
+            words
+                .iter()
+                .map(|w| Candidate::new(w.to_string()))
+                .collect()
+        };
+        assert_eq!(common_prefix(&cands(&["foo", "foobar"])), "foo");
+        assert_eq!(common_prefix(&cands(&["foobar", "foobaz", "fox"])), "fo");
+        assert_eq!(common_prefix(&cands(&["あいう", "あいえ"])), "あい");
+        assert_eq!(common_prefix(&cands(&["foo", "bar"])), "");
+        assert_eq!(common_prefix(&cands(&[])), "");
+    }
+
+    #[test]
+    fn process_cmdline() {
+        assert_eq!(
+            format_cmdline(b"vim\0-u\0NONE\0foo bar.txt\0").as_deref(),
+            Some("vim -u NONE foo bar.txt")
+        );
+        assert_eq!(format_cmdline(b"").as_deref(), None);
+    }
+
+    #[test]
+    fn help_flags() {
+        let help = "Usage: ls [OPTION]... [FILE]...
+  -a, --all                  do not ignore entries starting with .
+      --color[=WHEN]         color the output
+  -w, --width=COLS           set output width to COLS
+  -1                         list one file per line
+      --help     display this help and exit
+  ---weird
+  -- not a flag
+";
+        let flags: Vec<_> = parse_help(help)
+            .into_iter()
+            .map(|cand| (cand.text, cand.description.unwrap_or_default()))
+            .collect();
+        assert_eq!(
+            flags,
+            vec![
+                ("-a".into(), "do not ignore entries starting with .".into()),
+                (
+                    "--all".into(),
+                    "do not ignore entries starting with .".into()
+                ),
+                ("--color".into(), "color the output".into()),
+                ("-w".into(), "set output width to COLS".into()),
+                ("--width".into(), "set output width to COLS".into()),
+                ("-1".into(), "list one file per line".into()),
+                ("--help".into(), "display this help and exit".into()),
+            ] as Vec<(String, String)>
+        );
+    }
+
+    #[test]
+    fn external_completion() {
+        let comp = ExternalCompletion::new(vec![
+            "sh".into(),
+            "-c".into(),
+            "printf 'start\\tStart a unit\\nstop\\nstatus\\n'".into(),
+        ]);
+        let cands = comp.candidates(&["systemctl", "st"]);
+        assert_eq!(
+            cands,
+            vec![
+                Candidate {
+                    text: "start".into(),
+                    description: Some("Start a unit".into()),
+                },
+                Candidate::new("status".into()),
+                Candidate::new("stop".into()),
+            ]
+        );
+
+        let comp = ExternalCompletion::new(vec!["/nonexistent".into()]);
+        assert!(comp.candidates(&["foo", ""]).is_empty());
+    }
+
+    #[test]
+    fn file_order() {
+        let at = |secs| SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        let mut files = vec![
+            ("b.txt".to_owned(), false, at(3)),
+            (".hidden".to_owned(), false, at(5)),
+            ("A.txt".to_owned(), false, at(1)),
+            ("src".to_owned(), true, at(0)),
+            ("c.txt".to_owned(), false, at(2)),
+            (".git".to_owned(), true, at(4)),
+        ];
+        let names = |files: &[(String, bool, SystemTime)]| -> Vec<String> {
+            files.iter().map(|(name, _, _)| name.clone()).collect()
+        };
+
+        sort_files(&mut files, false);
+        assert_eq!(
+            names(&files),
+            ["src", ".git", "A.txt", "b.txt", "c.txt", ".hidden"]
+        );
+        sort_files(&mut files, true);
+        assert_eq!(
+            names(&files),
+            ["src", ".git", "b.txt", "c.txt", "A.txt", ".hidden"]
+        );
+    }
+
+    #[test]
+    fn described_words() {
+        let comp = StaticWordCompletion::new(vec![
+            Candidate::parse("add:Add file contents"),
+            Candidate::parse("am"),
+            Candidate::parse(":colon"),
+        ]);
+        let cands = comp.candidates(&["git", "a"]);
+        assert_eq!(cands[0].text, "add");
+        assert_eq!(cands[0].description.as_deref(), Some("Add file contents"));
+        assert_eq!(cands[1].text, "am");
+        assert_eq!(cands[1].description, None);
+        assert_eq!(comp.candidates(&[":"])[0].text, ":colon");
+    }
+
+    fn create_file(name: &str) {
+        std::fs::write(name, b"").unwrap();
+    }
+    fn create_dir(name: &str) {
+        std::fs::create_dir(name).unwrap();
+    }
+
+    #[test]
+    fn preview() {
+        assert_eq!(format_size(512), "512B");
+        assert_eq!(format_size(1536), "1.5K");
+        assert_eq!(format_size(20 * 1024 * 1024), "20M");
+
+        let dir = std::env::temp_dir().join(format!("preview-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let file = dir.join("a b.txt");
+        std::fs::write(&file, "first line\nsecond line\n").unwrap();
+        std::fs::write(dir.join("bin"), b"\x7fELF\0\0").unwrap();
+
+        let escaped = format!("{}/a\\ b.txt", dir.display());
+        let preview = file_preview(&escaped).unwrap();
+        assert!(preview.starts_with("file, 23B, "), "{preview}");
+        assert!(preview.ends_with(": first line"), "{preview}");
+
+        let preview = file_preview(&format!("{}/bin", dir.display())).unwrap();
+        assert!(!preview.contains(": "), "{preview}");
+        let preview = file_preview(&format!("{}/", dir.display())).unwrap();
+        assert!(preview.starts_with("directory, "), "{preview}");
+        assert_eq!(file_preview(&format!("{}/none", dir.display())), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn file_completion() {
+        {
+            let old_dir = std::env::current_dir().unwrap();
+
+            let mut temp_dir = std::env::temp_dir();
+            temp_dir.push("shell-test");
+            std::fs::create_dir(&temp_dir).unwrap();
+
+            std::env::set_current_dir(&temp_dir).unwrap();
+            create_file("./foo");
+            create_file("./foobar");
+            create_dir("./dir");
+
+            let _restore_cwd = crate::utils::Defer::new(move || {
+                let _ = std::env::set_current_dir(old_dir);
+                let _ = std::fs::remove_dir_all(temp_dir);
+            });
+
+            let comp = FileCompletion::new();
+            set_eq!(
+                comp.candidates(&["foo"]),
+                vec!["foo".into(), "foobar".into()] as Vec<String>
+            );
+            set_eq!(
+                comp.candidates(&["f"]),
+                vec!["foo".into(), "foobar".into()] as Vec<String>
+            );
+            set_eq!(
+                comp.candidates(&[""]),
+                vec!["foo".into(), "foobar".into(), "dir".into()] as Vec<String>
+            );
+            set_eq!(comp.candidates(&["d"]), vec!["dir/".into()] as Vec<String>);
+            set_eq!(
+                comp.candidates(&["./d"]),
+                vec!["./dir/".into()] as Vec<String>
+            );
+            set_eq!(
+                comp.candidates(&["fb"]),
+                vec!["foobar".into()] as Vec<String>
+            );
+        }
+
+        {
+            let old_dir = std::env::current_dir().unwrap();
+
+            let mut temp_dir = std::env::temp_dir();
+            temp_dir.push("shell-test");
+            std::fs::create_dir(&temp_dir).unwrap();
+
+            std::env::set_current_dir(&temp_dir).unwrap();
+            create_dir("./dup1");
+            create_dir("./dup2");
+            create_dir("./uniq");
+
+            let _restore_cwd = crate::utils::Defer::new(move || {
+                let _ = std::env::set_current_dir(old_dir);
+                let _ = std::fs::remove_dir_all(temp_dir);
+            });
+
+            let comp = FileCompletion::new();
+            set_eq!(
+                comp.candidates(&["d"]),
+                vec!["dup1".into(), "dup2".into()] as Vec<String>
+            );
+            set_eq!(comp.candidates(&["u"]), vec!["uniq/".into()] as Vec<String>);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Completion definitions written in a subset of TOML, e.g. `git.toml`:
+//!
+//! ```toml
+//! flags = ["--version:Print the version", "-C"]
+//! subcommands = ["add:Add file contents", "status"]
+//! args = "none"
+//!
+//! [commit]
+//! flags = ["-m:Use the given message", "--amend"]
+//! ```
+//!
+//! Words may be described like the ones given to `complete`. `args` is "files" (the default),
+//! "none", or an array of words. A table defines the flags and arguments of a subcommand.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::{filter_matches, Candidate, Complete, FileCompletion, StaticWordCompletion};
+use crate::toml::{self, Value};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Args {
+    Files,
+    Words(Vec<Candidate>),
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompletionSpec {
+    subcommands: Vec<Candidate>,
+    flags: Vec<Candidate>,
+    args: Args,
+    sub_specs: HashMap<String, CompletionSpec>,
+}
+
+impl Default for CompletionSpec {
+    fn default() -> Self {
+        Self {
+            subcommands: Vec::new(),
+            flags: Vec::new(),
+            args: Args::Files,
+            sub_specs: HashMap::new(),
+        }
+    }
+}
+
+/// Loads `<command>.toml` files in `dir`, reporting broken ones to stderr
+pub fn load_specs(dir: &Path) -> HashMap<String, CompletionSpec> {
+    let mut specs = HashMap::new();
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return specs;
+    };
+
+    for path in entries.filter_map(|ent| ent.ok()).map(|ent| ent.path()) {
+        if path.extension().and_then(|ext| ext.to_str()) != Some("toml") {
+            continue;
+        }
+        let Some(cmd_name) = path.file_stem().and_then(|stem| stem.to_str()) else {
+            continue;
+        };
+
+        let parsed = std::fs::read_to_string(&path)
+            .map_err(|err| err.to_string())
+            .and_then(|content| CompletionSpec::parse(&content));
+        match parsed {
+            Ok(spec) => {
+                specs.insert(cmd_name.to_owned(), spec);
+            }
+            Err(err) => eprintln!("{}: {err}", path.display()),
+        }
+    }
+    specs
+}
+
+impl CompletionSpec {
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut spec = Self::default();
+        for table in toml::parse(content)? {
+            let top = table.name.is_empty();
+            let target = if top {
+                &mut spec
+            } else {
+                spec.sub_specs.entry(table.name.clone()).or_default()
+            };
+
+            for (key, value) in table.entries {
+                let invalid = || format!("[{}] {key}: invalid entry", table.name);
+                let words = |items: &[Value]| -> Result<Vec<Candidate>, String> {
+                    items
+                        .iter()
+                        .map(|item| match item {
+                            Value::String(word) => Ok(Candidate::parse(word)),
+                            _ => Err(invalid()),
+                        })
+                        .collect()
+                };
+                match (key.as_str(), &value) {
+                    ("flags", Value::Array(items)) => target.flags = words(items)?,
+                    ("subcommands", Value::Array(items)) if top => {
+                        target.subcommands = words(items)?
+                    }
+                    ("args", Value::Array(items)) => target.args = Args::Words(words(items)?),
+                    ("args", Value::String(kind)) if kind == "files" => target.args = Args::Files,
+                    ("args", Value::String(kind)) if kind == "none" => target.args = Args::None,
+                    _ => return Err(invalid()),
+                }
+            }
+        }
+
+        // subcommands are defined by either of the list or a table
+        for sub in spec.subcommands.iter() {
+            spec.sub_specs.entry(sub.text.clone()).or_default();
+        }
+        for name in spec.sub_specs.keys() {
+            if spec.subcommands.iter().all(|sub| sub.text != *name) {
+                spec.subcommands.push(Candidate::new(name.clone()));
+            }
+        }
+        Ok(spec)
+    }
+}
+
+impl Complete for CompletionSpec {
+    fn candidates(&self, words: &[&str]) -> Vec<Candidate> {
+        let Some((word, given)) = words.split_last() else {
+            return Vec::new();
+        };
+
+        // follow the subcommand given before the current word
+        let mut spec = self;
+        let mut positional = false;
+        for arg in given.iter().skip(1).filter(|arg| !arg.starts_with('-')) {
+            match spec.sub_specs.get(*arg) {
+                Some(sub) if !positional => spec = sub,
+                _ => positional = true,
+            }
+        }
+
+        if word.starts_with('-') {
+            return filter_matches(word, spec.flags.iter().cloned());
+        }
+        if !positional && !spec.subcommands.is_empty() {
+            return filter_matches(word, spec.subcommands.iter().cloned());
+        }
+        match &spec.args {
+            Args::Files => FileCompletion::new().candidates(words),
+            Args::Words(items) => StaticWordCompletion::new(items.clone()).candidates(words),
+            Args::None => Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn completion_spec() {
+        let spec = CompletionSpec::parse(
+            r#"
+# git.toml
+flags = ["--version:Print the version", "-C"]
+subcommands = [
+    "add:Add file contents",
+    'status',
+]
+
+[commit]
+flags = ["-m", "--amend"]
+args = "none"
+
+[remote]
+args = ["add", "remove"]
+"#,
+        )
+        .unwrap();
+
+        let texts = |words: &[&str]| -> Vec<String> {
+            spec.candidates(words).into_iter().map(|c| c.text).collect()
+        };
+        assert_eq!(texts(&["git", ""]), ["add", "commit", "remote", "status"]);
+        assert_eq!(texts(&["git", "--v"]), ["--version"]);
+        assert_eq!(texts(&["git", "commit", "--"]), ["--amend"]);
+        assert_eq!(texts(&["git", "commit", ""]), [] as [String; 0]);
+        assert_eq!(texts(&["git", "-C", "remote", "r"]), ["remove"]);
+        assert_eq!(
+            spec.candidates(&["git", "a"])[0].description.as_deref(),
+            Some("Add file contents")
+        );
+
+        assert!(CompletionSpec::parse("flags = [\"-a\"").is_err());
+        assert!(CompletionSpec::parse("args = \"dirs\"").is_err());
+        assert!(CompletionSpec::parse("[commit\nflags = []").is_err());
+    }
+}
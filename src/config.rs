@@ -0,0 +1,233 @@
+//! The declarative configuration in `config.toml` under the config directory, which is loaded
+//! before the `startup` script. Each table is applied through the builtin of the same purpose:
+//!
+//! - `[aliases]`: `name = "words"` or `name = ["word", ...]` as `alias name = words`
+//! - `[abbreviations]`: `name = "expansion"` as `abbr name expansion`
+//! - `[env]`: `NAME = "value"` as `export NAME=value`
+//! - `[variables]`: `NAME = "value"` as `var NAME = value`
+//! - `[options]`: `name = true` as `set -o name`, or `set +o name` if false
+//! - `[keybindings]`, `[keybindings.MODE]`: `"key" = "function"` as `bindkey [-M MODE] key function`
+//! - `[completions]`: `command = ["word", ...]`, `"files"` or `"commands"` as `complete`
+//! - `[colors]`: `command`, `unknown-command`, `highlight` and `suggestion` are set to the
+//!   variables `COLOR_COMMAND` and so on, which are names like `"bold red"` as in `PROMPT`
+//!
+//! The file is written in the subset of TOML which the completion definitions use too.
+
+use crate::core::Shell;
+use std::path::Path;
+
+pub use crate::toml::{parse, Table, Value};
+
+/// Reads the configuration in `path`. Returns an empty one if the file doesn't exist.
+pub fn load(path: &Path) -> Result<Vec<Table>, String> {
+    match std::fs::read_to_string(path) {
+        Ok(text) => parse(&text).map_err(|err| format!("{}: {err}", path.display())),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(err) => Err(format!("{}: {err}", path.display())),
+    }
+}
+
+/// Loads `config.toml` and then evaluates the `startup` script. Returns the status of the last
+/// line of the script, or 1 if the configuration is invalid, or None if neither of them exists.
+pub fn load_rc(shell: &mut Shell) -> Option<i32> {
+    let mut config_ok = true;
+    if let Some(dir) = crate::config_dir() {
+        match load(&dir.join("config.toml")) {
+            Ok(tables) => config_ok = apply(shell, &tables),
+            Err(err) => {
+                eprintln!("config: {err}");
+                config_ok = false;
+            }
+        }
+    }
+
+    let status = if shell.exit_status().is_none() {
+        eval_file(shell, "startup")
+    } else {
+        None
+    };
+    if config_ok {
+        status
+    } else {
+        Some(status.filter(|status| *status != 0).unwrap_or(1))
+    }
+}
+
+/// Evaluates the file named `name` in the application directory line by line, if it exists
+pub fn eval_file(shell: &mut Shell, name: &str) -> Option<i32> {
+    use std::io::{BufRead as _, BufReader};
+
+    let file_path = crate::application_dir()?.join(name);
+    let file = match std::fs::File::open(&file_path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
+        _ => return Some(1),
+    };
+
+    let mut status = 0;
+    for line in BufReader::new(file).lines().map_while(Result::ok) {
+        let line = line.trim();
+        if !line.is_empty() {
+            status = shell.eval(line);
+        }
+
+        if shell.exit_status().is_some() {
+            break;
+        }
+    }
+    Some(status)
+}
+
+/// Applies the configuration to the shell, and returns false if any entry is invalid
+pub fn apply(shell: &mut Shell, tables: &[Table]) -> bool {
+    let mut ok = true;
+    for table in tables {
+        for (key, value) in table.entries.iter() {
+            let Some(args) = builtin_args(&table.name, key, value) else {
+                eprintln!("config: [{}] {key}: invalid entry", table.name);
+                ok = false;
+                continue;
+            };
+            let args: Vec<&str> = args.iter().map(String::as_str).collect();
+            if shell.run_builtin(&args) != 0 {
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
+// the arguments of the builtin which applies the entry
+fn builtin_args(table: &str, key: &str, value: &Value) -> Option<Vec<String>> {
+    let owned = |args: &[&str]| -> Vec<String> { args.iter().map(|arg| arg.to_string()).collect() };
+    let words = |value: &Value| -> Option<Vec<String>> {
+        match value {
+            Value::String(words) => Some(words.split_whitespace().map(str::to_owned).collect()),
+            Value::Array(items) => items.iter().map(scalar).collect(),
+            _ => None,
+        }
+    };
+
+    let args = match (table, value) {
+        ("aliases", value) => {
+            let words = words(value).filter(|words| !words.is_empty())?;
+            [owned(&["alias", key, "="]), words].concat()
+        }
+        ("abbreviations", value) => owned(&["abbr", key, &scalar(value)?]),
+        ("env", value) => owned(&["export", &format!("{key}={}", scalar(value)?)]),
+        ("variables", value) => owned(&["var", key, "=", &scalar(value)?]),
+        ("options", Value::Boolean(enable)) => {
+            owned(&["set", if *enable { "-o" } else { "+o" }, key])
+        }
+        ("keybindings", value) => owned(&["bindkey", key, &scalar(value)?]),
+        (table, value) if table.starts_with("keybindings.") => {
+            let mode = &table["keybindings.".len()..];
+            owned(&["bindkey", "-M", mode, key, &scalar(value)?])
+        }
+        ("completions", Value::String(kind)) => owned(&["complete", key, "=", kind]),
+        ("completions", Value::Array(_)) => {
+            [owned(&["complete", key, "=", "words"]), words(value)?].concat()
+        }
+        ("colors", Value::String(names)) => {
+            let name = match key {
+                "command" => "COLOR_COMMAND",
+                "unknown-command" => "COLOR_UNKNOWN_COMMAND",
+                "highlight" => "COLOR_HIGHLIGHT",
+                "suggestion" => "COLOR_SUGGESTION",
+                _ => return None,
+            };
+            owned(&["var", name, "=", names])
+        }
+        _ => return None,
+    };
+    Some(args)
+}
+
+// a string, an integer or a boolean as a word
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(string) => Some(string.clone()),
+        Value::Integer(n) => Some(n.to_string()),
+        Value::Boolean(b) => Some(b.to_string()),
+        Value::Array(_) => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn config_toml() {
+        let text = r#"
+# comment
+[aliases]
+ll = "ls -l"  # trailing comment
+gs = ['git', 'status']
+
+[keybindings.normal]
+"C-r" = "fuzzy-history"
+
+[options]
+autopair = true
+"#;
+        let tables = parse(text).unwrap();
+        assert_eq!(
+            tables,
+            vec![
+                Table {
+                    name: "aliases".to_owned(),
+                    entries: vec![
+                        ("ll".to_owned(), Value::String("ls -l".to_owned())),
+                        (
+                            "gs".to_owned(),
+                            Value::Array(vec![
+                                Value::String("git".to_owned()),
+                                Value::String("status".to_owned()),
+                            ])
+                        ),
+                    ],
+                },
+                Table {
+                    name: "keybindings.normal".to_owned(),
+                    entries: vec![("C-r".to_owned(), Value::String("fuzzy-history".to_owned()))],
+                },
+                Table {
+                    name: "options".to_owned(),
+                    entries: vec![("autopair".to_owned(), Value::Boolean(true))],
+                },
+            ]
+        );
+
+        assert_eq!(
+            builtin_args("aliases", "gs", &tables[0].entries[1].1),
+            Some(vec!["alias", "gs", "=", "git", "status"])
+                .map(|args| args.into_iter().map(str::to_owned).collect())
+        );
+        assert_eq!(
+            builtin_args("keybindings.normal", "C-r", &tables[1].entries[0].1),
+            Some(vec!["bindkey", "-M", "normal", "C-r", "fuzzy-history"])
+                .map(|args| args.into_iter().map(str::to_owned).collect())
+        );
+        assert_eq!(
+            builtin_args("options", "autopair", &Value::Integer(1)),
+            None
+        );
+
+        let escaped = parse(r#"x = "a\t\"b\" é""#).unwrap();
+        assert_eq!(
+            escaped[0].entries[0].1,
+            Value::String("a\t\"b\" é".to_owned())
+        );
+        assert_eq!(
+            parse("n = -1_000").unwrap()[0].entries[0].1,
+            Value::Integer(-1000)
+        );
+        assert_eq!(parse("\n[a\nx = 1").unwrap_err(), "line 2: expected `]`");
+        assert_eq!(parse("x = 'a").unwrap_err(), "line 1: unterminated string");
+        assert_eq!(
+            parse("x = 1\nx = 2").unwrap_err(),
+            "line 2: x is defined twice"
+        );
+    }
+}
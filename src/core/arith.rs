@@ -0,0 +1,232 @@
+/// Evaluates an integer expression like "i * (j + 1) % 3".
+/// Identifiers are resolved by `lookup`; unset variables are treated as 0.
+pub fn evaluate<F>(expr: &str, lookup: F) -> Result<i64, String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let tokens = tokenize(expr)?;
+    let mut parser = Parser {
+        tokens: &tokens,
+        pos: 0,
+        lookup,
+    };
+
+    let value = parser.expr(0)?;
+    match parser.tokens.get(parser.pos) {
+        None => Ok(value),
+        Some(tok) => Err(format!("unexpected token: {tok}")),
+    }
+}
+
+enum Token {
+    Num(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl std::fmt::Display for Token {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Token::Num(n) => write!(f, "{n}"),
+            Token::Ident(name) => write!(f, "{name}"),
+            Token::Op(op) => write!(f, "{op}"),
+        }
+    }
+}
+
+// longer operators must come first
+const OPERATORS: &[&str] = &[
+    "**", "==", "!=", "<=", ">=", "&&", "||", "<<", ">>", "+", "-", "*", "/", "%", "<", ">", "!",
+    "~", "&", "|", "^", "(", ")",
+];
+
+fn tokenize(expr: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = expr.trim_start();
+
+    while let Some(ch) = rest.chars().next() {
+        if ch.is_ascii_digit() {
+            let len = rest
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(rest.len());
+            let num = rest[..len]
+                .parse()
+                .map_err(|_| format!("number too large: {}", &rest[..len]))?;
+            tokens.push(Token::Num(num));
+            rest = &rest[len..];
+        } else if ch.is_ascii_alphabetic() || ch == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_owned()));
+            rest = &rest[len..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            return Err(format!("invalid character: {ch}"));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+struct Parser<'a, F> {
+    tokens: &'a [Token],
+    pos: usize,
+    lookup: F,
+}
+
+// returns (precedence, right associative) of binary operators
+fn binary_op(op: &str) -> Option<(u8, bool)> {
+    let prec = match op {
+        "||" => (1, false),
+        "&&" => (2, false),
+        "|" => (3, false),
+        "^" => (4, false),
+        "&" => (5, false),
+        "==" | "!=" => (6, false),
+        "<" | "<=" | ">" | ">=" => (7, false),
+        "<<" | ">>" => (8, false),
+        "+" | "-" => (9, false),
+        "*" | "/" | "%" => (10, false),
+        "**" => (11, true),
+        _ => return None,
+    };
+    Some(prec)
+}
+
+impl<'a, F> Parser<'a, F>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    fn next(&mut self) -> Option<&'a Token> {
+        let tok = self.tokens.get(self.pos);
+        self.pos += 1;
+        tok
+    }
+
+    fn expr(&mut self, min_prec: u8) -> Result<i64, String> {
+        let mut lhs = self.unary()?;
+
+        while let Some(Token::Op(op)) = self.tokens.get(self.pos) {
+            let Some((prec, right_assoc)) = binary_op(op) else {
+                break;
+            };
+            if prec < min_prec {
+                break;
+            }
+            self.pos += 1;
+
+            let next_min = if right_assoc { prec } else { prec + 1 };
+            let rhs = self.expr(next_min)?;
+            lhs = apply(op, lhs, rhs)?;
+        }
+
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> Result<i64, String> {
+        match self.next() {
+            Some(Token::Num(n)) => Ok(*n),
+            Some(Token::Ident(name)) => self.variable(name),
+            Some(Token::Op("(")) => {
+                let value = self.expr(0)?;
+                match self.next() {
+                    Some(Token::Op(")")) => Ok(value),
+                    _ => Err("missing `)`".to_owned()),
+                }
+            }
+            Some(Token::Op("-")) => {
+                let value = self.unary()?;
+                value.checked_neg().ok_or_else(|| "overflow".to_owned())
+            }
+            Some(Token::Op("+")) => self.unary(),
+            Some(Token::Op("!")) => Ok((self.unary()? == 0) as i64),
+            Some(Token::Op("~")) => Ok(!self.unary()?),
+            Some(tok) => Err(format!("unexpected token: {tok}")),
+            None => Err("unexpected end of expression".to_owned()),
+        }
+    }
+
+    fn variable(&self, name: &str) -> Result<i64, String> {
+        match (self.lookup)(name) {
+            None => Ok(0),
+            Some(value) if value.trim().is_empty() => Ok(0),
+            Some(value) => value
+                .trim()
+                .parse()
+                .map_err(|_| format!("{name}: not an integer: {value}")),
+        }
+    }
+}
+
+fn apply(op: &str, lhs: i64, rhs: i64) -> Result<i64, String> {
+    let overflow = || "overflow".to_owned();
+    let value = match op {
+        "+" => lhs.checked_add(rhs).ok_or_else(overflow)?,
+        "-" => lhs.checked_sub(rhs).ok_or_else(overflow)?,
+        "*" => lhs.checked_mul(rhs).ok_or_else(overflow)?,
+        "/" | "%" if rhs == 0 => return Err("division by zero".to_owned()),
+        "/" => lhs.checked_div(rhs).ok_or_else(overflow)?,
+        "%" => lhs.checked_rem(rhs).ok_or_else(overflow)?,
+        "**" => {
+            let exp = u32::try_from(rhs).map_err(|_| "negative exponent".to_owned())?;
+            lhs.checked_pow(exp).ok_or_else(overflow)?
+        }
+        "<<" => lhs
+            .checked_shl(u32::try_from(rhs).map_err(|_| overflow())?)
+            .ok_or_else(overflow)?,
+        ">>" => lhs
+            .checked_shr(u32::try_from(rhs).map_err(|_| overflow())?)
+            .ok_or_else(overflow)?,
+        "&" => lhs & rhs,
+        "|" => lhs | rhs,
+        "^" => lhs ^ rhs,
+        "==" => (lhs == rhs) as i64,
+        "!=" => (lhs != rhs) as i64,
+        "<" => (lhs < rhs) as i64,
+        "<=" => (lhs <= rhs) as i64,
+        ">" => (lhs > rhs) as i64,
+        ">=" => (lhs >= rhs) as i64,
+        "&&" => (lhs != 0 && rhs != 0) as i64,
+        "||" => (lhs != 0 || rhs != 0) as i64,
+        _ => unreachable!(),
+    };
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(expr: &str) -> Result<i64, String> {
+        evaluate(expr, |name| match name {
+            "i" => Some("41".to_owned()),
+            "s" => Some("foo".to_owned()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("i + 1"), Ok(42));
+        assert_eq!(eval("undefined + 1"), Ok(1));
+        assert_eq!(eval("-i % 5"), Ok(-1));
+        assert_eq!(eval("10 - 4 - 3"), Ok(3));
+        assert_eq!(eval("2 ** 3 ** 2"), Ok(512));
+        assert_eq!(eval("1 << 4 | 1"), Ok(17));
+        assert_eq!(eval("i > 40 && !(i == 0)"), Ok(1));
+        assert_eq!(eval("7/2"), Ok(3));
+
+        assert!(eval("1 / 0").is_err());
+        assert!(eval("s + 1").is_err());
+        assert!(eval("(1 + 2").is_err());
+        assert!(eval("1 2").is_err());
+        assert!(eval("").is_err());
+        assert!(eval("9223372036854775807 + 1").is_err());
+    }
+}
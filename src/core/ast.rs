@@ -47,6 +47,8 @@ pub type Str = Vec<StrPart>;
 pub enum StrPart {
     Chars(String),
     Expansion(Expansion),
+    /// the parts in quotes, which are neither split nor expanded as patterns
+    Quoted(Str),
 }
 
 #[derive(Debug, PartialEq)]
@@ -59,6 +61,121 @@ pub enum Expansion {
     Variable { name: String },
 }
 
+impl std::fmt::Display for List {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (cond, pipeline) in self.following.iter() {
+            match cond {
+                Condition::Always => write!(f, "; {pipeline}")?,
+                Condition::IfSuccess => write!(f, " && {pipeline}")?,
+                Condition::IfError => write!(f, " || {pipeline}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Pipeline::Single(cmd) => write!(f, "{cmd}"),
+            Pipeline::Connected { pipe, lhs, rhs } => {
+                let pipe = match pipe {
+                    Pipe::Stdout => "|",
+                    Pipe::Stderr => "|!",
+                    Pipe::Both => "|&",
+                };
+
+                if let Pipeline::Connected { .. } = **lhs {
+                    write!(f, "{{{lhs}}} {pipe} {rhs}")
+                } else {
+                    write!(f, "{lhs} {pipe} {rhs}")
+                }
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Command::Simple(args) => {
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{arg}")?;
+                }
+                Ok(())
+            }
+            Command::SubShell(list) => write!(f, "({list})"),
+        }
+    }
+}
+
+impl std::fmt::Display for Arguments {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Arguments::Arg(s) => fmt_str(s, f),
+            Arguments::AtExpansion(s) => {
+                write!(f, "@")?;
+                fmt_str(s, f)
+            }
+        }
+    }
+}
+
+impl std::fmt::Display for Expansion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Expansion::SubstStdout(list) => write!(f, "$({list})"),
+            Expansion::SubstStderr(list) => write!(f, "$!({list})"),
+            Expansion::SubstBoth(list) => write!(f, "$&({list})"),
+            Expansion::SubstPipeName(list) => write!(f, "=({list})"),
+            Expansion::SubstStatus(list) => write!(f, "?({list})"),
+            Expansion::Variable { name } if name == "?" => write!(f, "$?"),
+            Expansion::Variable { name } => write!(f, "${{{name}}}"),
+        }
+    }
+}
+
+// Writes a string in the form that the parser reads back to the same parts
+fn fmt_str(parts: &[StrPart], f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    for part in parts {
+        match part {
+            StrPart::Chars(chars) => {
+                for ch in chars.chars() {
+                    if let '\\' | ' ' | '\t' | '\n' | '@' | ';' | '&' | '|' | '$' | '(' | ')'
+                    | '[' | ']' | '\'' | '\"' | '=' | '?' | '{' | '}' = ch
+                    {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{ch}")?;
+                }
+            }
+            StrPart::Expansion(expansion) => write!(f, "{expansion}")?,
+            StrPart::Quoted(parts) => {
+                write!(f, "\"")?;
+                for part in parts {
+                    match part {
+                        StrPart::Chars(chars) => {
+                            for ch in chars.chars() {
+                                if let '\\' | '\"' | '$' = ch {
+                                    write!(f, "\\")?;
+                                }
+                                write!(f, "{ch}")?;
+                            }
+                        }
+                        part => fmt_str(std::slice::from_ref(part), f)?,
+                    }
+                }
+                write!(f, "\"")?;
+            }
+        }
+    }
+    Ok(())
+}
+
 peg::parser! {
     pub grammar parser() for str {
         pub rule toplevel() -> Box<Program> = list()
@@ -115,8 +232,8 @@ peg::parser! {
 
 
         pub rule string() -> Str
-        = text:single_quoted()  { vec![StrPart::Chars(text)] }
-        / parts:double_quoted() { parts }
+        = text:single_quoted()  { vec![StrPart::Quoted(vec![StrPart::Chars(text)])] }
+        / parts:double_quoted() { vec![StrPart::Quoted(parts)] }
         / parts:raw()           { parts }
 
         rule single_quoted() -> String
@@ -154,6 +271,20 @@ peg::parser! {
         / !"?(" ['?'] { '?' }
 
 
+        /// the template of the prompt, where only the expansions starting with `$` are expanded
+        pub rule template() -> Str
+        = parts:(template_part()*) { parts }
+
+        rule template_part() -> StrPart
+        = &"$" e:expansion() { StrPart::Expansion(e) }
+        / c:(template_char()+) { StrPart::Chars(c.into_iter().collect()) }
+        / "$" { StrPart::Chars("$".into()) }
+
+        rule template_char() -> char
+        = r#"\$"# { '$' }
+        / c:[^ '$'] { c }
+
+
         pub rule expansion() -> Expansion
         = "$&" list:subshell() { Expansion::SubstBoth(list) }
         / "$!" list:subshell() { Expansion::SubstStderr(list) }
@@ -165,6 +296,7 @@ peg::parser! {
         rule variable() -> String
         = "${" name:ident() "}" { name.to_string() }
         / "$"  name:ident()     { name.to_string() }
+        / "$?"                  { "?".to_string() }
 
         rule ws() = [' '|'\t'|'\n'|'\r']
     }
@@ -328,46 +460,50 @@ mod tests {
     #[test]
     fn parse_str_single_quote() {
         let input = r#"'foo bar'"#;
-        let expected = vec![StrPart::Chars("foo bar".into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars("foo bar".into())])];
         assert_eq!(parser::string(input), Ok(expected));
 
         let input = r#"'\''"#;
-        let expected = vec![StrPart::Chars("'".into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars("'".into())])];
         assert_eq!(parser::string(input), Ok(expected));
 
         let input = r#"'\\'"#;
-        let expected = vec![StrPart::Chars("\\".into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars("\\".into())])];
         assert_eq!(parser::string(input), Ok(expected));
 
         let input = r#"'"!@#$%^&*()_+-='"#;
-        let expected = vec![StrPart::Chars("\"!@#$%^&*()_+-=".into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars(
+            "\"!@#$%^&*()_+-=".into(),
+        )])];
         assert_eq!(parser::string(input), Ok(expected));
     }
 
     #[test]
     fn parse_str_double_quote() {
         let input = r#""foo bar""#;
-        let expected = vec![StrPart::Chars(r#"foo bar"#.into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars(r#"foo bar"#.into())])];
         assert_eq!(parser::string(input), Ok(expected));
 
         let input = r#""'""#;
-        let expected = vec![StrPart::Chars(r#"'"#.into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars(r#"'"#.into())])];
         assert_eq!(parser::string(input), Ok(expected));
 
         let input = r#""\"""#;
-        let expected = vec![StrPart::Chars(r#"""#.into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars(r#"""#.into())])];
         assert_eq!(parser::string(input), Ok(expected));
 
         let input = r#""\\""#;
-        let expected = vec![StrPart::Chars(r#"\"#.into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars(r#"\"#.into())])];
         assert_eq!(parser::string(input), Ok(expected));
 
         let input = r#""\$""#;
-        let expected = vec![StrPart::Chars(r#"$"#.into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars(r#"$"#.into())])];
         assert_eq!(parser::string(input), Ok(expected));
 
         let input = r#""!@#%^&*()_+-=""#;
-        let expected = vec![StrPart::Chars(r#"!@#%^&*()_+-="#.into())];
+        let expected = vec![StrPart::Quoted(vec![StrPart::Chars(
+            r#"!@#%^&*()_+-="#.into(),
+        )])];
         assert_eq!(parser::string(input), Ok(expected));
     }
 
@@ -411,6 +547,10 @@ mod tests {
         let input = r#"$xxx"#;
         let expected = Expansion::Variable { name: "xxx".into() };
         assert_eq!(parser::expansion(input), Ok(expected));
+
+        let input = r#"$?"#;
+        let expected = Expansion::Variable { name: "?".into() };
+        assert_eq!(parser::expansion(input), Ok(expected));
     }
 
     #[test]
@@ -476,6 +616,25 @@ mod tests {
         assert_eq!(parser::expansion(input), Ok(expected));
     }
 
+    #[test]
+    fn display_round_trip() {
+        let inputs = [
+            r#"foo"#,
+            r#"foo bar\ baz @args"#,
+            r#"a "xxx_$(b |!> err)_yyy" \$zzz $zzz ; (baz)"#,
+            r#"{foo |& bar} | baz && qux || quux"#,
+            r#"echo ${HOME}dir $? $!(foo) $&(bar) =(baz) ?(qux)"#,
+            r#"echo 'a;b' "c\"d" \= \?"#,
+            r#"echo "$x * \$y\\" '~'"#,
+        ];
+
+        for input in inputs {
+            let parsed = parser::toplevel(input).unwrap();
+            let displayed = parsed.to_string();
+            assert_eq!(parser::toplevel(&displayed), Ok(parsed), "{displayed}");
+        }
+    }
+
     #[test]
     fn parse_toplevel() {
         let input = r#"(foo)"#;
@@ -499,7 +658,7 @@ mod tests {
         let expected = Box::new(List {
             first: Pipeline::Single(Command::Simple(vec![
                 Arguments::Arg(vec![StrPart::Chars("a".into())]),
-                Arguments::Arg(vec![
+                Arguments::Arg(vec![StrPart::Quoted(vec![
                     StrPart::Chars("xxx_".into()),
                     StrPart::Expansion(Expansion::SubstStdout(
                         List {
@@ -520,7 +679,7 @@ mod tests {
                         .into(),
                     )),
                     StrPart::Chars("_yyy".into()),
-                ]),
+                ])]),
                 Arguments::Arg(vec![StrPart::Chars("$zzz".into())]),
                 Arguments::Arg(vec![StrPart::Expansion(Expansion::Variable {
                     name: "zzz".into(),
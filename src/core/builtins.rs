@@ -1,11 +1,18 @@
 use nix::sys::signal;
+use nix::sys::termios::Termios;
 use nix::unistd::Pid;
-use std::ffi::{CString, OsString};
+use std::ffi::{CString, OsStr, OsString};
 use std::io::Write;
+use std::os::unix::ffi::{OsStrExt as _, OsStringExt as _};
 use std::path::{Path, PathBuf};
 
+use crate::completion::CompletionRule;
+use crate::line_editor::{Action, Event, Keymap, KeymapMode};
+
+use super::error::{ShellError, SyscallResult as _};
 use super::io::Io;
-use super::{get_termios, set_termios, str_c_to_os, str_r_to_os, Pgid, Shell};
+use super::options::Options;
+use super::{get_termios, set_termios, str_c_to_os, str_r_to_os, Executable, Job, Pgid, Shell};
 
 pub fn builtin_args(_shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
     for (i, arg) in args.iter().enumerate().skip(1) {
@@ -14,16 +21,61 @@ pub fn builtin_args(_shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
     0
 }
 
-pub fn builtin_exit(shell: &mut Shell, _args: &[CString], mut io: Io) -> i32 {
-    if shell.jobs.is_empty() {
-        std::process::exit(0);
-    } else {
-        let _ = writeln!(
-            &mut io.error,
-            "exit: you have {} pending jobs.",
-            shell.jobs.len()
-        );
-        1
+pub fn builtin_exit(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let status = match args {
+        [_arg0] => shell.last_status,
+
+        [_arg0, status] => {
+            let status = std::str::from_utf8(status.as_bytes())
+                .ok()
+                .and_then(|s| s.parse::<i32>().ok());
+
+            match status {
+                Some(status) => status,
+                None => {
+                    let _ = writeln!(&mut io.error, "exit: {status:?}: numeric argument required");
+                    return 2;
+                }
+            }
+        }
+
+        _ => {
+            let _ = writeln!(&mut io.error, "exit: too many arguments");
+            return 1;
+        }
+    };
+
+    match shell.request_exit(status) {
+        Ok(()) => status,
+        Err(jobs) => {
+            let _ = writeln!(
+                &mut io.error,
+                "exit: you have {jobs} pending jobs. exit again to terminate them."
+            );
+            1
+        }
+    }
+}
+
+pub fn builtin_builtin(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let Some(name) = args.get(1) else {
+        return 0;
+    };
+
+    // aliases are not expanded here, and external commands are never looked up
+    match shell.env.commands.get(str_c_to_os(name)) {
+        Some(Executable::Builtin(impl_fptr)) => {
+            let impl_fptr = *impl_fptr;
+            impl_fptr(shell, &args[1..], io)
+        }
+        _ => {
+            let _ = writeln!(
+                &mut io.error,
+                "builtin: {}: not a shell builtin",
+                name.to_string_lossy()
+            );
+            1
+        }
     }
 }
 
@@ -34,7 +86,22 @@ pub fn builtin_cd(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
         Chdir(PathBuf),
     }
 
-    let op = match args.get(1) {
+    // errors are reported as `z` if it is called by that
+    let name = args[0].to_string_lossy().into_owned();
+
+    // -L (default): keep symbolic links in $PWD as typed, -P: resolve them
+    let mut physical = false;
+    let mut args = &args[1..];
+    while let Some(arg) = args.first() {
+        match arg.as_bytes() {
+            b"-P" => physical = true,
+            b"-L" => physical = false,
+            _ => break,
+        }
+        args = &args[1..];
+    }
+
+    let op = match args.first() {
         None => {
             let home = shell
                 .env
@@ -48,7 +115,7 @@ pub fn builtin_cd(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
         Some(arg1) => Op::Chdir(Path::new(str_c_to_os(arg1)).to_owned()),
     };
 
-    let old_cwd = std::env::current_dir();
+    let old_cwd = shell.cwd();
 
     match op {
         Op::Undo => {
@@ -60,10 +127,11 @@ pub fn builtin_cd(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
 
                 match std::env::set_current_dir(&new_cwd) {
                     Err(err) => {
-                        let _ = writeln!(&mut io.error, "cd: {err}");
+                        let _ = writeln!(&mut io.error, "{name}: {err}");
                         1
                     }
                     Ok(_) => {
+                        shell.dir_history.visit(&new_cwd);
                         shell.env.set_env("PWD", new_cwd.into_os_string());
                         0
                     }
@@ -82,10 +150,11 @@ pub fn builtin_cd(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
 
                 match std::env::set_current_dir(&new_cwd) {
                     Err(err) => {
-                        let _ = writeln!(&mut io.error, "cd: {err}");
+                        let _ = writeln!(&mut io.error, "{name}: {err}");
                         1
                     }
                     Ok(_) => {
+                        shell.dir_history.visit(&new_cwd);
                         shell.env.set_env("PWD", new_cwd.into_os_string());
                         0
                     }
@@ -95,73 +164,324 @@ pub fn builtin_cd(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
             }
         }
 
-        Op::Chdir(new_cwd) => match std::env::set_current_dir(&new_cwd) {
-            Err(err) => {
-                let _ = writeln!(&mut io.error, "cd: {err}");
-                1
+        Op::Chdir(new_cwd) => {
+            let via_cdpath = search_cdpath(shell, &new_cwd);
+            // tell the user where we are if it differs from what was typed
+            let mut announce = via_cdpath.is_some();
+            let mut new_cwd = via_cdpath.unwrap_or(new_cwd);
+
+            let mut result = change_dir(shell, &new_cwd, physical);
+            if matches!(&result, Err(err) if err.kind() == std::io::ErrorKind::NotFound) {
+                if let Some(corrected) = correct_dir_typo(&new_cwd) {
+                    let autocorrect = shell.env.get_var("CDSPELL").is_some_and(|v| !v.is_empty());
+                    if autocorrect {
+                        new_cwd = corrected;
+                        announce = true;
+                        result = change_dir(shell, &new_cwd, physical);
+                    } else {
+                        let _ = writeln!(
+                            &mut io.error,
+                            "{name}: {}: no such directory",
+                            new_cwd.display()
+                        );
+                        let _ = writeln!(&mut io.error, "did you mean {}?", corrected.display());
+                        return 1;
+                    }
+                }
             }
 
-            Ok(_) => {
-                let actual_new_cwd =
-                    std::env::current_dir().expect("getcwd right after chdir should success");
+            match result {
+                Err(err) => {
+                    let _ = writeln!(&mut io.error, "{name}: {err}");
+                    1
+                }
 
-                if let Ok(old_cwd) = old_cwd {
-                    shell.env.set_env("OLDPWD", old_cwd.as_os_str().to_owned());
-                    shell.cd_undo_stack.push(old_cwd);
+                Ok(actual_new_cwd) => {
+                    if announce {
+                        let _ = writeln!(&mut io.output, "{}", actual_new_cwd.display());
+                    }
+
+                    if let Ok(old_cwd) = old_cwd {
+                        shell.env.set_env("OLDPWD", old_cwd.as_os_str().to_owned());
+                        shell.cd_undo_stack.push(old_cwd);
+                    }
+                    shell.dir_history.visit(&actual_new_cwd);
+                    shell.env.set_env("PWD", actual_new_cwd.into_os_string());
+                    shell.cd_redo_stack.clear();
+                    0
                 }
-                shell.env.set_env("PWD", actual_new_cwd.into_os_string());
-                shell.cd_redo_stack.clear();
-                0
             }
-        },
+        }
+    }
+}
+
+pub fn builtin_z(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let fragments: Vec<&str> = match args[1..]
+        .iter()
+        .map(|arg| arg.to_str())
+        .collect::<Result<_, _>>()
+    {
+        Ok(fragments) => fragments,
+        Err(_) => {
+            let _ = writeln!(&mut io.error, "z: invalid UTF-8 sequence");
+            return 1;
+        }
+    };
+
+    // list the ranking without jumping
+    if fragments.is_empty() {
+        let mut matched = shell.dir_history.matches(&[]);
+        matched.reverse();
+        for (score, path) in matched {
+            let _ = writeln!(&mut io.output, "{score:<10.1} {}", path.display());
+        }
+        return 0;
+    }
+
+    let cwd = shell.cwd().ok();
+    let mut stale = Vec::new();
+    let mut found = None;
+    for (_, path) in shell.dir_history.matches(&fragments) {
+        if Some(path) == cwd.as_deref() {
+            continue;
+        }
+        if !path.is_dir() {
+            stale.push(path.to_owned());
+            continue;
+        }
+        found = Some(path.to_owned());
+        break;
+    }
+
+    for path in stale {
+        shell.dir_history.remove(&path);
+    }
+
+    match found {
+        Some(dir) => {
+            let dir =
+                CString::new(dir.into_os_string().into_vec()).expect("path never contains NUL");
+            builtin_cd(shell, &[args[0].clone(), dir], io)
+        }
+        None => {
+            let _ = writeln!(&mut io.error, "z: no matching directory");
+            1
+        }
     }
 }
 
-pub fn builtin_jobs(shell: &mut Shell, _args: &[CString], mut io: Io) -> i32 {
-    for (i, (pgid, _)) in shell.jobs.iter().enumerate() {
-        let _ = writeln!(&mut io.output, "[{i}] {pgid}");
+/// Changes the working directory and returns the new value of $PWD
+fn change_dir(shell: &Shell, target: &Path, physical: bool) -> std::io::Result<PathBuf> {
+    if !physical {
+        if let Ok(cwd) = shell.cwd() {
+            let logical = normalize_path(&cwd.join(target));
+            if std::env::set_current_dir(&logical).is_ok() {
+                return Ok(logical);
+            }
+        }
     }
-    0
+
+    // fall back to the physical path, e.g. "symlink/.." may exist only physically
+    std::env::set_current_dir(target)?;
+    std::env::current_dir()
 }
 
-pub fn builtin_fg(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
-    let job_pgid = if let Some(arg) = args.get(1) {
-        // CStr --> str --> i32 --> Pgid (Pid)
-        let valid_pgid = std::str::from_utf8(arg.as_bytes())
-            .ok()
-            .and_then(|s| s.parse::<i32>().ok())
-            .map(Pgid::from_raw)
-            .and_then(|pgid| {
-                if shell.jobs.contains_key(&pgid) {
-                    Some(pgid)
-                } else {
-                    None
-                }
-            });
+/// Resolves "." and ".." lexically without following symbolic links
+fn normalize_path(path: &Path) -> PathBuf {
+    use std::path::Component;
 
-        if let Some(pgid) = valid_pgid {
-            pgid
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+/// Returns `target` with misspelled components replaced by the closest existing directories
+fn correct_dir_typo(target: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+
+    let mut corrected = PathBuf::new();
+    for component in target.components() {
+        let Component::Normal(name) = component else {
+            corrected.push(component);
+            continue;
+        };
+
+        let candidate = corrected.join(name);
+        if candidate.is_dir() {
+            corrected = candidate;
+            continue;
+        }
+
+        let name = name.to_str()?;
+        let threshold = (name.chars().count() / 3).max(1);
+        let parent = if corrected.as_os_str().is_empty() {
+            Path::new(".")
         } else {
-            let _ = writeln!(&mut io.error, "fg: no such job is found");
-            let _ = writeln!(&mut io.error, "fg: usage: fg <pgid>");
+            corrected.as_path()
+        };
+
+        let (_, closest) = std::fs::read_dir(parent)
+            .ok()?
+            .filter_map(|ent| ent.ok())
+            .filter(|ent| ent.path().is_dir())
+            .filter_map(|ent| ent.file_name().into_string().ok())
+            .map(|dir| (crate::utils::edit_distance(name, &dir), dir))
+            .filter(|(dist, _)| *dist <= threshold)
+            .min()?;
+        corrected.push(closest);
+    }
+
+    if corrected == target {
+        None
+    } else {
+        Some(corrected)
+    }
+}
+
+/// Returns the directory found in CDPATH if `target` should be looked up there
+fn search_cdpath(shell: &Shell, target: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+
+    // paths like "/foo", "./foo" or "../foo" are never looked up in CDPATH
+    match target.components().next()? {
+        Component::Normal(_) => {}
+        _ => return None,
+    }
+    if target.is_dir() {
+        return None;
+    }
+
+    let cdpath = shell.env.get_var("CDPATH")?;
+    std::env::split_paths(cdpath)
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .map(|dir| dir.join(target))
+        .find(|candidate| candidate.is_dir())
+}
+
+pub fn builtin_jobs(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let long = match args {
+        [_arg0] => false,
+        [_arg0, opt] if opt.as_bytes() == b"-l" => true,
+        _ => {
+            let _ = writeln!(&mut io.error, "jobs: usage: jobs [-l]");
             return 1;
         }
-    } else {
-        match shell.jobs.iter().find(|(_, j)| j.is_stopped()) {
-            Some((pgid, _)) => *pgid,
-            None => {
-                let _ = writeln!(&mut io.error, "fg: you have no suspended job");
+    };
+
+    let (current, previous) = shell.current_jobs();
+
+    let mut jobs: Vec<(&Pgid, &Job)> = shell.jobs.iter().collect();
+    jobs.sort_by_key(|(_, job)| job.id);
+
+    for (pgid, job) in jobs {
+        let mark = if Some(*pgid) == current {
+            '+'
+        } else if Some(*pgid) == previous {
+            '-'
+        } else {
+            ' '
+        };
+
+        let id = job.id;
+        let state = job.state();
+        let command = &job.command;
+        if long {
+            let _ = writeln!(&mut io.output, "[{id}]{mark} {pgid:<8}{state:<10}{command}");
+        } else {
+            let _ = writeln!(&mut io.output, "[{id}]{mark} {state:<10}{command}");
+        }
+    }
+    0
+}
+
+pub fn builtin_fg(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let spec = match args {
+        [_arg0] => "%+",
+        [_arg0, spec] => match std::str::from_utf8(spec.as_bytes()) {
+            Ok(spec) => spec,
+            Err(_) => {
+                let _ = writeln!(&mut io.error, "fg: {spec:?}: no such job");
                 return 1;
             }
+        },
+        _ => {
+            let _ = writeln!(&mut io.error, "fg: usage: fg [%job | pgid]");
+            return 1;
+        }
+    };
+
+    let job_pgid = match shell.find_job(spec) {
+        Ok(pgid) => pgid,
+        Err(msg) => {
+            let _ = writeln!(&mut io.error, "fg: {spec}: {msg}");
+            return 1;
         }
     };
 
     let job = shell.jobs.get_mut(&job_pgid).unwrap();
-    let saved_termios = get_termios().expect("tcgetattr");
-    let job_termios = job.saved_termios.take().expect("not a suspended job");
-    set_termios(&job_termios).expect("tcsetattr");
+    let Some(job_termios) = job.saved_termios.take() else {
+        let _ = writeln!(&mut io.error, "fg: job {job_pgid} is not suspended");
+        return 1;
+    };
 
-    shell.set_foreground(job_pgid);
+    match resume_in_foreground(shell, job_pgid, &job_termios) {
+        Ok(status) => status,
+        Err(err) => {
+            let _ = writeln!(&mut io.error, "fg: {err}");
+            err.status()
+        }
+    }
+}
+
+/// Removes a job from the job table so that it isn't hung up with the terminal
+pub fn builtin_disown(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let spec = match args {
+        [_arg0] => "%+",
+        [_arg0, spec] => match std::str::from_utf8(spec.as_bytes()) {
+            Ok(spec) => spec,
+            Err(_) => {
+                let _ = writeln!(&mut io.error, "disown: {spec:?}: no such job");
+                return 1;
+            }
+        },
+        _ => {
+            let _ = writeln!(&mut io.error, "disown: usage: disown [%job | pgid]");
+            return 1;
+        }
+    };
+
+    match shell.find_job(spec) {
+        Ok(pgid) => {
+            if let Some(job) = shell.remove_job(pgid) {
+                let running = job.members.values().filter(|p| !p.is_completed());
+                shell.disowned.extend(running.map(|p| p.pid));
+            }
+            0
+        }
+        Err(msg) => {
+            let _ = writeln!(&mut io.error, "disown: {spec}: {msg}");
+            1
+        }
+    }
+}
+
+fn resume_in_foreground(
+    shell: &mut Shell,
+    job_pgid: Pgid,
+    job_termios: &Termios,
+) -> Result<i32, ShellError> {
+    let saved_termios = get_termios().syscall("tcgetattr")?;
+    set_termios(job_termios).syscall("tcsetattr")?;
+
+    shell.set_foreground(job_pgid)?;
 
     let status = {
         let job = shell.jobs.get_mut(&job_pgid).unwrap();
@@ -170,21 +490,30 @@ pub fn builtin_fg(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
         }
 
         let group_members = Pid::from_raw(-job_pgid.as_raw());
-        signal::kill(group_members, signal::Signal::SIGCONT).expect("kill");
-
-        shell.wait_for_job(job_pgid)
+        match signal::kill(group_members, signal::Signal::SIGCONT) {
+            Ok(()) => shell.wait_for_job(job_pgid),
+            Err(err) => Err(ShellError::Syscall("kill", err)),
+        }
     };
 
-    shell.set_foreground(shell.shell_pgid);
+    shell.set_foreground(shell.shell_pgid)?;
+    let status = status?;
 
     if let Some(job) = shell.jobs.get_mut(&job_pgid) {
         if job.is_stopped() {
-            job.saved_termios = Some(get_termios().expect("tcgetattr"));
-            set_termios(&saved_termios).expect("tcsetattr");
+            job.saved_termios = Some(get_termios().syscall("tcgetattr")?);
+            set_termios(&saved_termios).syscall("tcsetattr")?;
+
+            shell.job_order.retain(|pgid| *pgid != job_pgid);
+            shell.job_order.push(job_pgid);
+            shell.report_stopped(job_pgid);
         }
     }
+    if !shell.jobs.contains_key(&job_pgid) {
+        shell.settle_termios(status);
+    }
 
-    status
+    Ok(status)
 }
 
 pub fn builtin_append(_shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
@@ -211,10 +540,22 @@ pub fn builtin_append(_shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
     }
 }
 
-pub fn builtin_overwrite(_shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+pub fn builtin_overwrite(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
     match args {
         [_arg0, outpath] => {
             let outpath = Path::new(str_c_to_os(outpath));
+
+            // devices like /dev/null can be written even with noclobber
+            let exists_as_file = std::fs::metadata(outpath).is_ok_and(|m| m.is_file());
+            if shell.options.noclobber && exists_as_file {
+                let _ = writeln!(
+                    &mut io.error,
+                    ">: {}: cannot overwrite existing file",
+                    outpath.display()
+                );
+                return 1;
+            }
+
             let file = std::fs::OpenOptions::new()
                 .create(true)
                 .write(true)
@@ -236,6 +577,27 @@ pub fn builtin_overwrite(_shell: &mut Shell, args: &[CString], mut io: Io) -> i3
     }
 }
 
+pub fn builtin_input(_shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    match args {
+        [_arg0, inpath] => {
+            let inpath = Path::new(str_c_to_os(inpath));
+            let file = std::fs::File::open(inpath);
+
+            file.and_then(|mut file| std::io::copy(&mut file, &mut io.output))
+                .map(|_| 0)
+                .unwrap_or_else(|err| {
+                    let _ = writeln!(&mut io.error, "<: {err}");
+                    2
+                })
+        }
+
+        _ => {
+            let _ = writeln!(&mut io.error, "<: takes 1 argument");
+            1
+        }
+    }
+}
+
 pub fn builtin_alias(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
     match args {
         [_arg0] => {
@@ -259,6 +621,266 @@ pub fn builtin_alias(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
     }
 }
 
+/// The forms shared by `abbr` and `hook`: listing, removing names, and adding words to a name
+enum TableOp {
+    List,
+    Remove(Vec<String>),
+    Add(String, String),
+}
+
+// parses "", "-e NAME..." or "NAME WORDS...", where the words are joined with spaces
+fn parse_table_op(args: &[CString]) -> Option<TableOp> {
+    let args: Vec<String> = args[1..]
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+
+    match args.as_slice() {
+        [] => Some(TableOp::List),
+        [flag, names @ ..] if flag == "-e" && !names.is_empty() => {
+            Some(TableOp::Remove(names.to_vec()))
+        }
+        [name, words @ ..] if !words.is_empty() && !name.starts_with('-') => {
+            Some(TableOp::Add(name.clone(), words.join(" ")))
+        }
+        _ => None,
+    }
+}
+
+pub fn builtin_abbr(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let Some(op) = parse_table_op(args) else {
+        let _ = writeln!(
+            &mut io.error,
+            "abbr: usage: abbr [NAME EXPANSION...] [-e NAME...]"
+        );
+        return 2;
+    };
+
+    match op {
+        TableOp::List => {
+            let mut abbrs: Vec<_> = shell.abbreviations.iter().collect();
+            abbrs.sort();
+            for (name, expansion) in abbrs {
+                let _ = writeln!(
+                    &mut io.output,
+                    "abbr {} {}",
+                    escape_word(name),
+                    escape_word(expansion)
+                );
+            }
+            0
+        }
+
+        TableOp::Remove(names) => {
+            let mut status = 0;
+            for name in names {
+                if shell.abbreviations.remove(&name).is_none() {
+                    let _ = writeln!(&mut io.error, "abbr: {name}: no such abbreviation");
+                    status = 1;
+                }
+            }
+            status
+        }
+
+        TableOp::Add(name, expansion) => {
+            shell.abbreviations.insert(name, expansion);
+            0
+        }
+    }
+}
+
+/// The events which the `hook` builtin can register commands for
+const HOOK_EVENTS: &[&str] = &["preexec", "precmd", "chpwd"];
+
+pub fn builtin_hook(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let Some(op) = parse_table_op(args) else {
+        let _ = writeln!(
+            &mut io.error,
+            "hook: usage: hook [EVENT COMMAND...] [-e EVENT...]"
+        );
+        return 2;
+    };
+
+    match op {
+        TableOp::List => {
+            for event in HOOK_EVENTS {
+                for command in shell.hooks.get(*event).into_iter().flatten() {
+                    let command = escape_word(command);
+                    let _ = writeln!(&mut io.output, "hook {event} {command}");
+                }
+            }
+            0
+        }
+
+        TableOp::Remove(events) => {
+            let mut status = 0;
+            for event in events {
+                if HOOK_EVENTS.contains(&event.as_str()) {
+                    shell.hooks.remove(&event);
+                } else {
+                    let _ = writeln!(&mut io.error, "hook: {event}: no such event");
+                    status = 1;
+                }
+            }
+            status
+        }
+
+        TableOp::Add(event, _) if !HOOK_EVENTS.contains(&event.as_str()) => {
+            let _ = writeln!(&mut io.error, "hook: {event}: no such event");
+            1
+        }
+
+        TableOp::Add(event, command) => {
+            shell.hooks.entry(event).or_default().push(command);
+            0
+        }
+    }
+}
+
+pub fn builtin_let(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let [_arg0, name, eq, expr @ ..] = args else {
+        let _ = writeln!(&mut io.error, "let: usage: let NAME = EXPR");
+        return 2;
+    };
+    if eq.as_bytes() != b"=" || expr.is_empty() {
+        let _ = writeln!(&mut io.error, "let: usage: let NAME = EXPR");
+        return 2;
+    }
+
+    match eval_arith(shell, expr) {
+        Ok(value) => {
+            let name = str_c_to_os(name).to_owned();
+            let value = OsString::from(value.to_string());
+            shell.env.shell_vars.insert(name, value);
+            0
+        }
+        Err(msg) => {
+            let _ = writeln!(&mut io.error, "let: {msg}");
+            1
+        }
+    }
+}
+
+pub fn builtin_math(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    match eval_arith(shell, &args[1..]) {
+        Ok(value) => {
+            let _ = writeln!(&mut io.output, "{value}");
+            0
+        }
+        Err(msg) => {
+            let _ = writeln!(&mut io.error, "math: {msg}");
+            1
+        }
+    }
+}
+
+fn eval_arith(shell: &Shell, args: &[CString]) -> Result<i64, String> {
+    let words: Vec<&str> = args
+        .iter()
+        .map(|arg| arg.to_str())
+        .collect::<Result<_, _>>()
+        .map_err(|_| "invalid UTF-8 sequence".to_owned())?;
+
+    super::arith::evaluate(&words.join(" "), |name| {
+        if let Some(value) = shell.special_variable(name) {
+            return Some(value);
+        }
+        let value = shell.env.get_var(name)?;
+        Some(value.to_string_lossy().into_owned())
+    })
+}
+
+pub fn builtin_set(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    if args.len() == 1 {
+        let mut vars: Vec<(&OsString, &OsString)> = shell.env.env_vars.iter().collect();
+        vars.retain(|(key, _)| !shell.env.shell_vars.contains_key(*key));
+        vars.extend(shell.env.shell_vars.iter());
+        vars.sort();
+
+        for (key, val) in vars {
+            let _ = writeln!(
+                &mut io.output,
+                "{}={}",
+                key.to_string_lossy(),
+                val.to_string_lossy()
+            );
+        }
+        return 0;
+    }
+
+    let mut args = args[1..].iter().map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        let (enable, flags) = match (arg.strip_prefix('-'), arg.strip_prefix('+')) {
+            (Some(flags), _) if !flags.is_empty() => (true, flags),
+            (_, Some(flags)) if !flags.is_empty() => (false, flags),
+            _ => {
+                let _ = writeln!(&mut io.error, "set: invalid argument: {arg}");
+                return 2;
+            }
+        };
+
+        if flags == "o" {
+            let Some(name) = args.next() else {
+                // list the options
+                for name in Options::NAMES {
+                    let state = if shell.options.get(name) == Some(true) {
+                        "on"
+                    } else {
+                        "off"
+                    };
+                    let _ = writeln!(&mut io.output, "{name:<12}{state}");
+                }
+                return 0;
+            };
+
+            match shell.options.get_mut(&name) {
+                Some(opt) => *opt = enable,
+                None => {
+                    let _ = writeln!(&mut io.error, "set: {name}: invalid option name");
+                    return 2;
+                }
+            }
+            continue;
+        }
+
+        for letter in flags.chars() {
+            match Options::from_letter(letter).and_then(|name| shell.options.get_mut(name)) {
+                Some(opt) => *opt = enable,
+                None => {
+                    let _ = writeln!(&mut io.error, "set: -{letter}: invalid option");
+                    return 2;
+                }
+            }
+        }
+    }
+    0
+}
+
+pub fn builtin_reload(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    if args.len() > 1 {
+        let _ = writeln!(&mut io.error, "reload: too many arguments");
+        return 2;
+    }
+    // `reload` in the startup file would reload forever
+    if shell.reloading {
+        let _ = writeln!(&mut io.error, "reload: already reloading");
+        return 1;
+    }
+
+    // what the files define is cleared not to leave stale or duplicated ones
+    shell.options = Options::default();
+    shell.env.aliases.clear();
+    shell.abbreviations.clear();
+    shell.hooks.clear();
+    shell.keymap = Keymap::default();
+    shell.completion_rules.clear();
+
+    shell.reloading = true;
+    let status = crate::config::load_rc(shell).unwrap_or(0);
+    shell.reloading = false;
+    status
+}
+
 pub fn builtin_var(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
     match args {
         [_arg0] => {
@@ -294,7 +916,12 @@ pub fn builtin_evar(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
         [_arg0, key, eq, val] if eq.as_bytes() == b"=" => {
             let key = str_c_to_os(key).to_owned();
             let val = str_c_to_os(val).to_owned();
+            let path_changed = key == "PATH";
             shell.env.env_vars.insert(key, val);
+
+            if path_changed {
+                shell.env.update_commands();
+            }
             0
         }
 
@@ -305,6 +932,56 @@ pub fn builtin_evar(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
     }
 }
 
+pub fn builtin_export(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    if args.len() <= 1 {
+        for (key, val) in shell.env.env_vars.iter() {
+            let _ = writeln!(&mut io.output, "{key:?} => {val:?}");
+        }
+        return 0;
+    }
+
+    let mut status = 0;
+    for arg in args[1..].iter() {
+        let arg = arg.as_bytes();
+
+        let (key, val) = match arg.iter().position(|b| *b == b'=') {
+            Some(pos) => {
+                let key = OsStr::from_bytes(&arg[..pos]).to_owned();
+                let val = OsStr::from_bytes(&arg[pos + 1..]).to_owned();
+                shell.env.shell_vars.remove(&key);
+                (key, val)
+            }
+
+            None => {
+                let key = OsStr::from_bytes(arg).to_owned();
+                match shell.env.shell_vars.remove(&key) {
+                    Some(val) => (key, val),
+                    None if shell.env.env_vars.contains_key(&key) => continue,
+                    None => {
+                        let _ = writeln!(&mut io.error, "export: {key:?}: no such variable");
+                        status = 1;
+                        continue;
+                    }
+                }
+            }
+        };
+
+        if key.is_empty() {
+            let _ = writeln!(&mut io.error, "export: invalid assignment");
+            status = 1;
+            continue;
+        }
+
+        let path_changed = key == "PATH";
+        shell.env.env_vars.insert(key, val);
+
+        if path_changed {
+            shell.env.update_commands();
+        }
+    }
+    status
+}
+
 pub fn builtin_unset(shell: &mut Shell, args: &[CString], mut _io: Io) -> i32 {
     match args {
         [_arg0, names @ ..] => {
@@ -312,6 +989,10 @@ pub fn builtin_unset(shell: &mut Shell, args: &[CString], mut _io: Io) -> i32 {
                 let name = str_c_to_os(name);
                 shell.env.env_vars.remove(name);
                 shell.env.shell_vars.remove(name);
+
+                if name == "PATH" {
+                    shell.env.update_commands();
+                }
             }
             0
         }
@@ -319,3 +1000,265 @@ pub fn builtin_unset(shell: &mut Shell, args: &[CString], mut _io: Io) -> i32 {
         _ => 0,
     }
 }
+
+pub fn builtin_rehash(shell: &mut Shell, _args: &[CString], _io: Io) -> i32 {
+    shell.env.update_commands();
+    0
+}
+
+pub fn builtin_hash(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    match args {
+        [_arg0] => {
+            let mut commands: Vec<_> = shell.env.commands.iter().collect();
+            commands.sort_by_key(|(name, _)| *name);
+            for (name, exe) in commands {
+                let resolved = match exe {
+                    Executable::External(path) => path.to_string_lossy(),
+                    Executable::Builtin(_) => "builtin".into(),
+                    Executable::Plugin(path) => format!("plugin {}", path.display()).into(),
+                };
+                let _ = writeln!(&mut io.output, "{}\t{}", name.to_string_lossy(), resolved);
+            }
+            0
+        }
+
+        [_arg0, opt] if opt.as_bytes() == b"-r" => {
+            // external commands are looked up again when they are run
+            shell
+                .env
+                .commands
+                .retain(|_, exe| !matches!(exe, Executable::External(_)));
+            0
+        }
+
+        [_arg0, names @ ..] => {
+            let (print, names) = match names {
+                [opt, names @ ..] if opt.as_bytes() == b"-t" => (true, names),
+                names => (false, names),
+            };
+
+            let mut status = 0;
+            for name in names {
+                let name = str_c_to_os(name);
+                match shell.env.resolve_command(name) {
+                    Some(Executable::External(path)) if print => {
+                        let _ = writeln!(&mut io.output, "{}", path.display());
+                    }
+                    Some(Executable::Builtin(_)) if print => {
+                        let _ = writeln!(&mut io.output, "builtin");
+                    }
+                    Some(Executable::Plugin(path)) if print => {
+                        let _ = writeln!(&mut io.output, "plugin {}", path.display());
+                    }
+                    Some(_) => {}
+                    None => {
+                        let _ =
+                            writeln!(&mut io.error, "hash: {}: not found", name.to_string_lossy());
+                        status = 1;
+                    }
+                }
+            }
+            status
+        }
+
+        [] => unreachable!(),
+    }
+}
+
+pub fn builtin_bindkey(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let args: Vec<_> = args[1..].iter().map(|arg| arg.to_string_lossy()).collect();
+    let mut args: Vec<&str> = args.iter().map(|arg| arg.as_ref()).collect();
+
+    if args.first() == Some(&"-l") {
+        for action in Action::ALL {
+            let _ = writeln!(&mut io.output, "{}", action.name());
+        }
+        return 0;
+    }
+
+    // print the bindings in a form which can be put in the startup file
+    if args.first() == Some(&"-L") {
+        for mode in KeymapMode::ALL {
+            for (event, action) in shell.keymap.bindings(*mode) {
+                let key = escape_word(&event.to_string());
+                let _ = writeln!(
+                    &mut io.output,
+                    "bindkey -M {} {key} {}",
+                    mode.name(),
+                    action.name()
+                );
+            }
+        }
+        return 0;
+    }
+
+    let mut mode = KeymapMode::Insert;
+    if args.first() == Some(&"-M") {
+        let Some(name) = args.get(1) else {
+            let _ = writeln!(&mut io.error, "bindkey: -M requires a mode name");
+            return 2;
+        };
+        match KeymapMode::from_name(name) {
+            Some(m) => mode = m,
+            None => {
+                let _ = writeln!(&mut io.error, "bindkey: {name}: no such mode");
+                return 1;
+            }
+        }
+        args.drain(..2);
+    }
+
+    let parse_key = |key: &str, io: &mut Io| {
+        let event = Event::parse(key);
+        if event.is_none() {
+            let _ = writeln!(&mut io.error, "bindkey: {key}: invalid key");
+        }
+        event
+    };
+
+    match args.as_slice() {
+        [] => {
+            for (event, action) in shell.keymap.bindings(mode) {
+                let _ = writeln!(&mut io.output, "{:<12}{}", event.to_string(), action.name());
+            }
+            0
+        }
+
+        ["-r", key] => {
+            let Some(event) = parse_key(key, &mut io) else {
+                return 1;
+            };
+            if shell.keymap.unbind(mode, event) {
+                0
+            } else {
+                let _ = writeln!(&mut io.error, "bindkey: {key}: not bound");
+                1
+            }
+        }
+
+        [key, action] => {
+            let Some(event) = parse_key(key, &mut io) else {
+                return 1;
+            };
+            match Action::from_name(action) {
+                Some(action) => {
+                    shell.keymap.bind(mode, event, action);
+                    0
+                }
+                None => {
+                    let _ = writeln!(&mut io.error, "bindkey: {action}: no such function");
+                    1
+                }
+            }
+        }
+
+        _ => {
+            let _ = writeln!(
+                &mut io.error,
+                "bindkey: usage: bindkey [-l | -L] [-M mode] [-r key | key function]"
+            );
+            2
+        }
+    }
+}
+
+// escapes the characters which have special meanings in the shell
+fn escape_word(word: &str) -> String {
+    let mut escaped = String::new();
+    for ch in word.chars() {
+        if "\\ \t@;&|$()[]'\"=?{}*".contains(ch) {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+pub fn builtin_complete(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let args: Vec<String> = args[1..]
+        .iter()
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    match args.as_slice() {
+        [] => {
+            let mut rules: Vec<_> = shell.completion_rules.iter().collect();
+            rules.sort_by_key(|(cmd, _)| *cmd);
+            for (cmd, rule) in rules {
+                let _ = writeln!(&mut io.output, "complete {cmd} = {rule}");
+            }
+            0
+        }
+
+        ["-r", cmds @ ..] => {
+            for cmd in cmds {
+                shell.completion_rules.remove(*cmd);
+            }
+            0
+        }
+
+        [cmd, "=", kind, words @ ..] => {
+            let rule = match (*kind, words) {
+                ("words" | "subcommands", words) => {
+                    CompletionRule::Words(words.iter().map(|w| w.to_string()).collect())
+                }
+                ("files", []) => CompletionRule::Files,
+                ("commands", []) => CompletionRule::Commands,
+                ("external", [_, ..]) => {
+                    CompletionRule::External(words.iter().map(|w| w.to_string()).collect())
+                }
+                _ => {
+                    let _ = writeln!(&mut io.error, "complete: {kind}: invalid completion");
+                    return 1;
+                }
+            };
+            shell.completion_rules.insert(cmd.to_string(), rule);
+            0
+        }
+
+        _ => {
+            let _ = writeln!(
+                &mut io.error,
+                "complete: usage: complete [-r CMD... | CMD = words WORD[:DESCRIPTION]... | CMD = files | CMD = commands | CMD = external PROGRAM [ARG...]]"
+            );
+            2
+        }
+    }
+}
+
+/// Restores the terminal settings at startup, and the modes which programs may leave on
+pub fn builtin_reset_terminal(shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    if args.len() > 1 {
+        let _ = writeln!(&mut io.error, "reset-terminal: usage: reset-terminal");
+        return 1;
+    }
+
+    if let Some(initial) = shell.initial_termios.clone() {
+        if let Err(err) = set_termios(&initial) {
+            let _ = writeln!(&mut io.error, "reset-terminal: tcsetattr: {err}");
+            return 1;
+        }
+        shell.termios = Some(initial);
+    }
+
+    // the attributes, the cursor, the alternate screen and mouse reporting
+    let modes = "\x1b[m\x1b[?25h\x1b[?1049l\x1b[?1000l\x1b[?1002l\x1b[?1003l\x1b[?1006l";
+    let _ = write!(&mut io.output, "{modes}");
+    0
+}
+
+/// Prints the syntax tree of the arguments joined with spaces without running it
+pub fn builtin_parse(_shell: &mut Shell, args: &[CString], mut io: Io) -> i32 {
+    let words: Vec<_> = args[1..].iter().map(|arg| arg.to_string_lossy()).collect();
+    match Shell::dump_syntax_tree(&words.join(" ")) {
+        Ok(tree) => {
+            let _ = writeln!(&mut io.output, "{tree}");
+            0
+        }
+        Err(err) => {
+            let _ = writeln!(&mut io.error, "parse: {err}");
+            2
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::Write as _;
+use std::os::unix::ffi::OsStrExt as _;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// ranks are aged once their sum exceeds this limit (the same scheme as `z`)
+const MAX_TOTAL_RANK: f64 = 9000.0;
+
+struct Entry {
+    rank: f64,
+    last_visit: u64,
+}
+
+impl Entry {
+    fn frecency(&self, now: u64) -> f64 {
+        let elapsed = now.saturating_sub(self.last_visit);
+        let factor = if elapsed < 60 * 60 {
+            4.0
+        } else if elapsed < 24 * 60 * 60 {
+            2.0
+        } else if elapsed < 7 * 24 * 60 * 60 {
+            0.5
+        } else {
+            0.25
+        };
+        self.rank * factor
+    }
+}
+
+/// `DirHistory` remembers visited directories and ranks them by frequency and recency.
+/// The entries are persisted under the application directory.
+#[derive(Default)]
+pub struct DirHistory {
+    entries: HashMap<PathBuf, Entry>,
+    file_path: Option<PathBuf>,
+}
+
+impl Drop for DirHistory {
+    fn drop(&mut self) {
+        if let Err(err) = self.save() {
+            eprintln!("Failed to save directory history: {err}");
+        }
+    }
+}
+
+impl DirHistory {
+    pub fn load() -> Self {
+        let file_path = crate::application_dir().map(|mut path| {
+            path.push("dirs");
+            path
+        });
+
+        let mut history = Self {
+            entries: HashMap::new(),
+            file_path,
+        };
+        if let Some(content) = history
+            .file_path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+        {
+            history.parse(&content);
+        }
+        history
+    }
+
+    // each line has the form of "<path>|<rank>|<last visit>"
+    fn parse(&mut self, content: &[u8]) {
+        for line in content.split(|b| *b == b'\n') {
+            let mut fields = line.rsplitn(3, |b| *b == b'|');
+            let (Some(last_visit), Some(rank), Some(path)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                continue;
+            };
+
+            let rank = std::str::from_utf8(rank).ok().and_then(|s| s.parse().ok());
+            let last_visit = std::str::from_utf8(last_visit)
+                .ok()
+                .and_then(|s| s.parse().ok());
+
+            if let (Some(rank), Some(last_visit)) = (rank, last_visit) {
+                let path = PathBuf::from(OsStr::from_bytes(path));
+                self.entries.insert(path, Entry { rank, last_visit });
+            }
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        if let Some(path) = &self.file_path {
+            let mut file = std::io::BufWriter::new(std::fs::File::create(path)?);
+            for (path, entry) in self.entries.iter() {
+                file.write_all(path.as_os_str().as_bytes())?;
+                writeln!(file, "|{}|{}", entry.rank, entry.last_visit)?;
+            }
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Records a visit to `dir`
+    pub fn visit(&mut self, dir: &Path) {
+        self.visit_at(dir, now());
+    }
+
+    fn visit_at(&mut self, dir: &Path, now: u64) {
+        let entry = self.entries.entry(dir.to_owned()).or_insert(Entry {
+            rank: 0.0,
+            last_visit: now,
+        });
+        entry.rank += 1.0;
+        entry.last_visit = now;
+
+        let total: f64 = self.entries.values().map(|e| e.rank).sum();
+        if total > MAX_TOTAL_RANK {
+            for entry in self.entries.values_mut() {
+                entry.rank *= 0.99;
+            }
+            self.entries.retain(|_, e| e.rank >= 1.0);
+        }
+    }
+
+    /// Returns the directories matching all of `fragments`, the best one first
+    pub fn matches(&self, fragments: &[&str]) -> Vec<(f64, &Path)> {
+        self.matches_at(fragments, now())
+    }
+
+    fn matches_at(&self, fragments: &[&str], now: u64) -> Vec<(f64, &Path)> {
+        // case-insensitive unless some fragment contains an uppercase letter
+        let ignore_case = fragments
+            .iter()
+            .all(|frag| !frag.chars().any(char::is_uppercase));
+
+        let mut matched: Vec<_> = self
+            .entries
+            .iter()
+            .filter(|(path, _)| {
+                let path = path.to_string_lossy();
+                matches_in_order(&path, fragments, ignore_case)
+            })
+            .map(|(path, entry)| (entry.frecency(now), path.as_path()))
+            .collect();
+
+        matched.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+        matched
+    }
+
+    /// Forgets a directory, e.g. because it no longer exists
+    pub fn remove(&mut self, dir: &Path) {
+        self.entries.remove(dir);
+    }
+}
+
+fn matches_in_order(path: &str, fragments: &[&str], ignore_case: bool) -> bool {
+    let path = if ignore_case {
+        path.to_lowercase()
+    } else {
+        path.to_owned()
+    };
+
+    let mut rest = path.as_str();
+    for frag in fragments {
+        match rest.find(frag) {
+            Some(pos) => rest = &rest[pos + frag.len()..],
+            None => return false,
+        }
+    }
+    true
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frecency_ranking() {
+        let mut history = DirHistory {
+            entries: HashMap::new(),
+            file_path: None,
+        };
+        let day = 24 * 60 * 60;
+
+        history.visit_at(Path::new("/home/user/src/shell"), 0);
+        history.visit_at(Path::new("/home/user/src/shell"), 0);
+        history.visit_at(Path::new("/home/user/src/shell"), 0);
+        history.visit_at(Path::new("/home/user/Documents"), 10 * day);
+        history.visit_at(Path::new("/tmp/shell-test"), 10 * day);
+
+        let best = |fragments: &[&str]| -> Option<PathBuf> {
+            let matched = history.matches_at(fragments, 10 * day);
+            matched.first().map(|(_, path)| path.to_path_buf())
+        };
+
+        // recent visits outweigh older but more frequent ones
+        assert_eq!(best(&["shell"]), Some("/tmp/shell-test".into()));
+        assert_eq!(best(&["src", "sh"]), Some("/home/user/src/shell".into()));
+        assert_eq!(best(&["doc"]), Some("/home/user/Documents".into()));
+        assert_eq!(best(&["Doc"]), Some("/home/user/Documents".into()));
+        assert_eq!(best(&["DOC"]), None);
+        assert_eq!(best(&["sh", "src"]), None);
+    }
+}
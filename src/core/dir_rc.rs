@@ -0,0 +1,197 @@
+//! `.myshell.rc` in a directory is evaluated when the directory is entered, once the user has
+//! trusted it. The SHA-256 hashes of the trusted files are persisted under the application
+//! directory so that a file changed since then is asked about again.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io::Write as _;
+use std::os::unix::ffi::OsStrExt as _;
+use std::path::{Path, PathBuf};
+
+pub const FILE_NAME: &str = ".myshell.rc";
+
+/// `TrustedFiles` remembers the hash of each rc file which the user has trusted
+#[derive(Default)]
+pub struct TrustedFiles {
+    hashes: HashMap<PathBuf, String>,
+    /// the files the user refused, which are not asked about again in this session
+    declined: HashMap<PathBuf, String>,
+    file_path: Option<PathBuf>,
+}
+
+impl TrustedFiles {
+    pub fn load() -> Self {
+        let file_path = crate::application_dir().map(|mut path| {
+            path.push("trusted_rc");
+            path
+        });
+
+        let mut trusted = Self {
+            file_path,
+            ..Self::default()
+        };
+        if let Some(content) = trusted
+            .file_path
+            .as_ref()
+            .and_then(|p| std::fs::read(p).ok())
+        {
+            trusted.parse(&content);
+        }
+        trusted
+    }
+
+    // each line has the form of "<hash> <path>"
+    fn parse(&mut self, content: &[u8]) {
+        for line in content.split(|b| *b == b'\n') {
+            let mut fields = line.splitn(2, |b| *b == b' ');
+            let (Some(hash), Some(path)) = (fields.next(), fields.next()) else {
+                continue;
+            };
+            let Ok(hash) = std::str::from_utf8(hash) else {
+                continue;
+            };
+            let path = PathBuf::from(OsStr::from_bytes(path));
+            self.hashes.insert(path, hash.to_owned());
+        }
+    }
+
+    fn save(&self) -> std::io::Result<()> {
+        let Some(file_path) = &self.file_path else {
+            return Ok(());
+        };
+
+        let mut content = Vec::new();
+        for (path, hash) in self.hashes.iter() {
+            content.extend_from_slice(hash.as_bytes());
+            content.push(b' ');
+            content.extend_from_slice(path.as_os_str().as_bytes());
+            content.push(b'\n');
+        }
+        std::fs::write(file_path, content)
+    }
+
+    /// Returns whether the file at `path` whose content is `content` may be evaluated,
+    /// asking the user unless it has been trusted or refused with the same content
+    pub fn check(&mut self, path: &Path, content: &[u8]) -> bool {
+        let hash = sha256(content);
+        let trusted = self.hashes.get(path);
+        if trusted == Some(&hash) {
+            return true;
+        }
+        if self.declined.get(path) == Some(&hash) {
+            return false;
+        }
+
+        let state = if trusted.is_some() {
+            "has changed since you trusted it"
+        } else {
+            "is not trusted yet"
+        };
+        eprint!("{} {state}. Evaluate it? [y/N] ", path.display());
+        let _ = std::io::stderr().flush();
+        let mut answer = String::new();
+        let _ = std::io::stdin().read_line(&mut answer);
+
+        if matches!(answer.trim(), "y" | "Y" | "yes") {
+            self.hashes.insert(path.to_owned(), hash);
+            if let Err(err) = self.save() {
+                eprintln!("Failed to save trusted rc files: {err}");
+            }
+            true
+        } else {
+            self.declined.insert(path.to_owned(), hash);
+            false
+        }
+    }
+}
+
+/// Returns the SHA-256 digest of `data` in hex
+pub fn sha256(data: &[u8]) -> String {
+    const K: [u32; 64] = [
+        0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4,
+        0xab1c5ed5, 0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe,
+        0x9bdc06a7, 0xc19bf174, 0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f,
+        0x4a7484aa, 0x5cb0a9dc, 0x76f988da, 0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7,
+        0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967, 0x27b70a85, 0x2e1b2138, 0x4d2c6dfc,
+        0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85, 0xa2bfe8a1, 0xa81a664b,
+        0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070, 0x19a4c116,
+        0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+        0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7,
+        0xc67178f2,
+    ];
+    let mut h: [u32; 8] = [
+        0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab,
+        0x5be0cd19,
+    ];
+
+    // padded with a bit 1, zeros and the length in bits to a multiple of 64 bytes
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&((data.len() as u64) * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = (w[i - 16].wrapping_add(s0))
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut hh] = h;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let t1 = (hh.wrapping_add(s1))
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let t2 = s0.wrapping_add(maj);
+
+            hh = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(t1);
+            d = c;
+            c = b;
+            b = a;
+            a = t1.wrapping_add(t2);
+        }
+        for (h, v) in h.iter_mut().zip([a, b, c, d, e, f, g, hh]) {
+            *h = h.wrapping_add(v);
+        }
+    }
+
+    h.iter().map(|word| format!("{word:08x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_digest() {
+        assert_eq!(
+            sha256(b""),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        // two blocks
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+}
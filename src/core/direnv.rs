@@ -0,0 +1,139 @@
+//! Loads the environment of the directory from direnv as its hook for other shells does
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+
+/// Runs `direnv export json` with the environment `env`, and returns the variables to set,
+/// or to unset if the value is None. Returns None if direnv is not installed or fails.
+pub fn export(env: &HashMap<OsString, OsString>) -> Option<Vec<(String, Option<String>)>> {
+    use std::process::{Command, Stdio};
+
+    let output = Command::new("direnv")
+        .args(["export", "json"])
+        .env_clear()
+        .envs(env)
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    // nothing is printed if the environment is unchanged
+    let json = String::from_utf8(output.stdout).ok()?;
+    if json.trim().is_empty() {
+        return Some(Vec::new());
+    }
+    parse_object(&json)
+}
+
+// parses a JSON object whose values are strings or null
+fn parse_object(json: &str) -> Option<Vec<(String, Option<String>)>> {
+    let mut chars = json.trim().chars().peekable();
+    let mut vars = Vec::new();
+
+    let skip_spaces = |chars: &mut std::iter::Peekable<std::str::Chars>| {
+        while chars.next_if(|ch| ch.is_ascii_whitespace()).is_some() {}
+    };
+
+    if chars.next()? != '{' {
+        return None;
+    }
+    skip_spaces(&mut chars);
+    if chars.next_if_eq(&'}').is_some() {
+        return Some(vars);
+    }
+    loop {
+        skip_spaces(&mut chars);
+        let name = parse_string(&mut chars)?;
+        skip_spaces(&mut chars);
+        chars.next_if_eq(&':')?;
+        skip_spaces(&mut chars);
+        let value = if chars.peek() == Some(&'n') {
+            for expected in "null".chars() {
+                chars.next_if_eq(&expected)?;
+            }
+            None
+        } else {
+            Some(parse_string(&mut chars)?)
+        };
+        vars.push((name, value));
+
+        skip_spaces(&mut chars);
+        match chars.next()? {
+            ',' => continue,
+            '}' => return Some(vars),
+            _ => return None,
+        }
+    }
+}
+
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    chars.next_if_eq(&'"')?;
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(string),
+            '\\' => {
+                let ch = match chars.next()? {
+                    'b' => '\x08',
+                    'f' => '\x0c',
+                    'n' => '\n',
+                    'r' => '\r',
+                    't' => '\t',
+                    'u' => {
+                        let high = parse_hex4(chars)?;
+                        if (0xd800..0xdc00).contains(&high) {
+                            // a surrogate pair
+                            chars.next_if_eq(&'\\')?;
+                            chars.next_if_eq(&'u')?;
+                            let low = parse_hex4(chars)?;
+                            let code =
+                                0x10000 + ((high - 0xd800) << 10) + (low.checked_sub(0xdc00)?);
+                            char::from_u32(code)?
+                        } else {
+                            char::from_u32(high)?
+                        }
+                    }
+                    ch => ch,
+                };
+                string.push(ch);
+            }
+            ch => string.push(ch),
+        }
+    }
+}
+
+fn parse_hex4(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<u32> {
+    let hex: String = chars.take(4).collect();
+    if hex.len() != 4 {
+        return None;
+    }
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direnv_json() {
+        let json = r#"{
+  "DIRENV_DIFF": "eJx\/abc",
+  "FOO": "a \"b\"\n\u00e9\ud83d\ude00",
+  "OLD": null
+}"#;
+        assert_eq!(
+            parse_object(json),
+            Some(vec![
+                ("DIRENV_DIFF".to_owned(), Some("eJx/abc".to_owned())),
+                ("FOO".to_owned(), Some("a \"b\"\né😀".to_owned())),
+                ("OLD".to_owned(), None),
+            ])
+        );
+        assert_eq!(parse_object("{}"), Some(vec![]));
+        assert_eq!(parse_object(r#"{"A": 1}"#), None);
+        assert_eq!(parse_object(r#"{"A": "x""#), None);
+    }
+}
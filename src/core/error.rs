@@ -0,0 +1,69 @@
+use nix::errno::Errno;
+
+/// Errors which abort the evaluation of a command line
+#[derive(Debug)]
+pub enum ShellError {
+    Syntax(peg::error::ParseError<peg::str::LineCol>),
+    Syscall(&'static str, Errno),
+    Io(std::io::Error),
+    NulByte,
+    Unsupported(&'static str),
+    /// the output of a command substitution exceeds the limit in bytes
+    OutputTooLarge(usize),
+    /// a command substitution ran past the deadline
+    TimedOut,
+}
+
+impl ShellError {
+    /// Returns the exit status reported for this error
+    pub fn status(&self) -> i32 {
+        match self {
+            ShellError::Syntax(_) => 127,
+            _ => 1,
+        }
+    }
+}
+
+impl std::fmt::Display for ShellError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ShellError::Syntax(err) => write!(f, "syntax error: {err}"),
+            ShellError::Syscall(name, errno) => write!(f, "{name}: {}", errno.desc()),
+            ShellError::Io(err) => write!(f, "{err}"),
+            ShellError::NulByte => write!(f, "argument contains a null byte"),
+            ShellError::Unsupported(what) => write!(f, "{what} is not supported yet"),
+            ShellError::OutputTooLarge(limit) => {
+                write!(
+                    f,
+                    "output too large: command substitution exceeds {limit} bytes"
+                )
+            }
+            ShellError::TimedOut => write!(f, "command substitution timed out"),
+        }
+    }
+}
+
+impl std::error::Error for ShellError {}
+
+impl From<std::io::Error> for ShellError {
+    fn from(err: std::io::Error) -> Self {
+        ShellError::Io(err)
+    }
+}
+
+impl From<std::ffi::NulError> for ShellError {
+    fn from(_: std::ffi::NulError) -> Self {
+        ShellError::NulByte
+    }
+}
+
+/// Attaches the name of the failed system call to `nix::Result`
+pub trait SyscallResult<T> {
+    fn syscall(self, name: &'static str) -> Result<T, ShellError>;
+}
+
+impl<T> SyscallResult<T> for nix::Result<T> {
+    fn syscall(self, name: &'static str) -> Result<T, ShellError> {
+        self.map_err(|errno| ShellError::Syscall(name, errno))
+    }
+}
@@ -3,18 +3,22 @@ use nix::unistd;
 use std::io::{Read, Write};
 use std::os::unix::io::RawFd;
 
-fn set_cloexec(fd: RawFd) {
+fn set_cloexec(fd: RawFd) -> nix::Result<()> {
     use nix::fcntl::{fcntl, FcntlArg, OFlag};
-    let old_flags = OFlag::from_bits(fcntl(fd, FcntlArg::F_GETFL).expect("GETFL")).unwrap();
+    let old_flags = OFlag::from_bits_truncate(fcntl(fd, FcntlArg::F_GETFL)?);
     let new_flags = old_flags | OFlag::O_CLOEXEC;
-    fcntl(fd, FcntlArg::F_SETFL(new_flags)).expect("set O_CLOEXEC");
+    fcntl(fd, FcntlArg::F_SETFL(new_flags))?;
+    Ok(())
 }
 
-pub fn pipe_pair() -> (FdRead, FdWrite) {
-    let (pipe_out, pipe_in) = unistd::pipe().expect("pipe");
-    set_cloexec(pipe_out);
-    set_cloexec(pipe_in);
-    (FdRead(pipe_out), FdWrite(pipe_in))
+pub fn pipe_pair() -> nix::Result<(FdRead, FdWrite)> {
+    let (pipe_out, pipe_in) = unistd::pipe()?;
+    if let Err(err) = set_cloexec(pipe_out).and_then(|_| set_cloexec(pipe_in)) {
+        let _ = unistd::close(pipe_out);
+        let _ = unistd::close(pipe_in);
+        return Err(err);
+    }
+    Ok((FdRead(pipe_out), FdWrite(pipe_in)))
 }
 
 #[derive(Debug, Clone, Copy)]
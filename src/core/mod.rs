@@ -1,6 +1,13 @@
-mod ast;
+mod arith;
+pub mod ast;
 mod builtins;
+mod dir_history;
+mod dir_rc;
+mod direnv;
+mod error;
 mod io;
+mod options;
+mod path_watcher;
 
 use nix::errno::Errno;
 use nix::libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
@@ -8,13 +15,22 @@ use nix::sys::{signal, termios, wait};
 use nix::unistd::{self, Pid};
 use std::collections::HashMap;
 use std::ffi::{CStr, CString, OsStr, OsString};
-use std::io::Read;
-use std::os::unix::ffi::OsStrExt as _;
+use std::io::{Read, Write as _};
+use std::os::unix::ffi::{OsStrExt as _, OsStringExt as _};
+use std::os::unix::io::RawFd;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
 
+use crate::completion::{Candidate, CompletionRule};
+use crate::line_editor::Keymap;
 use crate::terminal_size;
 use ast::*;
+use dir_history::DirHistory;
+use dir_rc::TrustedFiles;
+use error::{ShellError, SyscallResult as _};
 use io::{pipe_pair, Io};
+use options::Options;
+use path_watcher::PathWatcher;
 
 fn str_c_to_os(cstr: &CStr) -> &OsStr {
     OsStr::from_bytes(cstr.to_bytes())
@@ -23,6 +39,17 @@ fn str_r_to_os(s: &str) -> &OsStr {
     OsStr::new(s)
 }
 
+fn random_seed() -> u64 {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or(0);
+    // xorshift never leaves the zero state
+    (nanos ^ (unistd::getpid().as_raw() as u64) << 32) | 1
+}
+
 fn get_termios() -> Result<termios::Termios, Errno> {
     termios::tcgetattr(STDIN_FILENO)
 }
@@ -32,7 +59,9 @@ fn set_termios(termios: &termios::Termios) -> Result<(), Errno> {
 
 pub fn expand_tilde(bytes: &[u8]) -> Vec<u8> {
     if bytes.first() == Some(&b'~') {
-        let home = std::env::var_os("HOME").unwrap_or_else(|| todo!());
+        let Some(home) = std::env::var_os("HOME") else {
+            return bytes.to_vec();
+        };
 
         let mut expanded = Vec::new();
         expanded.extend_from_slice(home.as_bytes());
@@ -43,9 +72,11 @@ pub fn expand_tilde(bytes: &[u8]) -> Vec<u8> {
     }
 }
 
-pub fn expand_pattern(bytes: &[u8]) -> Vec<u8> {
+/// Returns the paths matching the pattern `bytes` as separate words, or the pattern itself if
+/// nothing matches unless `nullglob` is set
+pub fn expand_pattern(bytes: &[u8], nullglob: bool) -> Vec<OsString> {
     if !bytes.contains(&b'*') {
-        return bytes.to_vec();
+        return vec![OsString::from_vec(bytes.to_vec())];
     }
 
     type Stack<T> = Vec<T>;
@@ -64,7 +95,8 @@ pub fn expand_pattern(bytes: &[u8]) -> Vec<u8> {
         }
     }
 
-    let mut origin = if bytes.first().copied() == Some(std::path::MAIN_SEPARATOR as u8) {
+    let absolute = bytes.first().copied() == Some(std::path::MAIN_SEPARATOR as u8);
+    let mut origin = if absolute {
         PathBuf::from("/")
     } else {
         PathBuf::from(".")
@@ -82,6 +114,10 @@ pub fn expand_pattern(bytes: &[u8]) -> Vec<u8> {
         for ent in dirhandle.iter().filter_map(|ent| ent.ok()) {
             let file_name = OsStr::from_bytes(ent.file_name().to_bytes());
 
+            // "." and ".." are matched only literally
+            if matches!(file_name.as_bytes(), b"." | b"..") && file_name != pat.as_os_str() {
+                continue;
+            }
             if !matches(pat.as_bytes(), file_name.as_bytes()) {
                 continue;
             }
@@ -140,34 +176,132 @@ pub fn expand_pattern(bytes: &[u8]) -> Vec<u8> {
     let mut matched = Vec::new();
     search(&mut matched, &mut origin, &mut patterns);
 
-    let mut ret = Vec::new();
-    for path in matched {
-        ret.extend(path.as_os_str().as_bytes());
-        ret.push(b' ');
+    if matched.is_empty() && !nullglob {
+        return vec![OsString::from_vec(bytes.to_vec())];
+    }
+
+    // sorted byte-wise as other shells do, without the "./" of the origin
+    let mut matched: Vec<&[u8]> = matched
+        .iter()
+        .map(|path| {
+            let path = path.as_os_str().as_bytes();
+            if absolute {
+                path
+            } else {
+                path.strip_prefix(b"./").unwrap_or(path)
+            }
+        })
+        .collect();
+    matched.sort_unstable();
+    matched.dedup();
+    matched
+        .into_iter()
+        .map(|path| OsString::from_vec(path.to_vec()))
+        .collect()
+}
+
+// appends `bytes` to `buf`, replacing each run of whitespaces with a space
+fn push_words(buf: &mut Vec<u8>, bytes: &[u8]) {
+    let words = bytes.split(|b| matches!(b, b' ' | b'\n' | b'\t'));
+    for (i, word) in words.enumerate() {
+        if i > 0 && !matches!(buf.last(), Some(b' ')) {
+            buf.push(b' ');
+        }
+        buf.extend_from_slice(word);
+    }
+}
+
+// reads `input` to the end into `buf` as `push_words` does unless `quoted`, failing if more than
+// `limit` bytes are appended or it doesn't end by `deadline`
+fn read_output(
+    input: &mut io::FdRead,
+    buf: &mut Vec<u8>,
+    limit: usize,
+    quoted: bool,
+    deadline: Option<std::time::Instant>,
+) -> Result<(), ShellError> {
+    use nix::poll::{poll, PollFd, PollFlags};
+
+    let start = buf.len();
+    let mut chunk = [0; 8192];
+    loop {
+        if let Some(deadline) = deadline {
+            let remaining = deadline.saturating_duration_since(std::time::Instant::now());
+            let mut fds = [PollFd::new(input.0, PollFlags::POLLIN)];
+            match poll(&mut fds, remaining.as_millis().min(i32::MAX as u128) as i32) {
+                Ok(0) => return Err(ShellError::TimedOut),
+                Err(Errno::EINTR) => continue,
+                _ => {}
+            }
+        }
+        match input.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(n) => {
+                if quoted {
+                    buf.extend_from_slice(&chunk[..n]);
+                } else {
+                    push_words(buf, &chunk[..n]);
+                }
+                if buf.len() - start > limit {
+                    return Err(ShellError::OutputTooLarge(limit));
+                }
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err.into()),
+        }
     }
-    ret.pop();
-    ret
 }
 
+// used if sysconf can't tell ARG_MAX
+const DEFAULT_ARG_MAX: usize = 0x200000;
+
 type Pgid = Pid;
 
 #[derive(Clone)]
 enum Executable {
     External(PathBuf),
     Builtin(fn(shell: &mut Shell, args: &[CString], io: Io) -> i32),
+    /// a command provided by the plugin at the path, run as `PLUGIN builtin NAME ARGS...`
+    Plugin(PathBuf),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum JobState {
+    Running,
+    Stopped,
+    Done,
+}
+
+impl std::fmt::Display for JobState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let s = match self {
+            JobState::Running => "Running",
+            JobState::Stopped => "Stopped",
+            JobState::Done => "Done",
+        };
+        // support width and alignment flags
+        s.fmt(f)
+    }
 }
 
 #[derive(Debug)]
 struct Job {
+    id: usize,
+    command: String,
     interactive: bool,
     pgid: Option<Pgid>,
     members: HashMap<Pid, Process>,
+    /// exit statuses of the commands in the order of the pipeline
+    statuses: Vec<Option<i32>>,
     last_status: Option<i32>,
+    /// the signal which has killed one of the commands
+    killed_by: Option<signal::Signal>,
     saved_termios: Option<termios::Termios>,
+    started: std::time::Instant,
 }
 
 impl Job {
-    fn new(interactive: bool) -> Self {
+    fn new(command: String, interactive: bool) -> Self {
         let pgid = if interactive {
             None
         } else {
@@ -175,11 +309,26 @@ impl Job {
         };
 
         Job {
+            id: 0,
+            command,
             interactive,
             pgid,
             members: HashMap::new(),
+            statuses: Vec::new(),
             last_status: None,
+            killed_by: None,
             saved_termios: None,
+            started: std::time::Instant::now(),
+        }
+    }
+
+    fn state(&self) -> JobState {
+        if self.is_completed() {
+            JobState::Done
+        } else if self.is_stopped() {
+            JobState::Stopped
+        } else {
+            JobState::Running
         }
     }
 
@@ -190,11 +339,29 @@ impl Job {
     fn is_completed(&self) -> bool {
         self.members.values().all(|p| p.is_completed())
     }
+
+    /// Returns the exit status of the whole pipeline once it has completed
+    fn pipeline_status(&self, pipefail: bool) -> Option<i32> {
+        let statuses: Option<Vec<i32>> = self.statuses.iter().copied().collect();
+        let statuses = statuses?;
+        if pipefail {
+            Some(statuses.into_iter().rev().find(|s| *s != 0).unwrap_or(0))
+        } else {
+            statuses.last().copied()
+        }
+    }
+
+    fn record_status(&mut self, status: i32) {
+        self.statuses.push(Some(status));
+        self.last_status = Some(status);
+    }
 }
 
 #[derive(Debug)]
 struct Process {
     pid: Pid,
+    /// index in the pipeline
+    position: usize,
     stopped: bool,
     status: Option<i32>,
 }
@@ -207,14 +374,54 @@ impl Process {
 
 pub struct Shell {
     shell_pgid: Pgid,
+    /// whether the shell controls the terminal, where its jobs can be stopped
+    interactive: bool,
     env: Env,
     jobs: HashMap<Pgid, Job>,
+    /// pgids of jobs in the order of being stopped, the most recent one last
+    job_order: Vec<Pgid>,
+    /// processes of disowned jobs, which are still reaped not to remain as zombies
+    disowned: Vec<Pid>,
+    last_status: i32,
+    exit_status: Option<i32>,
+    /// whether exiting was refused because of jobs, so that exiting again forces it
+    exit_warned: bool,
+    /// the terminal settings restored when a foreground job fails, and the ones at startup
+    termios: Option<termios::Termios>,
+    initial_termios: Option<termios::Termios>,
+    options: Options,
+    keymap: Keymap,
+    completion_rules: HashMap<String, CompletionRule>,
+    abbreviations: HashMap<String, String>,
+    /// commands registered by the `hook` builtin for each event
+    hooks: HashMap<String, Vec<String>>,
+    running_hook: bool,
 
     cd_undo_stack: Vec<PathBuf>,
     cd_redo_stack: Vec<PathBuf>,
+    dir_history: DirHistory,
+    trusted_rc: TrustedFiles,
+    /// whether `.myshell.rc` of a directory is being evaluated
+    sourcing_rc: bool,
+    /// whether the rc files are being evaluated by `reload`
+    reloading: bool,
+    /// command substitutions still running at this time are killed
+    subst_deadline: Option<std::time::Instant>,
+
+    start_time: std::time::Instant,
+    /// the number of input lines evaluated so far
+    line_number: usize,
+    random_state: std::cell::Cell<u64>,
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Shell {
+    /// Creates a shell which runs jobs in the foreground of the terminal on stdin
     pub fn new() -> Self {
         use signal::{killpg, sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
 
@@ -241,9 +448,21 @@ impl Shell {
         unsafe { sigaction(Signal::SIGTTIN, &sigign).expect("sigaction SIGTTIN") };
 
         let sigdfl = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
-        unsafe { sigaction(Signal::SIGCHLD, &sigdfl).expect("sigaction SIGCHLD") };
         unsafe { sigaction(Signal::SIGPIPE, &sigdfl).expect("sigaction SIGPIPE") };
 
+        use nix::fcntl::OFlag;
+        if let Ok((read_fd, write_fd)) = unistd::pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK) {
+            SIGCHLD_PIPE_READ.store(read_fd, Ordering::SeqCst);
+            SIGCHLD_PIPE_WRITE.store(write_fd, Ordering::SeqCst);
+        }
+        let handler = SigHandler::Handler(sigchld_handler);
+        let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+        unsafe { sigaction(Signal::SIGCHLD, &action).expect("sigaction SIGCHLD") };
+
+        let handler = SigHandler::Handler(sighup_handler);
+        let action = SigAction::new(handler, SaFlags::empty(), SigSet::empty());
+        unsafe { sigaction(Signal::SIGHUP, &action).expect("sigaction SIGHUP") };
+
         let pid = unistd::getpid();
         match unistd::setpgid(pid, pid) {
             Ok(()) => {}
@@ -259,6 +478,16 @@ impl Shell {
         let shell_pgid = pid;
         unistd::tcsetpgrp(STDIN_FILENO, shell_pgid).expect("tcsetpgrp");
 
+        Self::with_pgid(shell_pgid, true)
+    }
+
+    /// Creates a shell which doesn't need a terminal, running commands in the process group of
+    /// this process and waiting only for its own children
+    pub fn new_noninteractive() -> Self {
+        Self::with_pgid(unistd::getpgrp(), false)
+    }
+
+    fn with_pgid(shell_pgid: Pgid, interactive: bool) -> Self {
         let mut env = Env::new();
         if let Ok(cwd) = std::env::current_dir() {
             env.set_env("PWD", cwd.into_os_string());
@@ -266,11 +495,43 @@ impl Shell {
 
         Self {
             shell_pgid,
+            interactive,
             env,
             jobs: HashMap::new(),
+            job_order: Vec::new(),
+            disowned: Vec::new(),
+            last_status: 0,
+            exit_status: None,
+            exit_warned: false,
+            termios: get_termios().ok(),
+            initial_termios: get_termios().ok(),
+            options: Options::default(),
+            keymap: Keymap::default(),
+            completion_rules: HashMap::new(),
+            abbreviations: HashMap::new(),
+            hooks: HashMap::new(),
+            running_hook: false,
 
             cd_undo_stack: Vec::new(),
             cd_redo_stack: Vec::new(),
+            // an embedded shell leaves the files of the user alone
+            dir_history: if interactive {
+                DirHistory::load()
+            } else {
+                DirHistory::default()
+            },
+            trusted_rc: if interactive {
+                TrustedFiles::load()
+            } else {
+                TrustedFiles::default()
+            },
+            sourcing_rc: false,
+            reloading: false,
+            subst_deadline: None,
+
+            start_time: std::time::Instant::now(),
+            line_number: 0,
+            random_state: std::cell::Cell::new(random_seed()),
         }
     }
 
@@ -278,37 +539,404 @@ impl Shell {
         self.jobs.len()
     }
 
-    fn wait_for_job(&mut self, job_pgid: Pgid) -> i32 {
+    /// Returns the logical working directory, i.e. $PWD as long as it refers to the actual one
+    pub fn cwd(&self) -> std::io::Result<PathBuf> {
+        use std::os::unix::fs::MetadataExt as _;
+
+        let physical = std::env::current_dir()?;
+        if let Some(pwd) = self.env.get_env("PWD").map(Path::new) {
+            let same_dir = match (pwd.metadata(), physical.metadata()) {
+                (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+                _ => false,
+            };
+            if pwd.is_absolute() && same_dir {
+                return Ok(pwd.to_owned());
+            }
+        }
+        Ok(physical)
+    }
+
+    /// Returns the key bindings modified by the `bindkey` builtin
+    pub fn keymap(&self) -> &Keymap {
+        &self.keymap
+    }
+
+    /// Returns the completion rules registered by the `complete` builtin
+    pub fn completion_rules(&self) -> &HashMap<String, CompletionRule> {
+        &self.completion_rules
+    }
+
+    /// Returns whether the option named `name` is enabled by the `set` builtin
+    pub fn option(&self, name: &str) -> bool {
+        self.options.get(name) == Some(true)
+    }
+
+    /// Returns the value of a shell or environment variable
+    pub fn var(&self, name: &str) -> Option<String> {
+        self.env
+            .get_var(name)
+            .map(|val| val.to_string_lossy().into_owned())
+    }
+
+    /// Returns the environment variables passed to commands
+    pub fn env_vars(&self) -> &HashMap<OsString, OsString> {
+        &self.env.env_vars
+    }
+
+    /// Sets a shell variable
+    pub fn set_var(&mut self, name: &str, value: String) {
+        self.env.shell_vars.insert(name.into(), value.into());
+    }
+
+    /// Returns the abbreviations registered by the `abbr` builtin
+    pub fn abbreviations(&self) -> &HashMap<String, String> {
+        &self.abbreviations
+    }
+
+    /// Applies the environment which direnv exports for the current directory
+    pub fn load_direnv(&mut self) {
+        let Some(vars) = direnv::export(&self.env.env_vars) else {
+            return;
+        };
+        for (name, value) in vars {
+            match value {
+                Some(value) => self.env.set_env(&name, value.into()),
+                None => {
+                    self.env.env_vars.remove(str_r_to_os(&name));
+                }
+            }
+            if name == "PATH" {
+                self.env.update_commands();
+            }
+        }
+    }
+
+    /// Makes `name` run the plugin at `plugin`
+    pub fn register_plugin_command(&mut self, name: &str, plugin: &Path) {
+        self.env
+            .plugin_commands
+            .insert(name.into(), plugin.to_owned());
+        self.env.update_commands();
+    }
+
+    /// Runs the commands registered by the `hook` builtin for `event`, keeping `$?`.
+    /// Hooks are not run while another one is running.
+    pub fn run_hooks(&mut self, event: &str) {
+        if self.running_hook {
+            return;
+        }
+        let Some(commands) = self.hooks.get(event).cloned() else {
+            return;
+        };
+
+        self.running_hook = true;
+        let last_status = self.last_status;
+        for command in commands {
+            self.eval(&command);
+            if self.exit_status.is_some() {
+                break;
+            }
+        }
+        self.last_status = last_status;
+        self.running_hook = false;
+    }
+
+    // evaluates `.myshell.rc` in the current directory if the user trusts it, keeping `$?`
+    fn source_dir_rc(&mut self) {
+        if !self.interactive || self.sourcing_rc {
+            return;
+        }
+        let Ok(path) = self.cwd().map(|cwd| cwd.join(dir_rc::FILE_NAME)) else {
+            return;
+        };
+        // the content checked is evaluated even if the file is replaced meanwhile
+        let Ok(content) = std::fs::read(&path) else {
+            return;
+        };
+        if !self.trusted_rc.check(&path, &content) {
+            return;
+        }
+
+        self.sourcing_rc = true;
+        let last_status = self.last_status;
+        for line in String::from_utf8_lossy(&content).lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                self.eval(line);
+            }
+            if self.exit_status.is_some() {
+                break;
+            }
+        }
+        self.last_status = last_status;
+        self.sourcing_rc = false;
+    }
+
+    /// Returns the file descriptor which becomes readable when a child process changes its state
+    pub fn child_event_fd(&self) -> Option<RawFd> {
+        let fd = SIGCHLD_PIPE_READ.load(Ordering::SeqCst);
+        (fd >= 0).then_some(fd)
+    }
+
+    /// Collects the statuses of the jobs not waited for, and removes the completed ones
+    pub fn reap_jobs(&mut self) -> Vec<FinishedJob> {
+        if let Some(fd) = self.child_event_fd() {
+            let mut buf = [0; 16];
+            while matches!(unistd::read(fd, &mut buf), Ok(n) if n > 0) {}
+        }
+
+        // only the known processes are waited for, since other children like the ones of
+        // `run_helper` are waited for by their own threads
+        let pids: Vec<Pid> = self
+            .jobs
+            .values()
+            .flat_map(|job| job.members.values())
+            .filter(|p| !p.is_completed())
+            .map(|p| p.pid)
+            .chain(self.disowned.iter().copied())
+            .collect();
+        let mut reaped = Vec::new();
+        for pid in pids {
+            let flags = wait::WaitPidFlag::WNOHANG | wait::WaitPidFlag::WUNTRACED;
+            match wait::waitpid(pid, Some(flags)) {
+                Ok(wait::WaitStatus::StillAlive) => {}
+                Ok(wait_status @ wait::WaitStatus::Stopped(..)) => {
+                    self.mark_process_status(wait_status)
+                }
+                Ok(wait_status) => {
+                    self.mark_process_status(wait_status);
+                    reaped.push(pid);
+                }
+                Err(_) => reaped.push(pid),
+            }
+        }
+        self.disowned.retain(|pid| !reaped.contains(pid));
+
+        let completed: Vec<Pgid> = self
+            .jobs
+            .iter()
+            .filter(|(_, job)| !job.members.is_empty() && job.is_completed())
+            .map(|(pgid, _)| *pgid)
+            .collect();
+        let mut finished = Vec::new();
+        for pgid in completed {
+            if let Some(job) = self.remove_job(pgid) {
+                finished.push(FinishedJob {
+                    id: job.id,
+                    status: job.pipeline_status(self.options.pipefail).unwrap_or(0),
+                    killed_by: job.killed_by.map(signal_description),
+                    elapsed: job.started.elapsed(),
+                    command: job.command,
+                });
+            }
+        }
+        finished.sort_by_key(|job| job.id);
+        finished
+    }
+
+    /// Returns the status requested by the `exit` builtin, if any
+    pub fn exit_status(&self) -> Option<i32> {
+        self.exit_status
+    }
+
+    /// Requests exit with `status`. If there are jobs, returns their number and refuses it
+    /// unless it has just been refused, in which case the jobs are hung up.
+    pub fn request_exit(&mut self, status: i32) -> Result<(), usize> {
+        if !self.jobs.is_empty() {
+            if !self.exit_warned {
+                self.exit_warned = true;
+                return Err(self.jobs.len());
+            }
+            for &pgid in self.jobs.keys() {
+                hang_up(pgid);
+            }
+        }
+        self.exit_status = Some(status);
+        Ok(())
+    }
+
+    fn add_job(&mut self, mut job: Job) -> Pgid {
+        // the smallest unused number
+        job.id = (1..)
+            .find(|id| self.jobs.values().all(|j| j.id != *id))
+            .unwrap();
+
+        let job_pgid = job.pgid.unwrap();
+        self.jobs.insert(job_pgid, job);
+        let free = JOB_PGIDS.iter().find(|slot| {
+            let result =
+                slot.compare_exchange(0, job_pgid.as_raw(), Ordering::SeqCst, Ordering::SeqCst);
+            result.is_ok()
+        });
+        if free.is_none() {
+            eprintln!("shell: job {job_pgid} won't be hung up with the terminal");
+        }
+        job_pgid
+    }
+
+    fn remove_job(&mut self, job_pgid: Pgid) -> Option<Job> {
+        self.job_order.retain(|pgid| *pgid != job_pgid);
+        for slot in JOB_PGIDS.iter() {
+            let _ = slot.compare_exchange(job_pgid.as_raw(), 0, Ordering::SeqCst, Ordering::SeqCst);
+        }
+        self.jobs.remove(&job_pgid)
+    }
+
+    /// Returns the current job (`%+`) and the previous job (`%-`)
+    fn current_jobs(&self) -> (Option<Pgid>, Option<Pgid>) {
+        let mut iter = self.job_order.iter().rev().copied();
+        (iter.next(), iter.next())
+    }
+
+    /// Resolves a job spec (`%N`, `%+`, `%-`, `%prefix`, `%?substring` or a pgid) into a pgid
+    fn find_job(&self, spec: &str) -> Result<Pgid, &'static str> {
+        let (current, previous) = self.current_jobs();
+
+        let Some(spec) = spec.strip_prefix('%') else {
+            return spec
+                .parse::<i32>()
+                .ok()
+                .map(Pgid::from_raw)
+                .filter(|pgid| self.jobs.contains_key(pgid))
+                .ok_or("no such job");
+        };
+
+        match spec {
+            "" | "%" | "+" => current.ok_or("no current job"),
+            "-" => previous.ok_or("no previous job"),
+
+            _ if spec.bytes().all(|b| b.is_ascii_digit()) => {
+                let id: usize = spec.parse().map_err(|_| "no such job")?;
+                self.jobs
+                    .iter()
+                    .find(|(_, job)| job.id == id)
+                    .map(|(pgid, _)| *pgid)
+                    .ok_or("no such job")
+            }
+
+            _ => {
+                let mut matched = self.jobs.iter().filter(|(_, job)| {
+                    if let Some(substr) = spec.strip_prefix('?') {
+                        job.command.contains(substr)
+                    } else {
+                        job.command.starts_with(spec)
+                    }
+                });
+
+                match (matched.next(), matched.next()) {
+                    (Some((pgid, _)), None) => Ok(*pgid),
+                    (Some(_), Some(_)) => Err("ambiguous job spec"),
+                    (None, _) => Err("no such job"),
+                }
+            }
+        }
+    }
+
+    fn wait_for_job(&mut self, job_pgid: Pgid) -> Result<i32, ShellError> {
         if let Some(job) = self.jobs.get(&job_pgid) {
             if job.members.is_empty() {
-                let status = job.last_status.unwrap();
+                let status = job.pipeline_status(self.options.pipefail).unwrap();
                 if job.is_completed() {
-                    self.jobs.remove(&job_pgid);
+                    self.remove_job(job_pgid);
                 } else {
                     unreachable!();
                 }
-                return status;
+                return Ok(status);
             }
         }
 
         loop {
-            let child_any = Pid::from_raw(-1);
-            let handle_stop = Some(wait::WaitPidFlag::WUNTRACED);
-            let wait_status = wait::waitpid(child_any, handle_stop).expect("waitpid");
+            let (child, handle_stop) = if self.interactive {
+                // only the processes of the job, which are in its own process group
+                let group = Pid::from_raw(-job_pgid.as_raw());
+                (group, Some(wait::WaitPidFlag::WUNTRACED))
+            } else {
+                // other children of the process embedding the shell are left alone
+                let job = &self.jobs[&job_pgid];
+                let member = job.members.values().find(|p| !p.is_completed());
+                (member.map_or(Pid::from_raw(-1), |p| p.pid), None)
+            };
+            let wait_status = match wait::waitpid(child, handle_stop) {
+                Ok(wait_status) => wait_status,
+                Err(Errno::EINTR) => continue,
+                Err(err) => {
+                    // there is no child to wait for any more
+                    self.remove_job(job_pgid);
+                    return Err(ShellError::Syscall("waitpid", err));
+                }
+            };
 
             self.mark_process_status(wait_status);
 
             let job = self.jobs.get(&job_pgid).unwrap();
-            if job.is_stopped() || job.is_completed() {
-                let status = job.last_status.unwrap();
-                if job.is_completed() {
-                    self.jobs.remove(&job_pgid);
+            if job.is_completed() {
+                let status = job.pipeline_status(self.options.pipefail).unwrap();
+                if self.interactive && job.killed_by == Some(signal::Signal::SIGINT) {
+                    // on the next line of "^C" echoed by the terminal
+                    eprintln!();
                 }
-                return status;
+                self.remove_job(job_pgid);
+                return Ok(status);
+            }
+            if job.is_stopped() {
+                return Ok(job.last_status.unwrap());
+            }
+        }
+    }
+
+    fn wait_in_foreground(&mut self, job_pgid: Pgid) -> Result<i32, ShellError> {
+        if !self.interactive {
+            return self.wait_for_job(job_pgid);
+        }
+
+        let saved_termios = get_termios().syscall("tcgetattr")?;
+
+        self.set_foreground(job_pgid)?;
+        let status = self.wait_for_job(job_pgid);
+        self.set_foreground(self.shell_pgid)?;
+        let status = status?;
+
+        if let Some(job) = self.jobs.get_mut(&job_pgid) {
+            if job.is_stopped() {
+                job.saved_termios = Some(get_termios().syscall("tcgetattr")?);
+                set_termios(&saved_termios).syscall("tcsetattr")?;
+
+                self.job_order.retain(|pgid| *pgid != job_pgid);
+                self.job_order.push(job_pgid);
+                self.report_stopped(job_pgid);
             }
         }
+        if !self.jobs.contains_key(&job_pgid) {
+            self.settle_termios(status);
+        }
+
+        Ok(status)
+    }
+
+    /// Tells that the job, which has become the current one, is stopped as `jobs` shows it
+    fn report_stopped(&self, job_pgid: Pgid) {
+        if let Some(job) = self.jobs.get(&job_pgid) {
+            // on the next line of "^Z" echoed by the terminal
+            eprintln!("\n[{}]+ {:<10}{}", job.id, job.state(), job.command);
+        }
     }
 
+    /// Keeps the terminal settings changed by a foreground job which has succeeded, or restores
+    /// the previous ones if it has failed, as it may have died leaving them broken
+    fn settle_termios(&mut self, status: i32) {
+        let Ok(current) = get_termios() else {
+            return;
+        };
+        if status == 0 {
+            self.termios = Some(current);
+        } else if let Some(saved) = &self.termios {
+            if *saved != current {
+                let _ = set_termios(saved);
+            }
+        }
+    }
+
+    // the status of a process not in the jobs, which has been disowned, is ignored
     fn mark_process_status(&mut self, wait_status: wait::WaitStatus) {
         match wait_status {
             wait::WaitStatus::Exited(pid, status) => {
@@ -317,12 +945,12 @@ impl Shell {
                     for p in job.members.values_mut() {
                         if p.pid == pid {
                             p.status = Some(status);
+                            job.statuses[p.position] = Some(status);
                             job.last_status = Some(status);
                             return;
                         }
                     }
                 }
-                unreachable!("procedd {pid} not found");
             }
 
             wait::WaitStatus::Signaled(pid, signal, _coredump) => {
@@ -330,15 +958,15 @@ impl Shell {
                 for job in self.jobs.values_mut() {
                     for p in job.members.values_mut() {
                         if p.pid == pid {
-                            // eprintln!("\x1b[7mprocess {pid} is terminated by {signal:?}\x1b[m");
                             let signaled = 128 + signal as i32;
                             p.status = Some(signaled);
+                            job.statuses[p.position] = Some(signaled);
                             job.last_status = Some(signaled);
+                            job.killed_by = Some(signal);
                             return;
                         }
                     }
                 }
-                unreachable!("procedd {pid} not found");
             }
 
             wait::WaitStatus::Stopped(pid, signal) => {
@@ -346,7 +974,6 @@ impl Shell {
                 for job in self.jobs.values_mut() {
                     for p in job.members.values_mut() {
                         if p.pid == pid {
-                            // eprintln!("\x1b[7mprocess {pid} is stopped by {signal:?}\x1b[m");
                             p.stopped = true;
                             let signaled = 128 + signal as i32;
                             job.last_status = Some(signaled);
@@ -354,91 +981,224 @@ impl Shell {
                         }
                     }
                 }
-                unreachable!("procedd {pid} not found");
             }
 
             _ => unreachable!(),
         }
     }
 
-    fn set_foreground(&mut self, pgid: Pgid) {
-        unistd::tcsetpgrp(STDIN_FILENO, pgid).expect("tcsetpgrp");
+    fn set_foreground(&mut self, pgid: Pgid) -> Result<(), ShellError> {
+        unistd::tcsetpgrp(STDIN_FILENO, pgid).syscall("tcsetpgrp")
     }
 
     pub fn eval(&mut self, program: &str) -> i32 {
-        match ast::parser::toplevel(program) {
-            Ok(program_tree) => self.eval_list(&program_tree, Io::stdio(), true),
-            Err(_err) => {
-                eprintln!("Syntax Error");
-                127
+        self.eval_with_io(program, Io::stdio())
+    }
+
+    /// Runs the builtin `args[0]` with the arguments as they are, without expanding them
+    pub fn run_builtin(&mut self, args: &[&str]) -> i32 {
+        let Some(name) = args.first() else {
+            return 0;
+        };
+        let Ok(args) = args.iter().map(|arg| CString::new(*arg)).collect::<Result<Vec<_>, _>>()
+        else {
+            eprintln!("shell: {name}: arguments contain a null character");
+            return 1;
+        };
+        match self.env.commands.get(OsStr::new(name)) {
+            Some(Executable::Builtin(impl_fptr)) => {
+                let impl_fptr = *impl_fptr;
+                impl_fptr(self, &args, Io::stdio())
+            }
+            _ => {
+                eprintln!("shell: {name}: not a shell builtin");
+                1
             }
         }
     }
 
-    fn eval_list(&mut self, list: &List, io: Io, interactive: bool) -> i32 {
-        let mut last_status;
+    /// Evaluates `program` without the terminal, and returns the status and what it has
+    /// written to stdout and stderr
+    pub fn eval_str_capture(&mut self, program: &str) -> (i32, Vec<u8>, Vec<u8>) {
+        let pipes = pipe_pair().and_then(|output| Ok((output, pipe_pair()?)));
+        let ((out_read, out_write), (err_read, err_write)) = match pipes {
+            Ok(pipes) => pipes,
+            Err(err) => {
+                let message = format!("shell: {}", ShellError::Syscall("pipe", err));
+                return (1, Vec::new(), message.into_bytes());
+            }
+        };
 
-        {
-            let mut job = Job::new(interactive);
-            self.eval_pipeline(&list.first, &mut job, io);
-            let job_pgid = job.pgid.unwrap();
-            self.jobs.insert(job_pgid, job);
+        // both are read at once not to be blocked by either pipe
+        let read_all = |mut input: io::FdRead| {
+            std::thread::spawn(move || {
+                let mut buf = Vec::new();
+                let _ = input.read_to_end(&mut buf);
+                let _ = unistd::close(input.0);
+                buf
+            })
+        };
+        let out_reader = read_all(out_read);
+        let err_reader = read_all(err_read);
 
-            let saved_termios = get_termios().expect("tcgetattr");
+        let io = Io::stdio().set_output(out_write).set_error(err_write);
+        let status = self.eval_with_io(program, io);
+        let _ = unistd::close(out_write.0);
+        let _ = unistd::close(err_write.0);
 
-            self.set_foreground(job_pgid);
-            last_status = self.wait_for_job(job_pgid);
-            self.set_foreground(self.shell_pgid);
+        let output = out_reader.join().expect("reader thread");
+        let error = err_reader.join().expect("reader thread");
+        (status, output, error)
+    }
 
-            if let Some(job) = self.jobs.get_mut(&job_pgid) {
-                if job.is_stopped() {
-                    job.saved_termios = Some(get_termios().expect("tcgetattr"));
-                    set_termios(&saved_termios).expect("tcsetattr");
-                }
+    fn eval_with_io(&mut self, program: &str, io: Io) -> i32 {
+        self.line_number += 1;
+        let old_cwd = self.cwd().ok();
+        // the warning lasts only until the next line
+        let exit_warned = self.exit_warned && !self.running_hook;
+
+        let result = match ast::parser::toplevel(program) {
+            Ok(program_tree) => self.eval_list(&program_tree, io, self.interactive),
+            Err(err) => Err(ShellError::Syntax(err)),
+        };
+
+        let status = match result {
+            Ok(status) => status,
+            Err(err) => {
+                let mut error = io.error;
+                let _ = writeln!(error, "shell: {err}");
+                self.last_status = err.status();
+                self.last_status
             }
+        };
+
+        if exit_warned {
+            self.exit_warned = false;
+        }
+        if self.cwd().ok() != old_cwd {
+            self.run_hooks("chpwd");
+            self.source_dir_rc();
+        }
+        status
+    }
+
+    /// Expands the variables and the command substitutions in the prompt template as if it were
+    /// in double quotes. Backslashes in their values are escaped not to be taken as escapes of
+    /// the template, and the substitutions still running after `timeout` are killed.
+    pub fn expand_prompt(&mut self, template: &str, timeout: std::time::Duration) -> String {
+        let parts = match ast::parser::template(template) {
+            Ok(parts) => parts,
+            Err(err) => {
+                eprintln!("shell: PROMPT: {}", ShellError::Syntax(err));
+                return template.to_owned();
+            }
+        };
+
+        self.subst_deadline = Some(std::time::Instant::now() + timeout);
+        let mut expanded = String::new();
+        for part in parts.iter() {
+            match part {
+                StrPart::Expansion(_) => match self.eval_str(std::slice::from_ref(part), true) {
+                    Ok(value) => {
+                        let value = String::from_utf8_lossy(&value);
+                        expanded.push_str(&value.replace('\\', "\\\\"));
+                    }
+                    Err(err) => eprintln!("shell: PROMPT: {err}"),
+                },
+                StrPart::Chars(chars) => expanded.push_str(chars),
+                StrPart::Quoted(_) => unreachable!(),
+            }
+        }
+        self.subst_deadline = None;
+        expanded
+    }
+
+    /// Returns the syntax tree of `program` pretty-printed, or the syntax error
+    pub fn dump_syntax_tree(program: &str) -> Result<String, String> {
+        match ast::parser::toplevel(program) {
+            Ok(tree) => Ok(format!("{tree:#?}")),
+            Err(err) => Err(ShellError::Syntax(err).to_string()),
         }
+    }
+
+    /// Returns the byte offset where `program` has a syntax error, if any
+    pub fn syntax_error_at(program: &str) -> Option<usize> {
+        ast::parser::toplevel(program)
+            .err()
+            .map(|err| err.location.offset)
+    }
+
+    fn eval_list(&mut self, list: &List, io: Io, interactive: bool) -> Result<i32, ShellError> {
+        let mut last_status = self.eval_job(&list.first, io, interactive)?;
+
+        for (i, (cond, pipeline)) in list.following.iter().enumerate() {
+            self.check_errexit(last_status, Some(cond));
+            if self.exit_status.is_some() {
+                break;
+            }
 
-        for (cond, pipeline) in list.following.iter() {
             if (*cond == Condition::IfSuccess && last_status != 0)
                 || (*cond == Condition::IfError && last_status == 0)
             {
                 break;
             }
 
-            let mut job = Job::new(interactive);
-            self.eval_pipeline(pipeline, &mut job, io);
-            let job_pgid = job.pgid.unwrap();
-            self.jobs.insert(job_pgid, job);
+            last_status = self.eval_job(pipeline, io, interactive)?;
+            if i + 1 == list.following.len() {
+                self.check_errexit(last_status, None);
+            }
+        }
+        if list.following.is_empty() {
+            self.check_errexit(last_status, None);
+        }
 
-            let saved_termios = get_termios().expect("tcgetattr");
+        Ok(last_status)
+    }
 
-            self.set_foreground(job_pgid);
-            last_status = self.wait_for_job(job_pgid);
-            self.set_foreground(self.shell_pgid);
+    /// Requests exit if errexit is set and the failure isn't tested by `&&` or `||`
+    fn check_errexit(&mut self, status: i32, next: Option<&Condition>) {
+        let tested = matches!(next, Some(Condition::IfSuccess | Condition::IfError));
+        if self.options.errexit && status != 0 && !tested && self.exit_status.is_none() {
+            self.exit_status = Some(status);
+        }
+    }
 
-            if let Some(job) = self.jobs.get_mut(&job_pgid) {
-                if job.is_stopped() {
-                    job.saved_termios = Some(get_termios().expect("tcgetattr"));
-                    set_termios(&saved_termios).expect("tcsetattr");
-                }
+    fn eval_job(
+        &mut self,
+        pipeline: &Pipeline,
+        io: Io,
+        interactive: bool,
+    ) -> Result<i32, ShellError> {
+        let mut job = Job::new(pipeline.to_string(), interactive);
+        let result = self.eval_pipeline(pipeline, &mut job, io);
+
+        if let Err(err) = result {
+            // wait for the processes already launched so that they are not left behind
+            if job.pgid.is_some() && !job.members.is_empty() {
+                let job_pgid = self.add_job(job);
+                let _ = self.wait_in_foreground(job_pgid);
             }
+            return Err(err);
         }
 
-        if !interactive {
-            std::process::exit(last_status);
-        }
+        let job_pgid = self.add_job(job);
 
-        last_status
+        let status = self.wait_in_foreground(job_pgid)?;
+        self.last_status = status;
+        Ok(status)
     }
 
-    fn eval_pipeline(&mut self, pipeline: &Pipeline, job: &mut Job, io: Io) {
+    fn eval_pipeline(
+        &mut self,
+        pipeline: &Pipeline,
+        job: &mut Job,
+        io: Io,
+    ) -> Result<(), ShellError> {
         match pipeline {
-            Pipeline::Single(cmd) => {
-                self.eval_command(cmd, job, io);
-            }
+            Pipeline::Single(cmd) => self.eval_command(cmd, job, io),
 
             Pipeline::Connected { pipe, lhs, rhs } => {
-                let (pipe_read, pipe_write) = pipe_pair();
+                let (pipe_read, pipe_write) = pipe_pair().syscall("pipe")?;
 
                 let lhs_io;
                 let rhs_io;
@@ -457,48 +1217,102 @@ impl Shell {
                     }
                 }
 
-                self.eval_pipeline(lhs, job, lhs_io);
-                unistd::close(pipe_write.0).expect("close");
+                let result = self.eval_pipeline(lhs, job, lhs_io);
+                let _ = unistd::close(pipe_write.0);
+
+                let result = result.and_then(|_| self.eval_pipeline(rhs, job, rhs_io));
+                let _ = unistd::close(pipe_read.0);
 
-                self.eval_pipeline(rhs, job, rhs_io);
-                unistd::close(pipe_read.0).expect("close");
+                result
             }
         }
     }
 
-    fn eval_command(&mut self, cmd: &Command, job: &mut Job, io: Io) {
+    fn eval_command(&mut self, cmd: &Command, job: &mut Job, io: Io) -> Result<(), ShellError> {
         match cmd {
             Command::Simple(args) => {
-                let mut args: Vec<CString> = args.iter().flat_map(|a| self.eval_args(a)).collect();
-                assert!(!args.is_empty());
+                let mut evaluated = Vec::new();
+                for arg in args.iter() {
+                    evaluated.extend(self.eval_args(arg)?);
+                }
+                let mut args = evaluated;
+
+                if args.is_empty() {
+                    // every argument has been expanded to nothing
+                    if job.pgid.is_none() {
+                        job.pgid = Some(self.shell_pgid);
+                    }
+                    job.record_status(0);
+                    return Ok(());
+                }
 
                 let arg0 = str_c_to_os(&args[0]);
                 if let Some(alias_values) = self.env.aliases.get(arg0) {
                     let mut actual_args: Vec<CString> = alias_values
                         .iter()
-                        .map(|s| CString::new(s.as_bytes()).unwrap())
-                        .collect();
+                        .map(|s| CString::new(s.as_bytes()))
+                        .collect::<Result<_, _>>()?;
                     actual_args.extend(args.drain(1..));
                     std::mem::swap(&mut args, &mut actual_args);
                 }
 
+                if self.options.xtrace {
+                    let words: Vec<_> = args.iter().map(|a| a.to_string_lossy()).collect();
+                    eprintln!("+ {}", words.join(" "));
+                }
+
                 let exe = {
                     let arg0_os = str_c_to_os(&args[0]);
+                    if !self.env.commands.contains_key(arg0_os)
+                        && !arg0_os
+                            .as_bytes()
+                            .contains(&(std::path::MAIN_SEPARATOR as u8))
+                    {
+                        // the command may have been installed after the last scan, which the
+                        // watcher tells without rescanning PATH on every typo
+                        self.env.sync_commands();
+                        if !self.env.commands.contains_key(arg0_os) {
+                            // or the cache may have been cleared by `hash -r`
+                            self.env.resolve_command(arg0_os);
+                        }
+                    }
+
                     self.env.commands.get(arg0_os).cloned().unwrap_or_else(|| {
                         let path = PathBuf::from(arg0_os);
                         Executable::External(path)
                     })
                 };
 
+                // with `autocd`, a directory given alone is changed to like `cd DIR`
+                let exe = match exe {
+                    Executable::External(path)
+                        if self.options.autocd && args.len() == 1 && path.is_dir() =>
+                    {
+                        args.insert(0, CString::new("cd").unwrap());
+                        Executable::Builtin(builtins::builtin_cd)
+                    }
+                    exe => exe,
+                };
+
                 match exe {
                     Executable::External(exe_path) => self.do_fork_exec(&exe_path, &args, job, io),
 
+                    Executable::Plugin(plugin) => {
+                        let mut plugin_args = vec![
+                            CString::new(plugin.as_os_str().as_bytes())?,
+                            CString::new("builtin").unwrap(),
+                        ];
+                        plugin_args.extend(args);
+                        self.do_fork_exec(&plugin, &plugin_args, job, io)
+                    }
+
                     Executable::Builtin(impl_fptr) => {
                         let status = impl_fptr(self, &args, io);
                         if job.pgid.is_none() {
                             job.pgid = Some(self.shell_pgid);
                         }
-                        job.last_status = Some(status);
+                        job.record_status(status);
+                        Ok(())
                     }
                 }
             }
@@ -525,47 +1339,66 @@ impl Shell {
                 // the forked process will be terminated because:
                 // - it belongs to the foreground process group
                 // - it doesn't ignore the SIGINT signal
-                todo!();
+                Err(ShellError::Unsupported("subshell"))
             }
         }
     }
 
-    fn eval_args(&mut self, args: &Arguments) -> Vec<CString> {
+    fn eval_args(&mut self, args: &Arguments) -> Result<Vec<CString>, ShellError> {
+        self.eval_words(args)?
+            .into_iter()
+            .map(|word| Ok(CString::new(word.into_vec())?))
+            .collect()
+    }
+
+    // expands `args` into words, each of the paths matching a pattern being a word.
+    // a string with quoted parts is taken as a word as it is.
+    fn eval_words(&mut self, args: &Arguments) -> Result<Vec<OsString>, ShellError> {
+        let (Arguments::Arg(str_parts) | Arguments::AtExpansion(str_parts)) = args;
+        let bytes = self.eval_str(str_parts, false)?;
+        if str_parts
+            .iter()
+            .any(|part| matches!(part, StrPart::Quoted(_)))
+        {
+            return Ok(vec![OsString::from_vec(bytes)]);
+        }
+
+        let nullglob = self.options.nullglob;
         match args {
-            Arguments::Arg(str_parts) => {
-                let bytes = self.eval_str(str_parts);
-                let cstring = CString::new(bytes).unwrap();
-                vec![cstring]
-            }
+            Arguments::Arg(_) => Ok(expand_pattern(&expand_tilde(&bytes), nullglob)),
 
-            Arguments::AtExpansion(s) => {
-                self.eval_str(s)
+            Arguments::AtExpansion(_) => {
+                let fields = bytes
                     .split(|&b| {
                         // FIXME: support other whitespace characters
                         b == b' ' || b == b'\n' || b == b'\t'
                     })
-                    .filter(|chunk| !chunk.is_empty())
-                    .map(|chunk| {
-                        let bytes = chunk.to_vec();
-                        CString::new(bytes).unwrap()
-                    })
-                    .collect()
+                    .filter(|field| !field.is_empty());
+                // a tilde in the output is not expanded
+                Ok(fields
+                    .flat_map(|field| expand_pattern(field, nullglob))
+                    .collect())
             }
         }
     }
 
-    fn eval_str(&mut self, parts: &[StrPart]) -> Vec<u8> {
+    // concatenates `parts`, keeping the whitespaces in the output of substitutions if `quoted`
+    fn eval_str(&mut self, parts: &[StrPart], quoted: bool) -> Result<Vec<u8>, ShellError> {
         let mut buf = Vec::new();
         for part in parts {
             match part {
                 StrPart::Chars(chars) => buf.extend(chars.as_bytes()),
 
+                StrPart::Quoted(parts) => {
+                    let bytes = self.eval_str(parts, true)?;
+                    buf.extend(bytes);
+                }
+
                 StrPart::Expansion(expansion) => match expansion {
                     Expansion::Variable { name } => {
-                        let name = str_r_to_os(name);
-                        if let Some(value) = self.env.shell_vars.get(name) {
-                            buf.extend_from_slice(value.as_bytes());
-                        } else if let Some(value) = self.env.env_vars.get(name) {
+                        if let Some(value) = self.special_variable(name) {
+                            buf.extend(value.as_bytes());
+                        } else if let Some(value) = self.env.get_var(name) {
                             buf.extend_from_slice(value.as_bytes());
                         }
                     }
@@ -573,7 +1406,7 @@ impl Shell {
                     Expansion::SubstStdout(list)
                     | Expansion::SubstStderr(list)
                     | Expansion::SubstBoth(list) => {
-                        let (pipe_read, pipe_write) = pipe_pair();
+                        let (pipe_read, pipe_write) = pipe_pair().syscall("pipe")?;
 
                         let io = match expansion {
                             Expansion::SubstStdout(_) => Io::stdio().set_output(pipe_write),
@@ -584,78 +1417,175 @@ impl Shell {
                             _ => unreachable!(),
                         };
 
-                        let child = match unsafe { unistd::fork() } {
-                            Ok(unistd::ForkResult::Child) => {
-                                unistd::close(pipe_read.0).expect("close");
-
-                                self.eval_list(list, io, false);
-                                unreachable!();
-                            }
-
-                            Ok(unistd::ForkResult::Parent { child, .. }) => {
-                                unistd::close(pipe_write.0).expect("close");
-                                child
-                            }
-
-                            Err(_) => panic!("fork failed"),
+                        let start = buf.len();
+                        // the output becomes an argument, which can't exceed ARG_MAX
+                        let limit = match unistd::sysconf(unistd::SysconfVar::ARG_MAX) {
+                            Ok(Some(arg_max)) if arg_max > 0 => arg_max as usize,
+                            _ => DEFAULT_ARG_MAX,
                         };
 
-                        let mut pipe_read = pipe_read;
+                        // the ones in the prompt are forked anyway, to be killed in time
+                        // and not to change the shell on every prompt
+                        if self.subst_deadline.is_none() && self.is_builtin_only(list) {
+                            // builtins are run in this process, while another thread reads
+                            // the output not to be blocked by the pipe
+                            let mut words = std::mem::take(&mut buf);
+                            let reader = std::thread::spawn(move || {
+                                let mut pipe_read = pipe_read;
+                                let result =
+                                    read_output(&mut pipe_read, &mut words, limit, quoted, None);
+                                // the rest is discarded so that the builtins can finish
+                                let mut chunk = [0; 8192];
+                                while matches!(pipe_read.read(&mut chunk), Ok(n) if n > 0) {}
+                                let _ = unistd::close(pipe_read.0);
+                                (words, result)
+                            });
+
+                            // as in a subshell, neither `exit`, `set -e` nor `$?` reach
+                            // the shell itself
+                            let exit_status = self.exit_status.take();
+                            let exit_warned = self.exit_warned;
+                            let last_status = self.last_status;
+                            let errexit = std::mem::replace(&mut self.options.errexit, false);
+                            if let Err(err) = self.eval_list(list, io, false) {
+                                eprintln!("shell: {err}");
+                            }
+                            self.options.errexit = errexit;
+                            self.last_status = last_status;
+                            self.exit_warned = exit_warned;
+                            self.exit_status = exit_status;
+                            let _ = unistd::close(pipe_write.0);
+
+                            let (words, read_result) = reader.join().expect("reader thread");
+                            buf = words;
+                            read_result?;
+                        } else {
+                            let child = match unsafe { unistd::fork() } {
+                                Ok(unistd::ForkResult::Child) => {
+                                    let _ = unistd::close(pipe_read.0);
+                                    if self.subst_deadline.is_some() {
+                                        // to be killed with the commands it runs, which are
+                                        // kept away from the terminal
+                                        let _ = unistd::setpgid(Pid::from_raw(0), Pid::from_raw(0));
+                                        self.interactive = false;
+                                    }
+
+                                    let status = match self.eval_list(list, io, false) {
+                                        Ok(status) => status,
+                                        Err(err) => {
+                                            eprintln!("shell: {err}");
+                                            err.status()
+                                        }
+                                    };
+                                    std::process::exit(status);
+                                }
 
-                        // TODO: sysconf ARG_MAX
-                        const ARG_SIZE_LIMIT: u64 = 0x200000;
+                                Ok(unistd::ForkResult::Parent { child, .. }) => {
+                                    let _ = unistd::close(pipe_write.0);
+                                    if self.subst_deadline.is_some() {
+                                        let _ = unistd::setpgid(child, child);
+                                    }
+                                    child
+                                }
 
-                        let mut arg_buf = Vec::new();
-                        (&mut pipe_read)
-                            .take(ARG_SIZE_LIMIT)
-                            .read_to_end(&mut arg_buf)
-                            .expect("read");
+                                Err(err) => {
+                                    let _ = unistd::close(pipe_read.0);
+                                    let _ = unistd::close(pipe_write.0);
+                                    return Err(ShellError::Syscall("fork", err));
+                                }
+                            };
 
-                        unistd::close(pipe_read.0).expect("close");
+                            let mut pipe_read = pipe_read;
+                            let deadline = self.subst_deadline;
+                            let read_result =
+                                read_output(&mut pipe_read, &mut buf, limit, quoted, deadline);
 
-                        wait::waitpid(child, None).expect("wait");
+                            // the child is killed by SIGPIPE if it is still writing
+                            let _ = unistd::close(pipe_read.0);
 
-                        for byte in arg_buf {
-                            if byte == b' ' || byte == b'\n' || byte == b'\t' {
-                                if !matches!(buf.last(), Some(b' ')) {
-                                    buf.push(b' ');
-                                }
-                            } else {
-                                buf.push(byte);
+                            if matches!(read_result, Err(ShellError::TimedOut)) {
+                                let _ = signal::killpg(child, signal::Signal::SIGKILL);
                             }
+                            let wait_result = wait::waitpid(child, None);
+                            read_result?;
+                            wait_result.syscall("waitpid")?;
                         }
 
-                        if matches!(buf.last(), Some(b' ')) {
+                        if quoted {
+                            while buf.len() > start && matches!(buf.last(), Some(b'\n')) {
+                                buf.pop();
+                            }
+                        } else if matches!(buf.last(), Some(b' ')) {
                             buf.pop();
                         }
                     }
 
                     Expansion::SubstPipeName(_list) => {
-                        todo!();
+                        return Err(ShellError::Unsupported("=(...) substitution"));
                     }
 
                     Expansion::SubstStatus(_list) => {
-                        todo!();
+                        return Err(ShellError::Unsupported("?(...) substitution"));
                     }
                 },
             }
         }
 
-        let buf = expand_tilde(&buf);
-        let buf = expand_pattern(&buf);
+        Ok(buf)
+    }
 
-        buf
+    // whether `list` runs only builtins, which are run without forking in a substitution
+    fn is_builtin_only(&self, list: &List) -> bool {
+        let mut pipelines =
+            std::iter::once(&list.first).chain(list.following.iter().map(|(_, p)| p));
+        pipelines.all(|pipeline| {
+            let Pipeline::Single(Command::Simple(args)) = pipeline else {
+                return false;
+            };
+            let Some(Arguments::Arg(arg0)) = args.first() else {
+                return false;
+            };
+            let [StrPart::Chars(name)] = arg0.as_slice() else {
+                return false;
+            };
+            let name = str_r_to_os(name);
+            if self.env.aliases.contains_key(name) {
+                return false;
+            }
+            // `builtin NAME` runs the builtin NAME
+            let name = match args.get(1) {
+                Some(Arguments::Arg(arg1)) if name == "builtin" => match arg1.as_slice() {
+                    [StrPart::Chars(name)] => str_r_to_os(name),
+                    _ => return false,
+                },
+                _ => name,
+            };
+            // these would exit the shell, or take over its jobs and the terminal, instead of
+            // only the substitution
+            !["exit", "fg", "disown", "reload"]
+                .iter()
+                .any(|b| name == *b)
+                && matches!(self.env.commands.get(name), Some(Executable::Builtin(_)))
+        })
     }
 
-    fn do_fork_exec(&mut self, exe_path: &Path, args: &[CString], job: &mut Job, io: Io) {
-        let exe = CString::new(exe_path.as_os_str().as_bytes()).unwrap();
+    fn do_fork_exec(
+        &mut self,
+        exe_path: &Path,
+        args: &[CString],
+        job: &mut Job,
+        io: Io,
+    ) -> Result<(), ShellError> {
+        let exe = CString::new(exe_path.as_os_str().as_bytes())?;
 
         match unsafe { unistd::fork() } {
             Ok(unistd::ForkResult::Child) => {
                 let current_pid = unistd::getpid();
                 let pgid = job.pgid.unwrap_or(current_pid);
                 unistd::setpgid(current_pid, pgid).expect("setpgid");
-                unistd::tcsetpgrp(STDIN_FILENO, pgid).expect("tcsetpgrp");
+                if self.interactive {
+                    unistd::tcsetpgrp(STDIN_FILENO, pgid).expect("tcsetpgrp");
+                }
 
                 use signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
                 let sigdfl = SigAction::new(SigHandler::SigDfl, SaFlags::empty(), SigSet::empty());
@@ -692,7 +1622,8 @@ impl Shell {
                 match unistd::execve(&exe, args, &envs) {
                     Ok(_) => unreachable!(),
                     Err(Errno::ENOENT) => {
-                        std::process::exit(127);
+                        let status = self.command_not_found(args);
+                        std::process::exit(status);
                     }
                     Err(_) => {
                         std::process::exit(126);
@@ -702,36 +1633,151 @@ impl Shell {
 
             Ok(unistd::ForkResult::Parent { child, .. }) => {
                 let pgid = job.pgid.unwrap_or(child);
-                match unistd::setpgid(child, pgid) {
-                    Ok(()) => {}
+                let result = match unistd::setpgid(child, pgid) {
+                    Ok(()) => Ok(()),
                     Err(Errno::EACCES) => {
                         // ignore this error
+                        Ok(())
                     }
-                    Err(err) => {
-                        panic!("setpgid: {err}");
-                    }
-                }
+                    Err(err) => Err(ShellError::Syscall("setpgid", err)),
+                };
 
                 let process = Process {
                     pid: child,
+                    position: job.statuses.len(),
                     stopped: false,
                     status: None,
                 };
 
                 job.pgid = Some(pgid);
                 job.members.insert(child, process);
+                job.statuses.push(None);
+                result
             }
 
-            Err(_) => panic!("fork failed"),
+            Err(err) => Err(ShellError::Syscall("fork", err)),
         }
     }
 
-    pub fn list_commands(&self) -> Vec<String> {
-        self.env
-            .commands
-            .keys()
-            .filter_map(|os| Some(std::str::from_utf8(os.as_bytes()).ok()?.to_owned()))
-            .collect()
+    // runs $COMMAND_NOT_FOUND with the command line if it is set, and reports the error
+    // unless it exits with 127. Returns the exit status of the command.
+    fn command_not_found(&self, args: &[CString]) -> i32 {
+        let name = String::from_utf8_lossy(args[0].to_bytes());
+        if name.contains(std::path::MAIN_SEPARATOR) {
+            eprintln!("{name}: no such file or directory");
+            return 127;
+        }
+
+        if let Some(handler) = self.env.get_var("COMMAND_NOT_FOUND") {
+            let handler = handler.to_string_lossy();
+            let mut words = handler.split_whitespace();
+            if let Some(program) = words.next() {
+                let status = std::process::Command::new(program)
+                    .args(words)
+                    .args(args.iter().map(|arg| str_c_to_os(arg)))
+                    .env_clear()
+                    .envs(&self.env.env_vars)
+                    .status();
+                match status.map(|status| status.code()) {
+                    Ok(Some(127)) => {}
+                    Ok(Some(code)) => return code,
+                    Ok(None) => return 1,
+                    Err(err) => eprintln!("shell: {program}: {err}"),
+                }
+            }
+        }
+
+        eprintln!("{name}: command not found");
+        match self.env.similar_commands(&name).as_slice() {
+            [] => {}
+            [cmd] => eprintln!("did you mean {cmd}?"),
+            cmds => eprintln!("did you mean one of: {}?", cmds.join(", ")),
+        }
+        127
+    }
+
+    /// Returns the names usable as a command, with builtins and aliases described as such
+    pub fn list_commands(&self) -> Vec<Candidate> {
+        let to_string = |os: &OsStr| Some(std::str::from_utf8(os.as_bytes()).ok()?.to_owned());
+
+        let commands = self.env.commands.iter().filter_map(|(name, exe)| {
+            let mut cand = Candidate::new(to_string(name)?);
+            match exe {
+                Executable::Builtin(_) => cand.description = Some("builtin".to_owned()),
+                Executable::Plugin(_) => cand.description = Some("plugin".to_owned()),
+                Executable::External(_) => {}
+            }
+            Some(cand)
+        });
+
+        // aliases shadow the commands of the same name
+        let aliases = self.env.aliases.iter().filter_map(|(name, values)| {
+            let values: Vec<_> = values.iter().map(|v| v.to_string_lossy()).collect();
+            let mut cand = Candidate::new(to_string(name)?);
+            cand.description = Some(format!("alias for {}", values.join(" ")));
+            Some(cand)
+        });
+
+        let mut names: HashMap<String, Candidate> = HashMap::new();
+        for cand in commands.chain(aliases) {
+            names.insert(cand.text.clone(), cand);
+        }
+        names.into_values().collect()
+    }
+
+    /// Returns the specs referring to the jobs (`%N`, pgids and `%command`), described by their state
+    pub fn list_jobs(&self) -> Vec<Candidate> {
+        let mut specs = Vec::new();
+        for (pgid, job) in self.jobs.iter() {
+            let description = format!("{}  {}", job.state(), job.command);
+            let mut push = |text: String| {
+                specs.push(Candidate {
+                    text,
+                    description: Some(description.clone()),
+                })
+            };
+
+            push(format!("%{}", job.id));
+            push(pgid.to_string());
+
+            // a prefix shared with other jobs would be ambiguous
+            let name = job.command.split_whitespace().next().unwrap_or_default();
+            let unique = self
+                .jobs
+                .values()
+                .filter(|other| other.command.starts_with(name))
+                .count()
+                == 1;
+            if !name.is_empty() && unique {
+                push(format!("%{name}"));
+            }
+        }
+        specs
+    }
+
+    /// Refreshes the command cache if PATH directories have changed since the last scan
+    pub fn sync_commands(&mut self) {
+        self.env.sync_commands();
+    }
+
+    /// Returns the value of a variable computed at expansion time
+    fn special_variable(&self, name: &str) -> Option<String> {
+        let value = match name {
+            "?" => self.last_status.to_string(),
+            "LINENO" => self.line_number.to_string(),
+            "SECONDS" => self.start_time.elapsed().as_secs().to_string(),
+            "RANDOM" => {
+                // xorshift64*
+                let mut x = self.random_state.get();
+                x ^= x >> 12;
+                x ^= x << 25;
+                x ^= x >> 27;
+                self.random_state.set(x);
+                ((x.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 33) & 0x7fff).to_string()
+            }
+            _ => return None,
+        };
+        Some(value)
     }
 
     pub fn update_variables(&mut self) {
@@ -745,12 +1791,87 @@ impl Shell {
     }
 }
 
-#[derive(Clone)]
-pub struct Env {
+/// A job which has completed while it was not waited for
+pub struct FinishedJob {
+    pub id: usize,
+    pub command: String,
+    pub status: i32,
+    /// the description of the signal which has killed it, like "Terminated"
+    pub killed_by: Option<&'static str>,
+    pub elapsed: std::time::Duration,
+}
+
+fn signal_description(signal: signal::Signal) -> &'static str {
+    use signal::Signal::*;
+    match signal {
+        SIGHUP => "Hangup",
+        SIGINT => "Interrupt",
+        SIGQUIT => "Quit",
+        SIGILL => "Illegal instruction",
+        SIGABRT => "Aborted",
+        SIGBUS => "Bus error",
+        SIGFPE => "Floating point exception",
+        SIGKILL => "Killed",
+        SIGSEGV => "Segmentation fault",
+        SIGPIPE => "Broken pipe",
+        SIGALRM => "Alarm clock",
+        SIGTERM => "Terminated",
+        signal => signal.as_str(),
+    }
+}
+
+// the pipe written by the signal handler to wake up the line editor
+static SIGCHLD_PIPE_READ: AtomicI32 = AtomicI32::new(-1);
+static SIGCHLD_PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn sigchld_handler(_: i32) {
+    let fd = SIGCHLD_PIPE_WRITE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        let _ = unistd::write(fd, &[0]);
+    }
+}
+
+const MAX_HUNG_UP_JOBS: usize = 64;
+
+// the pgids of the jobs to hang up when the terminal is closed, or zero for unused slots
+#[allow(clippy::declare_interior_mutable_const)]
+const NO_JOB: AtomicI32 = AtomicI32::new(0);
+static JOB_PGIDS: [AtomicI32; MAX_HUNG_UP_JOBS] = [NO_JOB; MAX_HUNG_UP_JOBS];
+
+// sends SIGHUP to the job, continuing it to receive the signal if it is stopped
+fn hang_up(pgid: Pgid) {
+    let _ = signal::killpg(pgid, signal::Signal::SIGHUP);
+    let _ = signal::killpg(pgid, signal::Signal::SIGCONT);
+}
+
+extern "C" fn sighup_handler(_: i32) {
+    for slot in JOB_PGIDS.iter() {
+        let pgid = slot.load(Ordering::SeqCst);
+        if pgid > 0 {
+            hang_up(Pid::from_raw(pgid));
+        }
+    }
+
+    // the shell itself is terminated as it would be without this handler
+    let sigdfl = signal::SigAction::new(
+        signal::SigHandler::SigDfl,
+        signal::SaFlags::empty(),
+        signal::SigSet::empty(),
+    );
+    unsafe {
+        let _ = signal::sigaction(signal::Signal::SIGHUP, &sigdfl);
+    }
+    let _ = signal::raise(signal::Signal::SIGHUP);
+}
+
+pub(crate) struct Env {
     aliases: HashMap<OsString, Vec<OsString>>,
     commands: HashMap<OsString, Executable>,
     env_vars: HashMap<OsString, OsString>,
     shell_vars: HashMap<OsString, OsString>,
+    /// commands provided by plugins, which take precedence over the ones in PATH
+    plugin_commands: HashMap<OsString, PathBuf>,
+    path_watcher: Option<PathWatcher>,
 }
 
 impl Env {
@@ -760,23 +1881,37 @@ impl Env {
             commands: HashMap::new(),
             env_vars: std::env::vars_os().collect(),
             shell_vars: HashMap::new(),
+            plugin_commands: HashMap::new(),
+            path_watcher: PathWatcher::new(),
         };
 
         env.update_commands();
         env
     }
 
+    /// Rescans PATH only if the watcher has noticed changes in its directories
+    pub fn sync_commands(&mut self) {
+        let changed = match &self.path_watcher {
+            Some(watcher) => watcher.has_changed(),
+            None => false,
+        };
+
+        if changed {
+            self.update_commands();
+        }
+    }
+
     pub fn update_commands(&mut self) {
         self.commands.clear();
 
-        let path_value = match self.get_env("PATH") {
-            Some(val) => val.to_owned(),
-            None => {
-                return;
-            }
-        };
+        let path_value = self.get_env("PATH").map(|val| val.to_owned());
+        if let Some(watcher) = &mut self.path_watcher {
+            let dirs = path_value.iter().flat_map(std::env::split_paths);
+            watcher.watch(dirs);
+        }
 
-        for path in std::env::split_paths(&path_value) {
+        // builtins are registered below even if PATH is unset
+        for path in path_value.iter().flat_map(std::env::split_paths) {
             let entries = match std::fs::read_dir(&path) {
                 Ok(ents) => ents,
                 Err(_err) => {
@@ -807,6 +1942,11 @@ impl Env {
             }
         }
 
+        for (name, plugin) in &self.plugin_commands {
+            let exe = Executable::Plugin(plugin.clone());
+            self.commands.insert(name.clone(), exe);
+        }
+
         // register builtin commands
         {
             macro_rules! builtin_bind {
@@ -819,18 +1959,99 @@ impl Env {
             use builtins::*;
             builtin_bind!("args", builtin_args);
             builtin_bind!("exit", builtin_exit);
+            builtin_bind!("builtin", builtin_builtin);
             builtin_bind!("cd", builtin_cd);
+            builtin_bind!("z", builtin_z);
             builtin_bind!("jobs", builtin_jobs);
             builtin_bind!("fg", builtin_fg);
+            builtin_bind!("disown", builtin_disown);
             builtin_bind!(">>", builtin_append);
             builtin_bind!(">", builtin_overwrite);
+            builtin_bind!("<", builtin_input);
             builtin_bind!("alias", builtin_alias);
+            builtin_bind!("abbr", builtin_abbr);
+            builtin_bind!("hook", builtin_hook);
+            builtin_bind!("reload", builtin_reload);
             builtin_bind!("var", builtin_var);
+            builtin_bind!("let", builtin_let);
+            builtin_bind!("math", builtin_math);
             builtin_bind!("evar", builtin_evar);
+            builtin_bind!("export", builtin_export);
             builtin_bind!("unset", builtin_unset);
+            builtin_bind!("set", builtin_set);
+            builtin_bind!("rehash", builtin_rehash);
+            builtin_bind!("hash", builtin_hash);
+            builtin_bind!("bindkey", builtin_bindkey);
+            builtin_bind!("complete", builtin_complete);
+            builtin_bind!("reset-terminal", builtin_reset_terminal);
+            builtin_bind!("parse", builtin_parse);
         }
     }
 
+    /// Looks up `name` in PATH again and caches the result
+    fn resolve_command(&mut self, name: &OsStr) -> Option<Executable> {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        if let Some(Executable::Builtin(f)) = self.commands.get(name) {
+            return Some(Executable::Builtin(*f));
+        }
+        if let Some(plugin) = self.plugin_commands.get(name) {
+            return Some(Executable::Plugin(plugin.clone()));
+        }
+
+        let path_value = self.get_env("PATH")?.to_owned();
+        let found = std::env::split_paths(&path_value)
+            .map(|dir| dir.join(name))
+            .find(|path| {
+                path.metadata()
+                    .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+            });
+
+        match found {
+            Some(path) => {
+                let exe = Executable::External(path);
+                self.commands.insert(name.to_owned(), exe.clone());
+                Some(exe)
+            }
+            None => {
+                self.commands.remove(name);
+                None
+            }
+        }
+    }
+
+    /// Returns command names close to `name`, the closest one first
+    pub fn similar_commands(&self, name: &str) -> Vec<String> {
+        const MAX_SUGGESTIONS: usize = 3;
+        let threshold = (name.chars().count() / 3).max(1);
+
+        let mut similar: Vec<(usize, String)> = self
+            .commands
+            .keys()
+            .chain(self.aliases.keys())
+            .filter_map(|cmd| cmd.to_str())
+            .map(|cmd| (crate::utils::edit_distance(name, cmd), cmd.to_owned()))
+            .filter(|(dist, _)| *dist <= threshold)
+            .collect();
+
+        similar.sort_unstable();
+        similar.dedup();
+        similar
+            .into_iter()
+            .take(MAX_SUGGESTIONS)
+            .map(|(_, cmd)| cmd)
+            .collect()
+    }
+
+    /// Returns the value of a shell variable, or an environment variable of the same name
+    pub fn get_var(&self, name: &str) -> Option<&'_ OsStr> {
+        let name = str_r_to_os(name);
+        self.shell_vars
+            .get(name)
+            .or_else(|| self.env_vars.get(name))
+            .map(|val| val.as_os_str())
+    }
+
     pub fn get_env<'a>(&self, name: &'a str) -> Option<&'_ OsStr> {
         self.env_vars
             .get(str_r_to_os(name))
@@ -841,3 +2062,191 @@ impl Env {
         self.env_vars.insert(str_r_to_os(name).to_owned(), value);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn substitution_words() {
+        let mut buf = b"echo ".to_vec();
+        push_words(&mut buf, b"a  b\n");
+        push_words(&mut buf, b"\tc\td");
+        assert_eq!(buf, b"echo a b c d");
+    }
+
+    #[test]
+    fn glob_sorted() {
+        let dir = std::env::temp_dir().join(format!("glob-sorted-{}", std::process::id()));
+        for name in ["b.rs", "a.rs", "C.rs", "a.txt"] {
+            std::fs::create_dir_all(dir.join("sub")).unwrap();
+            std::fs::write(dir.join("sub").join(name), "").unwrap();
+        }
+
+        let pattern = format!("{}/*/*.rs", dir.display());
+        let expected = ["C.rs", "a.rs", "b.rs"]
+            .map(|name| OsString::from(format!("{}/sub/{name}", dir.display())));
+        assert_eq!(expand_pattern(pattern.as_bytes(), false), expected);
+        assert_eq!(
+            expand_pattern(b"no-such-dir/*", false),
+            [OsString::from("no-such-dir/*")]
+        );
+        assert_eq!(
+            expand_pattern(b"no-such-dir/*", true),
+            Vec::<OsString>::new()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn eval_capture() {
+        let mut shell = Shell::new_noninteractive();
+
+        let (status, output, error) = shell.eval_str_capture(r#"var X = "a  *"; args "$X" @"$X""#);
+        assert_eq!(status, 0);
+        assert_eq!(output, b"1: \"a  *\"\n2: \"a  *\"\n");
+        assert_eq!(error, b"");
+
+        let (status, output, _) = shell.eval_str_capture(r#"args "$(printf 'x  y\n')" | cat"#);
+        assert_eq!(status, 0);
+        assert_eq!(output, b"1: \"x  y\"\n");
+
+        let (status, output, error) = shell.eval_str_capture("false || args $?; exit 3 4");
+        assert_eq!(status, 1);
+        assert_eq!(output, b"1: \"1\"\n");
+        assert_eq!(error, b"exit: too many arguments\n");
+    }
+
+    #[test]
+    fn pipefail_and_nullglob() {
+        let mut shell = Shell::new_noninteractive();
+
+        // the status of the last command unless `pipefail` is set
+        let (status, _, _) = shell.eval_str_capture("false | true");
+        assert_eq!(status, 0);
+        let (status, _, _) = shell.eval_str_capture("set -o pipefail; false | true");
+        assert_eq!(status, 1);
+        let (status, _, _) = shell.eval_str_capture("true | true");
+        assert_eq!(status, 0);
+
+        // an unmatched pattern is kept as is unless `nullglob` is set
+        let (_, output, _) = shell.eval_str_capture("args no-such-dir/*");
+        assert_eq!(output, b"1: \"no-such-dir/*\"\n");
+        let (status, output, _) = shell.eval_str_capture("set -o nullglob; args a no-such-dir/*");
+        assert_eq!(status, 0);
+        assert_eq!(output, b"1: \"a\"\n");
+    }
+
+    #[test]
+    fn builtin_substitution_keeps_shell_state() {
+        let mut shell = Shell::new_noninteractive();
+        let (status, _, _) = shell.eval_str_capture("args $(builtin exit 7)");
+        assert_eq!(status, 0);
+        assert_eq!(shell.exit_status(), None);
+        // `builtin exit` is forked with the rest of the list, not to exit or hang up the jobs
+        let (status, output, _) =
+            shell.eval_str_capture("var Z = a; args x$(var Z = b; builtin exit) $Z");
+        assert_eq!(status, 0);
+        assert_eq!(output, b"1: \"x\"\n2: \"a\"\n");
+        assert_eq!(shell.exit_status(), None);
+
+        // `$?` is still the status of the command before the substitution
+        let (_, output, _) = shell.eval_str_capture("cd /nonexistent; args x$(args) $?");
+        assert_eq!(output, b"1: \"x\"\n2: \"1\"\n");
+
+        let mut shell = Shell::new_noninteractive();
+        let (_, output, _) = shell.eval_str_capture("set -e; args x$(cd /nonexistent) after");
+        assert_eq!(output, b"1: \"x\"\n2: \"after\"\n");
+        assert_eq!(shell.exit_status(), None);
+    }
+
+    #[test]
+    fn prompt_substitution_is_forked() {
+        let mut shell = Shell::new_noninteractive();
+        shell.eval_str_capture("var X = a");
+        let timeout = std::time::Duration::from_secs(1);
+        assert_eq!(shell.expand_prompt("$(var X = b)[$X]", timeout), "[a]");
+        let (_, output, _) = shell.eval_str_capture("args $X");
+        assert_eq!(output, b"1: \"a\"\n");
+    }
+
+    #[test]
+    fn glob_words() {
+        let dir = std::env::temp_dir().join(format!("glob-words-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("a b.txt"), "").unwrap();
+        let dir = dir.to_str().unwrap();
+
+        // a matched path is a word even if it contains a space
+        let mut shell = Shell::new_noninteractive();
+        let (status, output, _) = shell.eval_str_capture(&format!("args {dir}/*.txt"));
+        assert_eq!(status, 0);
+        assert_eq!(output, format!("1: \"{dir}/a b.txt\"\n").as_bytes());
+        let (_, output, _) = shell.eval_str_capture(&format!("args @$(printf '{dir}/*.txt')"));
+        assert_eq!(output, format!("1: \"{dir}/a b.txt\"\n").as_bytes());
+
+        // the fields of the output are not expanded as `~`
+        let (_, output, _) = shell.eval_str_capture("args @$(printf '~ ~/x')");
+        assert_eq!(output, b"1: \"~\"\n2: \"~/x\"\n");
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn hash_builtin() {
+        use std::os::unix::fs::PermissionsExt as _;
+
+        let dir = std::env::temp_dir().join(format!("hash-builtin-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for (name, mode) in [("runnable", 0o755), ("plain", 0o644)] {
+            let path = dir.join(name);
+            std::fs::write(&path, "#!/bin/sh\necho ran\n").unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+        }
+
+        let mut shell = Shell::new_noninteractive();
+        let path = format!(
+            "{}:{}",
+            dir.display(),
+            shell.var("PATH").unwrap_or_default()
+        );
+        shell.set_var("DIR", path);
+        let (status, _, _) = shell.eval_str_capture("evar PATH = $DIR");
+        assert_eq!(status, 0);
+
+        // files without the execute permission are not commands
+        let (status, _, error) = shell.eval_str_capture("hash plain");
+        assert_eq!(status, 1);
+        assert_eq!(error, b"hash: plain: not found\n");
+
+        // the cleared commands are looked up again when they are run
+        let (status, output, _) = shell.eval_str_capture("hash -r; runnable");
+        assert_eq!(status, 0);
+        assert_eq!(output, b"ran\n");
+        let (_, output, _) = shell.eval_str_capture("hash -t runnable");
+        assert_eq!(
+            output,
+            format!("{}\n", dir.join("runnable").display()).into_bytes()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prompt_expansion() {
+        use std::time::{Duration, Instant};
+
+        let mut shell = Shell::new_noninteractive();
+        shell.set_var("X", r"a\b".to_owned());
+        let template = r"\w $X:$(printf 'x  y\n') \$ $";
+        let expanded = shell.expand_prompt(template, Duration::from_secs(5));
+        assert_eq!(expanded, r"\w a\\b:x  y $ $");
+
+        // the slow substitution is killed
+        let started = Instant::now();
+        let expanded = shell.expand_prompt("[$(sleep 5)]", Duration::from_millis(100));
+        assert_eq!(expanded, "[]");
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
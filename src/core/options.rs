@@ -0,0 +1,90 @@
+/// Shell options which can be toggled by the `set` builtin
+#[derive(Debug, Default)]
+pub struct Options {
+    /// exit as soon as a pipeline fails
+    pub errexit: bool,
+    /// print each command before executing it
+    pub xtrace: bool,
+    /// the status of a pipeline is the last non-zero status of its commands
+    pub pipefail: bool,
+    /// patterns matching nothing are expanded to nothing
+    pub nullglob: bool,
+    /// `>` doesn't overwrite existing files
+    pub noclobber: bool,
+    /// the line editor closes quotes and brackets as they are typed
+    pub autopair: bool,
+    /// files are completed in the order of modification time, the newest first
+    pub mtimesort: bool,
+    /// the line editor handles clicks and the mouse wheel
+    pub mouse: bool,
+    /// the terminal title shows the current directory or the running command
+    pub title: bool,
+    /// a directory given as a command is changed to
+    pub autocd: bool,
+    /// the environment of the directory is loaded by direnv before each prompt
+    pub direnv: bool,
+}
+
+impl Options {
+    pub const NAMES: &'static [&'static str] = &[
+        "errexit",
+        "xtrace",
+        "pipefail",
+        "nullglob",
+        "noclobber",
+        "autopair",
+        "mtimesort",
+        "mouse",
+        "title",
+        "autocd",
+        "direnv",
+    ];
+
+    /// Returns the value of the option named `name`
+    pub fn get(&self, name: &str) -> Option<bool> {
+        let opt = match name {
+            "errexit" => self.errexit,
+            "xtrace" => self.xtrace,
+            "pipefail" => self.pipefail,
+            "nullglob" => self.nullglob,
+            "noclobber" => self.noclobber,
+            "autopair" => self.autopair,
+            "mtimesort" => self.mtimesort,
+            "mouse" => self.mouse,
+            "title" => self.title,
+            "autocd" => self.autocd,
+            "direnv" => self.direnv,
+            _ => return None,
+        };
+        Some(opt)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        let opt = match name {
+            "errexit" => &mut self.errexit,
+            "xtrace" => &mut self.xtrace,
+            "pipefail" => &mut self.pipefail,
+            "nullglob" => &mut self.nullglob,
+            "noclobber" => &mut self.noclobber,
+            "autopair" => &mut self.autopair,
+            "mtimesort" => &mut self.mtimesort,
+            "mouse" => &mut self.mouse,
+            "title" => &mut self.title,
+            "autocd" => &mut self.autocd,
+            "direnv" => &mut self.direnv,
+            _ => return None,
+        };
+        Some(opt)
+    }
+
+    /// Returns the long name of a single letter option like `-e`
+    pub fn from_letter(letter: char) -> Option<&'static str> {
+        let name = match letter {
+            'e' => "errexit",
+            'x' => "xtrace",
+            'C' => "noclobber",
+            _ => return None,
+        };
+        Some(name)
+    }
+}
@@ -0,0 +1,65 @@
+use nix::sys::inotify::{AddWatchFlags, InitFlags, Inotify, WatchDescriptor};
+use nix::unistd;
+use std::os::unix::io::AsRawFd as _;
+use std::path::Path;
+
+/// `PathWatcher` watches the directories listed in PATH
+/// so that the command cache can be invalidated only when something has changed.
+pub struct PathWatcher {
+    inotify: Inotify,
+    watches: Vec<WatchDescriptor>,
+}
+
+impl PathWatcher {
+    /// Creates a new watcher, or returns `None` if inotify is not available
+    pub fn new() -> Option<Self> {
+        let inotify = Inotify::init(InitFlags::IN_NONBLOCK | InitFlags::IN_CLOEXEC).ok()?;
+        Some(Self {
+            inotify,
+            watches: Vec::new(),
+        })
+    }
+
+    /// Replaces the set of watched directories with `dirs`
+    pub fn watch<P: AsRef<Path>>(&mut self, dirs: impl Iterator<Item = P>) {
+        for wd in self.watches.drain(..) {
+            let _ = self.inotify.rm_watch(wd);
+        }
+
+        let flags = AddWatchFlags::IN_CREATE
+            | AddWatchFlags::IN_DELETE
+            | AddWatchFlags::IN_MOVED_FROM
+            | AddWatchFlags::IN_MOVED_TO
+            | AddWatchFlags::IN_ATTRIB
+            | AddWatchFlags::IN_DELETE_SELF
+            | AddWatchFlags::IN_MOVE_SELF;
+
+        for dir in dirs {
+            // directories which don't exist (yet) are just ignored
+            if let Ok(wd) = self.inotify.add_watch(dir.as_ref(), flags) {
+                self.watches.push(wd);
+            }
+        }
+
+        // discard events caused by the previous set of directories
+        let _ = self.has_changed();
+    }
+
+    /// Consumes pending events and returns whether any of the directories has changed
+    pub fn has_changed(&self) -> bool {
+        let mut changed = false;
+        while let Ok(events) = self.inotify.read_events() {
+            if events.is_empty() {
+                break;
+            }
+            changed = true;
+        }
+        changed
+    }
+}
+
+impl Drop for PathWatcher {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.inotify.as_raw_fd());
+    }
+}
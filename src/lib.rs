@@ -0,0 +1,34 @@
+//! An interactive shell, which can also be embedded to evaluate commands without a terminal
+//! through `core::Shell::eval_str_capture`
+
+pub mod completion;
+pub mod config;
+pub mod core;
+pub mod line_editor;
+pub mod plugin;
+pub mod prompt;
+pub mod terminal_size;
+mod toml;
+mod utils;
+
+// TODO: consider being XDG complient
+pub fn application_dir() -> Option<std::path::PathBuf> {
+    let home = std::env::var_os("HOME")?;
+    let mut p = std::path::PathBuf::from(home);
+    p.push(".myshell");
+    Some(p)
+}
+
+/// Returns `$XDG_CONFIG_HOME/myshell`, or `~/.config/myshell` if the variable is not set
+pub fn config_dir() -> Option<std::path::PathBuf> {
+    let mut p = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => std::path::PathBuf::from(dir),
+        _ => {
+            let mut home = std::path::PathBuf::from(std::env::var_os("HOME")?);
+            home.push(".config");
+            home
+        }
+    };
+    p.push("myshell");
+    Some(p)
+}
@@ -0,0 +1,106 @@
+//! The system clipboard behind the `"+` register
+
+use super::input::query_terminal;
+
+// tried in order; the ones for the display server not running fail immediately
+const PASTE_COMMANDS: &[&[&str]] = &[
+    &["wl-paste", "--no-newline"],
+    &["xclip", "-o", "-selection", "clipboard"],
+    &["xsel", "--output", "--clipboard"],
+];
+
+/// Reads the clipboard with a helper command, or asks the terminal for it with OSC 52.
+/// Returns the text and the keys typed meanwhile.
+pub fn paste() -> (Option<String>, Vec<u8>) {
+    for args in PASTE_COMMANDS {
+        let mut command = std::process::Command::new(args[0]);
+        command.args(&args[1..]);
+        match crate::completion::run_helper(&mut command) {
+            Some(output) if output.status.success() => {
+                return (String::from_utf8(output.stdout).ok(), Vec::new());
+            }
+            _ => {}
+        }
+    }
+    query_terminal("\x1b]52;c;?\x07", find_osc52_response)
+}
+
+/// Sets the clipboard with OSC 52, which works over ssh as well
+pub fn copy(text: &str) {
+    print!("\x1b]52;c;{}\x07", base64_encode(text.as_bytes()));
+}
+
+// finds "ESC ] 52 ; <selection> ; <base64> (BEL | ESC \)"
+fn find_osc52_response(input: &[u8]) -> Option<(String, (usize, usize))> {
+    (0..input.len()).find_map(|start| {
+        let rest = input[start..].strip_prefix(b"\x1b]52;")?;
+        let data_begin = rest.iter().position(|b| *b == b';')? + 1;
+        let len = rest.iter().position(|b| *b == b'\x07' || *b == b'\x1b')?;
+        let terminator = if rest[len] == b'\x07' { 1 } else { 2 };
+        let data = base64_decode(rest.get(data_begin..len)?)?;
+        let text = String::from_utf8(data).ok()?;
+        Some((text, (start, start + 5 + len + terminator)))
+    })
+}
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0_u32, |bits, (i, b)| bits | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64_CHARS[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+fn base64_decode(encoded: &[u8]) -> Option<Vec<u8>> {
+    let mut data = Vec::new();
+    let (mut bits, mut nbits) = (0_u32, 0);
+    for b in encoded.iter().take_while(|b| **b != b'=') {
+        let value = BASE64_CHARS.iter().position(|c| c == b)?;
+        bits = bits << 6 | value as u32;
+        nbits += 6;
+        if nbits >= 8 {
+            nbits -= 8;
+            data.push((bits >> nbits) as u8);
+        }
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn osc52() {
+        for text in ["", "f", "fo", "foo", "foob", "echo 'あ'\n"] {
+            let encoded = base64_encode(text.as_bytes());
+            assert_eq!(encoded.len() % 4, 0);
+            assert_eq!(base64_decode(encoded.as_bytes()).unwrap(), text.as_bytes());
+        }
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+
+        let input = b"ab\x1b]52;c;Zm9vYg==\x1b\\cd";
+        assert_eq!(
+            find_osc52_response(input),
+            Some(("foob".to_owned(), (2, 19)))
+        );
+        let input = b"\x1b]52;c;Zm9v\x07";
+        assert_eq!(
+            find_osc52_response(input),
+            Some(("foo".to_owned(), (0, 12)))
+        );
+        assert_eq!(find_osc52_response(b"\x1b]52;c;Zm9v"), None);
+    }
+}
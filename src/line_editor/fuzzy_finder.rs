@@ -0,0 +1,103 @@
+//! Widgets which pick a history entry or a file with an external fuzzy finder like fzf
+
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The finder used if `FUZZY_FINDER` is not set
+pub const DEFAULT_FINDER: &str = "fzf";
+
+// the files listed are limited not to wait too long in a large directory
+const MAX_FILES: usize = 100_000;
+
+/// Runs `finder`, which may have arguments separated by spaces, with `items` given on its stdin
+/// and `query` as the initial query. Returns the selected item.
+/// The terminal is expected to be in the cooked mode as the finder draws on it by itself.
+pub fn select(finder: &str, items: &[String], query: &str) -> Option<String> {
+    let mut args = finder.split_whitespace();
+    let mut command = Command::new(args.next()?);
+    command.args(args);
+    if !query.is_empty() {
+        command.args(["--query", query]);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .ok()?;
+
+    // the finder may quit before reading all of them
+    let mut stdin = child.stdin.take()?;
+    let input = items.join("\n");
+    let writer = std::thread::spawn(move || {
+        let _ = stdin.write_all(input.as_bytes());
+    });
+    let output = child.wait_with_output().ok()?;
+    let _ = writer.join();
+
+    if !output.status.success() {
+        return None;
+    }
+    let selected = String::from_utf8(output.stdout).ok()?;
+    let selected = selected.trim_end_matches('\n');
+    (!selected.is_empty()).then(|| selected.to_owned())
+}
+
+/// Lists the files under `dir` recursively, skipping hidden ones. Directories end with a slash.
+pub fn list_files(dir: &Path) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut dirs: Vec<PathBuf> = vec![PathBuf::new()];
+    while let Some(rel) = dirs.pop() {
+        let Ok(entries) = std::fs::read_dir(dir.join(&rel)) else {
+            continue;
+        };
+        let mut entries: Vec<_> = entries
+            .filter_map(|ent| {
+                let ent = ent.ok()?;
+                let name = ent.file_name().into_string().ok()?;
+                let is_dir = ent.file_type().ok()?.is_dir();
+                (!name.starts_with('.')).then_some((name, is_dir))
+            })
+            .collect();
+        entries.sort();
+
+        for (name, is_dir) in entries {
+            if files.len() >= MAX_FILES {
+                return files;
+            }
+            let path = rel.join(&name);
+            if is_dir {
+                files.push(format!("{}/", path.display()));
+                dirs.push(path);
+            } else {
+                files.push(path.display().to_string());
+            }
+        }
+    }
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_finder() {
+        let dir = std::env::temp_dir().join(format!("fuzzy-finder-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub/.hidden")).unwrap();
+        std::fs::write(dir.join("a"), "").unwrap();
+        std::fs::write(dir.join("sub/b"), "").unwrap();
+        std::fs::write(dir.join(".c"), "").unwrap();
+
+        let files = list_files(&dir);
+        assert_eq!(files, ["a", "sub/", "sub/b"]);
+
+        // `head -n1` picks the first one, ignoring the query
+        let items = ["foo".to_owned(), "bar".to_owned()];
+        assert_eq!(select("head -n1", &items, ""), Some("foo".to_owned()));
+        assert_eq!(select("false", &items, ""), None);
+        assert_eq!(select("no-such-finder", &items, ""), None);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,443 @@
+use std::collections::BTreeMap;
+use std::io::Write as _;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::line::{graphemes, Line};
+
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub line: Line,
+    /// unix time when the command was started
+    pub started_at: Option<u64>,
+    /// how long the command took in seconds
+    pub duration: Option<u64>,
+}
+
+impl HistoryEntry {
+    pub fn new(line: Line) -> Self {
+        Self {
+            line,
+            started_at: Some(now()),
+            duration: None,
+        }
+    }
+
+    // entries are saved in the extended format of zsh, i.e. ": <start>:<elapsed>;<command>"
+    fn parse(s: &str) -> Self {
+        let parse_extended = || {
+            let (meta, command) = s.strip_prefix(": ")?.split_once(';')?;
+            let (started_at, duration) = meta.split_once(':')?;
+            let started_at = started_at.parse().ok()?;
+            let duration = duration.parse().ok()?;
+            Some(Self {
+                line: Line::from(command),
+                started_at: Some(started_at),
+                duration: Some(duration),
+            })
+        };
+
+        parse_extended().unwrap_or_else(|| Self {
+            line: Line::from(s),
+            started_at: None,
+            duration: None,
+        })
+    }
+
+    /// Returns a short description like "5m ago, took 2s"
+    pub fn describe(&self) -> Option<String> {
+        let started_at = self.started_at?;
+        let ago = format_duration(now().saturating_sub(started_at));
+        match self.duration {
+            Some(duration) => Some(format!("{ago} ago, took {}", format_duration(duration))),
+            None => Some(format!("{ago} ago")),
+        }
+    }
+}
+
+impl std::fmt::Display for HistoryEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.started_at {
+            Some(started_at) => {
+                let duration = self.duration.unwrap_or(0);
+                write!(f, ": {started_at}:{duration};{}", self.line)
+            }
+            None => write!(f, "{}", self.line),
+        }
+    }
+}
+
+/// `PrefixIndex` finds the most recent history entry starting with a given prefix
+#[derive(Debug, Default)]
+pub struct PrefixIndex {
+    // command -> index of its latest entry
+    latest: BTreeMap<String, usize>,
+}
+
+impl PrefixIndex {
+    pub fn new(history: &[HistoryEntry]) -> Self {
+        let mut index = Self::default();
+        for (i, entry) in history.iter().enumerate() {
+            index.insert(entry.line.to_string(), i);
+        }
+        index
+    }
+
+    pub fn insert(&mut self, command: String, idx: usize) {
+        self.latest.insert(command, idx);
+    }
+
+    /// Returns the most recent command which is longer than `prefix` and starts with it
+    pub fn find(&self, prefix: &str) -> Option<&str> {
+        self.latest
+            .range::<str, _>((Bound::Included(prefix), Bound::Unbounded))
+            .take_while(|(command, _)| command.starts_with(prefix))
+            .filter(|(command, _)| command.len() > prefix.len())
+            .max_by_key(|(_, idx)| **idx)
+            .map(|(command, _)| command.as_str())
+    }
+}
+
+/// Finds the next entry containing `query` from `start`, wrapping around at both ends.
+/// `start` may be `history.len()`, which stands for the line being edited.
+/// Returns the index of the entry and the char position of the match.
+pub fn search_history(
+    history: &[HistoryEntry],
+    query: &str,
+    start: usize,
+    backward: bool,
+    include_start: bool,
+) -> Option<(usize, usize)> {
+    // the line being edited takes the extra slot of the ring
+    let slots = history.len() + 1;
+    let start = start.min(history.len());
+    let first = usize::from(!include_start);
+
+    (first..slots)
+        .map(|k| {
+            if backward {
+                (start + slots - k) % slots
+            } else {
+                (start + k) % slots
+            }
+        })
+        .filter(|&i| i < history.len())
+        .find_map(|i| {
+            let line = history[i].line.to_string();
+            let pos = line.find(query)?;
+            Some((i, graphemes(&line[..pos]).count()))
+        })
+}
+
+/// Returns (n, total) where the entry at `idx` is the n-th newest of `total` entries containing `query`
+pub fn match_position(history: &[HistoryEntry], query: &str, idx: usize) -> (usize, usize) {
+    let matched: Vec<usize> = history
+        .iter()
+        .enumerate()
+        .filter(|(_, entry)| entry.line.to_string().contains(query))
+        .map(|(i, _)| i)
+        .collect();
+    let nth = matched.iter().filter(|&&i| i >= idx).count();
+    (nth, matched.len())
+}
+
+fn format_duration(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{secs}s"),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// The number of the recent entries read at startup
+pub const RECENT_ENTRIES: usize = 1000;
+/// The number of the older entries read at once when they are needed
+pub const CHUNK_ENTRIES: usize = 1000;
+
+/// The entries saved in the history file, of which only the recent ones are read at startup.
+/// Older ones are read in chunks when they are needed, which shifts the indices of the entries.
+#[derive(Debug, Default)]
+pub struct History {
+    entries: Vec<HistoryEntry>,
+    path: Option<PathBuf>,
+    /// the size of the beginning of the file which is not read yet
+    unread: u64,
+}
+
+impl History {
+    /// Reads the most recent `count` entries from the file at `path`
+    pub fn open(path: PathBuf, count: usize) -> Self {
+        let unread = std::fs::metadata(&path).map_or(0, |meta| meta.len());
+        let mut history = Self {
+            entries: Vec::new(),
+            path: Some(path),
+            unread,
+        };
+        history.load_older(count);
+        history
+    }
+
+    /// Reads up to `count` entries older than the ones read, and returns how many are read
+    pub fn load_older(&mut self, count: usize) -> usize {
+        let Some(path) = &self.path else {
+            return 0;
+        };
+        while self.unread > 0 {
+            let Ok((lines, start)) = read_lines_before(path, self.unread, count) else {
+                return 0;
+            };
+            self.unread = start;
+            if !lines.is_empty() {
+                let older = lines.iter().map(|line| HistoryEntry::parse(line));
+                self.entries.splice(0..0, older);
+                return lines.len();
+            }
+        }
+        0
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        self.entries.push(entry);
+    }
+
+    /// Finds an entry as `search_history` does, reading older entries before wrapping around at
+    /// the oldest one read. Returns the number of the entries read as well.
+    pub fn search(
+        &mut self,
+        query: &str,
+        start: usize,
+        backward: bool,
+        include_start: bool,
+    ) -> (Option<(usize, usize)>, usize) {
+        let (mut start, mut read) = (start, 0);
+        loop {
+            let found = search_history(&self.entries, query, start, backward, include_start);
+            let wrapped = match found {
+                Some((i, _)) if i == start => !include_start,
+                Some((i, _)) if backward => i > start,
+                Some((i, _)) => i < start,
+                None => true,
+            };
+            if !wrapped {
+                return (found, read);
+            }
+            match self.load_older(CHUNK_ENTRIES) {
+                0 => return (found, read),
+                n => {
+                    start += n;
+                    read += n;
+                }
+            }
+        }
+    }
+
+    /// Writes the entries read over the end of the file, keeping the ones not read
+    pub fn save(&self) -> std::io::Result<()> {
+        use std::io::{Seek as _, SeekFrom};
+
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            // the lines read from the file are kept, only the rest is cut off below
+            .truncate(false)
+            .open(path)?;
+        file.set_len(self.unread)?;
+
+        let mut file = std::io::BufWriter::new(file);
+        file.seek(SeekFrom::Start(self.unread))?;
+        for entry in self.entries.iter() {
+            writeln!(file, "{}", entry)?;
+        }
+        file.flush()
+    }
+}
+
+impl std::ops::Deref for History {
+    type Target = [HistoryEntry];
+    fn deref(&self) -> &Self::Target {
+        &self.entries
+    }
+}
+
+impl std::ops::DerefMut for History {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.entries
+    }
+}
+
+// reads up to `count` lines which end before the offset `end` of the file, returning them with
+// the offset where the first of them starts
+fn read_lines_before(path: &Path, end: u64, count: usize) -> std::io::Result<(Vec<String>, u64)> {
+    use std::os::unix::fs::FileExt as _;
+    const BLOCK_SIZE: u64 = 64 * 1024;
+
+    let file = std::fs::File::open(path)?;
+    let mut start = end;
+    let mut buf = Vec::new();
+    // a line is known to be whole if a newline precedes it
+    while start > 0 && buf.iter().filter(|&&b| b == b'\n').count() <= count {
+        let size = BLOCK_SIZE.min(start);
+        start -= size;
+        let mut block = vec![0; size as usize];
+        file.read_exact_at(&mut block, start)?;
+        block.extend_from_slice(&buf);
+        buf = block;
+    }
+
+    let mut rest = &buf[..];
+    let mut offset = start;
+    if start > 0 {
+        // the first line may be cut
+        let newline = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .map_or(rest.len(), |i| i + 1);
+        rest = &rest[newline..];
+        offset += newline as u64;
+    }
+
+    // blank lines and invalid ones are skipped
+    let rest_start = offset;
+    let mut lines = Vec::new();
+    for line in rest.split_inclusive(|&b| b == b'\n') {
+        match std::str::from_utf8(line).map(str::trim) {
+            Ok(text) if !text.is_empty() => lines.push((offset, text)),
+            _ => {}
+        }
+        offset += line.len() as u64;
+    }
+    let lines = &lines[lines.len().saturating_sub(count)..];
+    let first = lines.first().map_or(rest_start, |(offset, _)| *offset);
+    let lines = lines.iter().map(|(_, text)| text.to_string()).collect();
+    Ok((lines, first))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn history_format() {
+        let entry = HistoryEntry::parse(": 1700000000:3;echo foo; echo bar");
+        assert_eq!(entry.line.to_string(), "echo foo; echo bar");
+        assert_eq!(entry.started_at, Some(1700000000));
+        assert_eq!(entry.duration, Some(3));
+        assert_eq!(entry.to_string(), ": 1700000000:3;echo foo; echo bar");
+
+        // entries saved by older versions have no metadata
+        let entry = HistoryEntry::parse("ls -l");
+        assert_eq!(entry.line.to_string(), "ls -l");
+        assert_eq!(entry.started_at, None);
+        assert_eq!(entry.to_string(), "ls -l");
+
+        let entry = HistoryEntry::parse(": not a timestamp;x");
+        assert_eq!(entry.line.to_string(), ": not a timestamp;x");
+        assert_eq!(entry.started_at, None);
+    }
+
+    #[test]
+    fn prefix_index() {
+        let history: Vec<_> = ["git status", "git commit", "ls", "git status"]
+            .iter()
+            .map(|s| HistoryEntry::parse(s))
+            .collect();
+        let mut index = PrefixIndex::new(&history);
+
+        assert_eq!(index.find("git"), Some("git status"));
+        assert_eq!(index.find("git c"), Some("git commit"));
+        assert_eq!(index.find("l"), Some("ls"));
+        assert_eq!(index.find("ls"), None);
+        assert_eq!(index.find("cargo"), None);
+
+        index.insert("git commit".to_owned(), 4);
+        assert_eq!(index.find("git"), Some("git commit"));
+    }
+
+    #[test]
+    fn incremental_search() {
+        let history: Vec<_> = ["make", "cargo build", "ls", "cargo test"]
+            .iter()
+            .map(|s| HistoryEntry::parse(s))
+            .collect();
+
+        assert_eq!(
+            search_history(&history, "cargo", 4, true, false),
+            Some((3, 0))
+        );
+        assert_eq!(
+            search_history(&history, "cargo", 3, true, false),
+            Some((1, 0))
+        );
+        assert_eq!(
+            search_history(&history, "cargo", 3, true, true),
+            Some((3, 0))
+        );
+        // wraps around at the oldest entry
+        assert_eq!(
+            search_history(&history, "cargo", 1, true, false),
+            Some((3, 0))
+        );
+        assert_eq!(
+            search_history(&history, "cargo", 1, false, false),
+            Some((3, 0))
+        );
+        assert_eq!(
+            search_history(&history, "cargo", 3, false, false),
+            Some((1, 0))
+        );
+        assert_eq!(
+            search_history(&history, "build", 4, false, false),
+            Some((1, 6))
+        );
+        assert_eq!(search_history(&history, "rustc", 4, true, false), None);
+
+        assert_eq!(match_position(&history, "cargo", 3), (1, 2));
+        assert_eq!(match_position(&history, "cargo", 1), (2, 2));
+    }
+
+    #[test]
+    fn lazy_history() {
+        let path = std::env::temp_dir().join(format!("lazy-history-{}", std::process::id()));
+        let lines: Vec<String> = (0..10).map(|i| format!("cmd{i}")).collect();
+        std::fs::write(&path, lines.join("\n") + "\n\n").unwrap();
+
+        let mut history = History::open(path.clone(), 3);
+        let commands = |history: &History| -> Vec<String> {
+            history.iter().map(|entry| entry.line.to_string()).collect()
+        };
+        assert_eq!(commands(&history), ["cmd7", "cmd8", "cmd9"]);
+        assert_eq!(history.load_older(4), 4);
+        assert_eq!(commands(&history), lines[3..]);
+
+        // the search reads older entries instead of wrapping around
+        assert_eq!(history.search("cmd1", 7, true, false), (Some((1, 0)), 3));
+        assert_eq!(history.load_older(4), 0);
+
+        history.push(HistoryEntry::parse("cmd10"));
+        history.save().unwrap();
+        let mut history = History::open(path.clone(), 100);
+        assert_eq!(history.len(), 11);
+        assert_eq!(history.load_older(1), 0);
+
+        // the entries not read are kept
+        history.save().unwrap();
+        let history = History::open(path.clone(), 2);
+        history.save().unwrap();
+        let saved = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(saved, lines.join("\n") + "\ncmd10\n");
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,405 @@
+use super::keymap::{Event, Key, ALT, CTRL, SHIFT};
+
+/// `InputParser` turns bytes read from the terminal into key events.
+/// Escape sequences and UTF-8 characters split across reads are kept until they are complete.
+#[derive(Debug, Default)]
+pub struct InputParser {
+    pending: Vec<u8>,
+}
+
+enum Parsed {
+    Event(Event, usize),
+    Ignored(usize),
+    Incomplete,
+}
+
+impl InputParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the events completed by `input`
+    pub fn feed(&mut self, input: &[u8]) -> Vec<Event> {
+        self.pending.extend_from_slice(input);
+
+        let mut events = Vec::new();
+        let mut pos = 0;
+        while pos < self.pending.len() {
+            match parse(&self.pending[pos..]) {
+                Parsed::Event(ev, len) => {
+                    events.push(ev);
+                    pos += len;
+                }
+                Parsed::Ignored(len) => pos += len,
+                Parsed::Incomplete => break,
+            }
+        }
+        self.pending.drain(..pos);
+        events
+    }
+
+    /// Returns whether an incomplete sequence is waiting for more input
+    pub fn is_pending(&self) -> bool {
+        !self.pending.is_empty()
+    }
+
+    /// Gives up waiting for the rest of the sequence, e.g. a single ESC is the Escape key
+    pub fn flush(&mut self) -> Vec<Event> {
+        let pending = std::mem::take(&mut self.pending);
+        match pending.split_first() {
+            Some((b'\x1b', rest)) => {
+                let mut events = vec![Event::KeyEscape];
+                events.extend(self.feed(rest));
+                // an unfinished sequence after ESC is dropped
+                self.pending.clear();
+                events
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+fn parse(input: &[u8]) -> Parsed {
+    match input {
+        [] => Parsed::Incomplete,
+        [b'\x1b'] => Parsed::Incomplete,
+        [b'\x1b', b'[', b'<', rest @ ..] => parse_mouse(rest),
+        [b'\x1b', b'[', rest @ ..] => parse_csi(rest),
+        [b'\x1b', b'O'] => Parsed::Incomplete,
+        [b'\x1b', b'O', final_byte, ..] => match ss3_key(*final_byte) {
+            Some(ev) => Parsed::Event(ev, 3),
+            None => Parsed::Ignored(3),
+        },
+        [b'\x1b', rest @ ..] => match parse_char(rest) {
+            Parsed::Event(Event::Char(ch), len) => Parsed::Event(Event::Alt(ch), len + 1),
+            Parsed::Incomplete => Parsed::Incomplete,
+            // ESC followed by another control key
+            _ => Parsed::Event(Event::KeyEscape, 1),
+        },
+        _ => parse_char(input),
+    }
+}
+
+// parses "ESC [ <params> <final>", e.g. "\x1b[1;5D" or "\x1b[5~"
+fn parse_csi(input: &[u8]) -> Parsed {
+    let Some(end) = input.iter().position(|b| (0x40..=0x7e).contains(b)) else {
+        // a broken sequence shouldn't keep swallowing the input
+        if input.iter().all(|b| (0x20..=0x3f).contains(b)) {
+            return Parsed::Incomplete;
+        } else {
+            return Parsed::Ignored(2);
+        }
+    };
+    let len = 2 + end + 1;
+
+    // modifiers are encoded as 1 + bits like "1;5" (Ctrl); sub-parameters after ':' are ignored
+    let params = std::str::from_utf8(&input[..end]).unwrap_or_default();
+    let mut params = params
+        .split(';')
+        .map(|param| param.split(':').next().and_then(|n| n.parse::<u32>().ok()));
+    let first = params.next().flatten();
+    let mods = params.next().flatten().unwrap_or(1).saturating_sub(1) as u8;
+    // Super, Caps Lock and so on are ignored
+    let mods = mods & (SHIFT | ALT | CTRL);
+
+    let key = match (input[end], first) {
+        (b'A', _) => Key::Up,
+        (b'B', _) => Key::Down,
+        (b'C', _) => Key::Right,
+        (b'D', _) => Key::Left,
+        (b'H', _) => Key::Home,
+        (b'F', _) => Key::End,
+        (b'Z', _) => return Parsed::Event(Event::KeyBackTab, len),
+        (b'P'..=b'S', _) => Key::F(input[end] - b'P' + 1),
+        (b'~', Some(1 | 7)) => Key::Home,
+        (b'~', Some(4 | 8)) => Key::End,
+        (b'~', Some(2)) => Key::Insert,
+        (b'~', Some(3)) => Key::Delete,
+        (b'~', Some(5)) => Key::PageUp,
+        (b'~', Some(6)) => Key::PageDown,
+        (b'~', Some(n @ 11..=15)) => Key::F((n - 10) as u8),
+        (b'~', Some(n @ 17..=21)) => Key::F((n - 11) as u8),
+        (b'~', Some(n @ 23..=24)) => Key::F((n - 12) as u8),
+        // "CSI <code> ; <mods> u" of the kitty keyboard protocol
+        (b'u', Some(code)) => match code {
+            9 => Key::Tab,
+            13 => Key::Enter,
+            27 => Key::Escape,
+            127 => Key::Backspace,
+            _ => match char::from_u32(code) {
+                Some(ch) if !ch.is_control() => Key::Char(ch),
+                _ => return Parsed::Ignored(len),
+            },
+        },
+        _ => return Parsed::Ignored(len),
+    };
+    Parsed::Event(Event::modified(mods, key), len)
+}
+
+// parses "ESC [ < <button> ; <col> ; <row> M" (or "m" on release) of the SGR mouse mode
+fn parse_mouse(input: &[u8]) -> Parsed {
+    let Some(end) = input.iter().position(|b| matches!(b, b'M' | b'm')) else {
+        if input.iter().all(|b| b.is_ascii_digit() || *b == b';') {
+            return Parsed::Incomplete;
+        } else {
+            return Parsed::Ignored(3);
+        }
+    };
+    let len = 3 + end + 1;
+
+    let params = std::str::from_utf8(&input[..end]).unwrap_or_default();
+    let params: Vec<u16> = params.split(';').filter_map(|p| p.parse().ok()).collect();
+    let event = match (params.as_slice(), input[end]) {
+        // the left button pressed without motion or modifiers
+        ([0, col, row], b'M') => Event::MouseClick(col.saturating_sub(1), row.saturating_sub(1)),
+        ([64, _, _], b'M') => Event::WheelUp,
+        ([65, _, _], b'M') => Event::WheelDown,
+        _ => return Parsed::Ignored(len),
+    };
+    Parsed::Event(event, len)
+}
+
+// sends `query` to the terminal and reads the input until `find` finds the response in it.
+// returns what `find` returns, and the keys typed meanwhile.
+pub(super) fn query_terminal<T, F>(query: &str, find: F) -> (Option<T>, Vec<u8>)
+where
+    F: Fn(&[u8]) -> Option<(T, (usize, usize))>,
+{
+    use nix::libc::STDIN_FILENO;
+    use std::io::Write as _;
+
+    print!("{query}");
+    let _ = std::io::stdout().flush();
+
+    let mut input = Vec::new();
+    let mut buf = [0_u8; 64];
+    loop {
+        if let Some((found, (start, end))) = find(&input) {
+            input.drain(start..end);
+            return (Some(found), input);
+        }
+        // terminals which don't answer at all are not waited for long
+        if !super::input_ready(200) {
+            return (None, input);
+        }
+        match nix::unistd::read(STDIN_FILENO, &mut buf) {
+            Ok(0) | Err(_) => return (None, input),
+            Ok(n) => input.extend_from_slice(&buf[..n]),
+        }
+    }
+}
+
+/// Asks the terminal whether it supports the kitty keyboard protocol.
+/// Returns the answer and the keys typed meanwhile.
+pub fn query_kitty_keyboard() -> (bool, Vec<u8>) {
+    // the terminal answers the query for the protocol before the one for device attributes
+    let (supported, typeahead) = query_terminal("\x1b[?u\x1b[c", find_kitty_response);
+    (supported.unwrap_or(false), typeahead)
+}
+
+/// Asks the terminal the (row, column) of the cursor, counted from 0.
+/// Returns it and the keys typed meanwhile.
+pub fn query_cursor_position() -> (Option<(usize, usize)>, Vec<u8>) {
+    query_terminal("\x1b[6n", find_cursor_position)
+}
+
+// finds "ESC [ <row> ; <col> R"
+fn find_cursor_position(input: &[u8]) -> Option<((usize, usize), (usize, usize))> {
+    (0..input.len()).find_map(|start| {
+        let rest = input[start..].strip_prefix(b"\x1b[")?;
+        let len = rest.iter().position(|b| *b == b'R')?;
+        let params = std::str::from_utf8(&rest[..len]).ok()?;
+        let (row, col) = params.split_once(';')?;
+        let pos = (
+            row.parse::<usize>().ok()?.checked_sub(1)?,
+            col.parse::<usize>().ok()?.checked_sub(1)?,
+        );
+        Some((pos, (start, start + 2 + len + 1)))
+    })
+}
+
+/// Makes the terminal report modified keys unambiguously with the kitty keyboard protocol
+pub fn enable_kitty_keyboard() {
+    print!("\x1b[>1u");
+}
+
+/// Restores the keyboard mode before `enable_kitty_keyboard`
+pub fn disable_kitty_keyboard() {
+    print!("\x1b[<u");
+}
+
+// finds the responses to the queries, returning whether the protocol is supported and
+// the range of the responses
+fn find_kitty_response(input: &[u8]) -> Option<(bool, (usize, usize))> {
+    let responses = |i: usize| -> Option<(bool, usize)> {
+        let rest = input.get(i..)?.strip_prefix(b"\x1b[?")?;
+        let len = rest
+            .iter()
+            .position(|b| !(b.is_ascii_digit() || *b == b';'))?;
+        match rest[len] {
+            b'u' => Some((true, i + 3 + len + 1)),
+            b'c' => Some((false, i + 3 + len + 1)),
+            _ => None,
+        }
+    };
+
+    let start = (0..input.len()).find(|&i| responses(i).is_some())?;
+    let (supported, mut end) = responses(start)?;
+    if supported {
+        // the device attributes follow
+        let (_, da_end) = responses(end)?;
+        end = da_end;
+    }
+    Some((supported, (start, end)))
+}
+
+// "ESC O <final>" is sent by some terminals in the application mode
+fn ss3_key(final_byte: u8) -> Option<Event> {
+    let event = match final_byte {
+        b'A' => Event::KeyUp,
+        b'B' => Event::KeyDown,
+        b'C' => Event::KeyRight,
+        b'D' => Event::KeyLeft,
+        b'H' => Event::KeyHome,
+        b'F' => Event::KeyEnd,
+        b'P'..=b'S' => Event::KeyF(final_byte - b'P' + 1),
+        _ => return None,
+    };
+    Some(event)
+}
+
+fn parse_char(input: &[u8]) -> Parsed {
+    let len = match input[0] {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return Parsed::Ignored(1),
+    };
+    if input.len() < len {
+        return Parsed::Incomplete;
+    }
+    let Some(ch) = std::str::from_utf8(&input[..len])
+        .ok()
+        .and_then(|s| s.chars().next())
+    else {
+        return Parsed::Ignored(1);
+    };
+
+    let event = match ch {
+        '\x00' => Event::Ctrl('@'),
+        '\x09' => Event::KeyTab,
+        '\x0d' => Event::KeyReturn,
+        '\x1b' => Event::KeyEscape,
+        '\x01'..='\x1a' => Event::Ctrl((b'a' + ch as u8 - 1) as char),
+        '\x1c' => Event::Ctrl('\\'),
+        '\x1d' => Event::Ctrl(']'),
+        '\x1e' => Event::Ctrl('^'),
+        '\x1f' => Event::Ctrl('_'),
+        '\x7f' => Event::KeyBackspace,
+        ch if ch.is_control() => return Parsed::Ignored(len),
+        _ => Event::Char(ch),
+    };
+    Parsed::Event(event, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn input_parser() {
+        use Event::*;
+        let mut parser = InputParser::new();
+
+        assert_eq!(
+            parser.feed(b"a\x1b[A\x1bOF\x1b[3~\x1b[1;5D\x1b[D\x1bf\x05\r"),
+            [
+                Char('a'),
+                KeyUp,
+                KeyEnd,
+                KeyDelete,
+                Modified(CTRL, Key::Left),
+                KeyLeft,
+                Alt('f'),
+                Ctrl('e'),
+                KeyReturn
+            ]
+        );
+        assert_eq!(
+            parser.feed(b"\x1b[5~\x1b[6~\x1b[15~\x1bOP\x1b[Z\x1b[H"),
+            [
+                KeyPageUp,
+                KeyPageDown,
+                KeyF(5),
+                KeyF(1),
+                KeyBackTab,
+                KeyHome
+            ]
+        );
+
+        // split across reads
+        assert_eq!(parser.feed(b"\x1b["), []);
+        assert!(parser.is_pending());
+        assert_eq!(parser.feed(b"2"), []);
+        assert_eq!(parser.feed(b"~x"), [KeyInsert, Char('x')]);
+        assert_eq!(parser.feed("あ".as_bytes().split_at(2).0), []);
+        assert_eq!(parser.feed("あ".as_bytes().split_at(2).1), [Char('あ')]);
+        assert_eq!(parser.feed("\x1bé".as_bytes()), [Alt('é')]);
+
+        // a single ESC is the Escape key once no more input comes
+        assert_eq!(parser.feed(b"\x1b"), []);
+        assert_eq!(parser.flush(), [KeyEscape]);
+        assert!(!parser.is_pending());
+        assert_eq!(parser.feed(b"\x1b\x7f"), [KeyEscape, KeyBackspace]);
+        assert_eq!(parser.feed(b"\x1b\x1b"), [KeyEscape]);
+        assert_eq!(parser.flush(), [KeyEscape]);
+
+        // unknown sequences are skipped
+        assert_eq!(parser.feed(b"\x1b[99~\x1b[?1;2cz"), [Char('z')]);
+
+        // mouse events
+        assert_eq!(
+            parser.feed(b"\x1b[<0;12;3M\x1b[<0;12;3m\x1b[<64;1;1M\x1b[<65;1;1M\x1b[<2;1;1M"),
+            [MouseClick(11, 2), WheelUp, WheelDown]
+        );
+
+        // modified keys
+        assert_eq!(
+            parser.feed(b"\x1b[1;5C\x1b[13;5u\x1b[9;2u\x1b[97;5u\x1b[97;3u\x1b[27u"),
+            [
+                Modified(CTRL, Key::Right),
+                Modified(CTRL, Key::Enter),
+                KeyBackTab,
+                Ctrl('a'),
+                Alt('a'),
+                KeyEscape,
+            ]
+        );
+        assert_eq!(
+            parser.feed(b"\x1b[3;6~\x1b[97:65;6u"),
+            [
+                Modified(CTRL | SHIFT, Key::Delete),
+                Modified(CTRL | SHIFT, Key::Char('a'))
+            ]
+        );
+    }
+
+    #[test]
+    fn kitty_query() {
+        assert_eq!(
+            find_kitty_response(b"ab\x1b[?1u\x1b[?62;22cc"),
+            Some((true, (2, 16)))
+        );
+        assert_eq!(find_kitty_response(b"\x1b[?62;22c"), Some((false, (0, 9))));
+        assert_eq!(find_kitty_response(b"\x1b[?1u\x1b[?6"), None);
+        assert_eq!(find_kitty_response(b"ab"), None);
+
+        assert_eq!(
+            find_cursor_position(b"x\x1b[12;3Ry"),
+            Some(((11, 2), (1, 8)))
+        );
+        assert_eq!(find_cursor_position(b"\x1b[12;3"), None);
+        assert_eq!(find_cursor_position(b"\x1b[0;0R"), None);
+    }
+}
@@ -0,0 +1,588 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Event {
+    KeyEscape,
+    KeyTab,
+    KeyBackTab,
+    KeyBackspace,
+    KeyDelete,
+    KeyReturn,
+    KeyUp,
+    KeyDown,
+    KeyLeft,
+    KeyRight,
+    KeyHome,
+    KeyEnd,
+    KeyInsert,
+    KeyPageUp,
+    KeyPageDown,
+    KeyF(u8),
+    Ctrl(char),
+    Alt(char),
+    Char(char),
+    /// a key with modifiers which has no plain representation, e.g. Ctrl-Enter
+    Modified(u8, Key),
+    /// a click at the (column, row) of the screen, counted from 0
+    MouseClick(u16, u16),
+    WheelUp,
+    WheelDown,
+}
+
+/// Modifier bits of `Event::Modified`
+pub const SHIFT: u8 = 1;
+pub const ALT: u8 = 2;
+pub const CTRL: u8 = 4;
+
+/// A key which can be pressed with modifiers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Key {
+    Escape,
+    Tab,
+    Backspace,
+    Delete,
+    Enter,
+    Up,
+    Down,
+    Left,
+    Right,
+    Home,
+    End,
+    Insert,
+    PageUp,
+    PageDown,
+    F(u8),
+    Char(char),
+}
+
+impl Key {
+    fn from_event(event: Event) -> Option<Self> {
+        let key = match event {
+            Event::KeyEscape => Key::Escape,
+            Event::KeyTab => Key::Tab,
+            Event::KeyBackspace => Key::Backspace,
+            Event::KeyDelete => Key::Delete,
+            Event::KeyReturn => Key::Enter,
+            Event::KeyUp => Key::Up,
+            Event::KeyDown => Key::Down,
+            Event::KeyLeft => Key::Left,
+            Event::KeyRight => Key::Right,
+            Event::KeyHome => Key::Home,
+            Event::KeyEnd => Key::End,
+            Event::KeyInsert => Key::Insert,
+            Event::KeyPageUp => Key::PageUp,
+            Event::KeyPageDown => Key::PageDown,
+            Event::KeyF(n) => Key::F(n),
+            Event::Char(ch) => Key::Char(ch),
+            _ => return None,
+        };
+        Some(key)
+    }
+
+    /// Returns the event of the key pressed without modifiers
+    pub fn plain(self) -> Event {
+        match self {
+            Key::Escape => Event::KeyEscape,
+            Key::Tab => Event::KeyTab,
+            Key::Backspace => Event::KeyBackspace,
+            Key::Delete => Event::KeyDelete,
+            Key::Enter => Event::KeyReturn,
+            Key::Up => Event::KeyUp,
+            Key::Down => Event::KeyDown,
+            Key::Left => Event::KeyLeft,
+            Key::Right => Event::KeyRight,
+            Key::Home => Event::KeyHome,
+            Key::End => Event::KeyEnd,
+            Key::Insert => Event::KeyInsert,
+            Key::PageUp => Event::KeyPageUp,
+            Key::PageDown => Event::KeyPageDown,
+            Key::F(n) => Event::KeyF(n),
+            Key::Char(ch) => Event::Char(ch),
+        }
+    }
+}
+
+impl Event {
+    /// Returns the event of `key` pressed with `mods`, as a plain event if it can be represented as such
+    pub fn modified(mods: u8, key: Key) -> Self {
+        match (mods, key) {
+            (0, key) => key.plain(),
+            (SHIFT, Key::Tab) => Event::KeyBackTab,
+            (SHIFT, Key::Char(ch)) if ch.is_ascii_lowercase() => {
+                Event::Char(ch.to_ascii_uppercase())
+            }
+            (CTRL, Key::Char(ch)) if ch.is_ascii_alphabetic() || "@[\\]^_".contains(ch) => {
+                match ch.to_ascii_lowercase() {
+                    '[' => Event::KeyEscape,
+                    'i' => Event::KeyTab,
+                    'm' => Event::KeyReturn,
+                    ch => Event::Ctrl(ch),
+                }
+            }
+            (ALT, Key::Char(ch)) => Event::Alt(ch),
+            (mods, key) => Event::Modified(mods, key),
+        }
+    }
+
+    /// Parses a key notation like "a", "^k", "tab", "up" or "C-S-left"
+    pub fn parse(key: &str) -> Option<Self> {
+        // modifiers are given as prefixes
+        let mut mods = 0;
+        let mut base = key;
+        while base.len() > 2 {
+            // `get` doesn't split a multibyte character
+            let bit = match base.get(..2) {
+                Some("C-") => CTRL,
+                Some("M-") => ALT,
+                Some("S-") => SHIFT,
+                _ => break,
+            };
+            mods |= bit;
+            base = &base[2..];
+        }
+        if mods != 0 {
+            let key = Key::from_event(Self::parse(base)?)?;
+            return Some(Self::modified(mods, key));
+        }
+
+        let event = match key {
+            "esc" | "^[" => Event::KeyEscape,
+            "tab" | "^I" | "^i" => Event::KeyTab,
+            "backtab" => Event::KeyBackTab,
+            "backspace" | "^?" => Event::KeyBackspace,
+            "delete" => Event::KeyDelete,
+            "enter" | "return" | "^M" | "^m" => Event::KeyReturn,
+            "up" => Event::KeyUp,
+            "down" => Event::KeyDown,
+            "left" => Event::KeyLeft,
+            "right" => Event::KeyRight,
+            "home" => Event::KeyHome,
+            "end" => Event::KeyEnd,
+            "insert" => Event::KeyInsert,
+            "pageup" => Event::KeyPageUp,
+            "pagedown" => Event::KeyPageDown,
+            "wheelup" => Event::WheelUp,
+            "wheeldown" => Event::WheelDown,
+            _ if key.starts_with('f') && key.len() > 1 => {
+                let n = key[1..].parse().ok().filter(|n| (1..=12).contains(n))?;
+                Event::KeyF(n)
+            }
+            _ => {
+                let mut chars = key.chars();
+                match (chars.next()?, chars.next(), chars.next()) {
+                    (ch, None, None) => Event::Char(ch),
+                    ('^', Some(ch), None) if ch.is_ascii_graphic() => {
+                        Event::Ctrl(ch.to_ascii_lowercase())
+                    }
+                    _ => return None,
+                }
+            }
+        };
+        Some(event)
+    }
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Event::KeyEscape => write!(f, "esc"),
+            Event::KeyTab => write!(f, "tab"),
+            Event::KeyBackTab => write!(f, "backtab"),
+            Event::KeyBackspace => write!(f, "backspace"),
+            Event::KeyDelete => write!(f, "delete"),
+            Event::KeyReturn => write!(f, "enter"),
+            Event::KeyUp => write!(f, "up"),
+            Event::KeyDown => write!(f, "down"),
+            Event::KeyLeft => write!(f, "left"),
+            Event::KeyRight => write!(f, "right"),
+            Event::KeyHome => write!(f, "home"),
+            Event::KeyEnd => write!(f, "end"),
+            Event::KeyInsert => write!(f, "insert"),
+            Event::KeyPageUp => write!(f, "pageup"),
+            Event::KeyPageDown => write!(f, "pagedown"),
+            Event::KeyF(n) => write!(f, "f{n}"),
+            Event::Ctrl(ch) => write!(f, "^{ch}"),
+            Event::Alt(ch) => write!(f, "M-{ch}"),
+            Event::Char(ch) => write!(f, "{ch}"),
+            Event::MouseClick(col, row) => write!(f, "click({col},{row})"),
+            Event::WheelUp => write!(f, "wheelup"),
+            Event::WheelDown => write!(f, "wheeldown"),
+            Event::Modified(mods, key) => {
+                for (bit, prefix) in [(CTRL, "C-"), (ALT, "M-"), (SHIFT, "S-")] {
+                    if mods & bit != 0 {
+                        write!(f, "{prefix}")?;
+                    }
+                }
+                write!(f, "{}", key.plain())
+            }
+        }
+    }
+}
+
+macro_rules! actions {
+    ($($variant:ident => $name:literal,)*) => {
+        /// Editing functions which can be bound to keys
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Action {
+            $($variant,)*
+        }
+
+        impl Action {
+            pub const ALL: &'static [Action] = &[$(Action::$variant,)*];
+
+            pub fn name(self) -> &'static str {
+                match self {
+                    $(Action::$variant => $name,)*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some(Action::$variant),)*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+actions! {
+    SelfInsert => "self-insert",
+    QuotedInsert => "quoted-insert",
+    AcceptLine => "accept-line",
+    OperateAndGetNext => "operate-and-get-next",
+    Abort => "abort",
+    BackwardChar => "backward-char",
+    ForwardChar => "forward-char",
+    BackwardWord => "backward-word",
+    ForwardWord => "forward-word",
+    EndOfLine => "end-of-line",
+    BeginningOfLine => "beginning-of-line",
+    UpHistory => "up-history",
+    DownHistory => "down-history",
+    HistorySearch => "history-search",
+    HistorySearchForward => "history-search-forward",
+    BackwardDeleteChar => "backward-delete-char",
+    DeleteChar => "delete-char",
+    BackwardKillWord => "backward-kill-word",
+    KillWord => "kill-word",
+    KillToEnd => "kill-to-end",
+    KillWholeLine => "kill-whole-line",
+    Yank => "yank",
+    YankPop => "yank-pop",
+    DuplicateWord => "duplicate-word",
+    ToggleSudo => "toggle-sudo",
+    TransposeChars => "transpose-chars",
+    TransposeWords => "transpose-words",
+    Complete => "complete",
+    ListChoices => "list-choices",
+    ReverseMenuComplete => "reverse-menu-complete",
+    ClearScreen => "clear-screen",
+    FuzzyHistory => "fuzzy-history",
+    FuzzyFile => "fuzzy-file",
+    CdParent => "cd-parent",
+    CdUndo => "cd-undo",
+    CdRedo => "cd-redo",
+    Undo => "undo",
+    Redo => "redo",
+    ViCmdMode => "vi-cmd-mode",
+    ViInsert => "vi-insert",
+    ViInsertBol => "vi-insert-bol",
+    ViAppend => "vi-append",
+    ViAppendEol => "vi-append-eol",
+    ViVisual => "vi-visual",
+    ViVisualLine => "vi-visual-line",
+    ViForwardWord => "vi-forward-word",
+    ViForwardBlankWord => "vi-forward-blank-word",
+    ViForwardWordEnd => "vi-forward-word-end",
+    ViForwardBlankWordEnd => "vi-forward-blank-word-end",
+    ViBackwardWord => "vi-backward-word",
+    ViBackwardBlankWord => "vi-backward-blank-word",
+    ViBackwardWordEnd => "vi-backward-word-end",
+    ViBackwardBlankWordEnd => "vi-backward-blank-word-end",
+    ViGotoPrefix => "vi-goto-prefix",
+    ViMatchBracket => "vi-match-bracket",
+    ViFirstNonBlank => "vi-first-non-blank",
+    ViFindNextChar => "vi-find-next-char",
+    ViFindPrevChar => "vi-find-prev-char",
+    ViTillNextChar => "vi-find-next-char-skip",
+    ViTillPrevChar => "vi-find-prev-char-skip",
+    ViRepeatFind => "vi-repeat-find",
+    ViRevRepeatFind => "vi-rev-repeat-find",
+    ViRepeatChange => "vi-repeat-change",
+    ViSetRegister => "vi-set-register",
+    ViRecordMacro => "vi-record-macro",
+    ViPlayMacro => "vi-play-macro",
+    ViDelete => "vi-delete",
+    ViDeleteChar => "vi-delete-char",
+    ViChange => "vi-change",
+    ViChangeEol => "vi-change-eol",
+    ViChangeWholeLine => "vi-change-whole-line",
+    ViSubstitute => "vi-substitute",
+    ViYank => "vi-yank",
+    ViYankWholeLine => "vi-yank-whole-line",
+    ViPutBefore => "vi-put-before",
+    ViPutAfter => "vi-put-after",
+    ViExchangeEnds => "vi-exchange-ends",
+    ViSelectInner => "vi-select-inner",
+    ViSelectAround => "vi-select-around",
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum KeymapMode {
+    Insert,
+    Normal,
+    Visual,
+    Search,
+}
+
+impl KeymapMode {
+    pub const ALL: &'static [KeymapMode] = &[
+        KeymapMode::Insert,
+        KeymapMode::Normal,
+        KeymapMode::Visual,
+        KeymapMode::Search,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            KeymapMode::Insert => "insert",
+            KeymapMode::Normal => "normal",
+            KeymapMode::Visual => "visual",
+            KeymapMode::Search => "search",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|mode| mode.name() == name)
+    }
+}
+
+/// `Keymap` maps keys to editing functions for each mode of the line editor
+#[derive(Debug, Clone)]
+pub struct Keymap {
+    bindings: HashMap<(KeymapMode, Event), Action>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        use Action::*;
+        use Event::*;
+        use KeymapMode::*;
+
+        let mut keymap = Self {
+            bindings: HashMap::new(),
+        };
+
+        let mut bind = |mode, events: &[Event], action| {
+            for event in events {
+                keymap.bindings.insert((mode, *event), action);
+            }
+        };
+
+        bind(Insert, &[KeyEscape], ViCmdMode);
+        bind(Insert, &[KeyReturn], AcceptLine);
+        bind(Insert, &[KeyLeft], BackwardChar);
+        bind(Insert, &[KeyRight], ForwardChar);
+        let (ctrl_left, alt_left) = (Modified(CTRL, Key::Left), Modified(ALT, Key::Left));
+        let (ctrl_right, alt_right) = (Modified(CTRL, Key::Right), Modified(ALT, Key::Right));
+        bind(Insert, &[ctrl_left, alt_left, Alt('b')], BackwardWord);
+        bind(Insert, &[ctrl_right, alt_right, Alt('f')], ForwardWord);
+        bind(Insert, &[KeyHome], BeginningOfLine);
+        bind(Insert, &[KeyEnd], EndOfLine);
+        bind(Insert, &[KeyUp, WheelUp], UpHistory);
+        bind(Insert, &[KeyDown, WheelDown], DownHistory);
+        bind(Insert, &[KeyBackspace], BackwardDeleteChar);
+        bind(Insert, &[KeyDelete], DeleteChar);
+        bind(Insert, &[Ctrl('w')], BackwardKillWord);
+        bind(Insert, &[Ctrl('u')], KillWholeLine);
+        bind(Insert, &[Ctrl('k')], KillToEnd);
+        bind(Insert, &[Alt('d')], KillWord);
+        bind(Insert, &[Ctrl('y')], Yank);
+        bind(Insert, &[Alt('y')], YankPop);
+        bind(Insert, &[KeyTab], Complete);
+        bind(Insert, &[KeyBackTab], ReverseMenuComplete);
+        bind(Insert, &[Ctrl('d')], ListChoices);
+        bind(Insert, &[Ctrl('p')], CdParent);
+        bind(Insert, &[Ctrl('o')], OperateAndGetNext);
+        bind(Insert, &[Ctrl('r')], HistorySearch);
+        bind(Insert, &[Ctrl('s')], HistorySearchForward);
+        bind(Insert, &[Ctrl('n')], DuplicateWord);
+        bind(Insert, &[Alt('s')], ToggleSudo);
+        bind(Insert, &[Ctrl('t')], TransposeChars);
+        bind(Insert, &[Alt('t')], TransposeWords);
+        bind(Insert, &[Ctrl('l')], ClearScreen);
+        bind(Insert, &[Alt('r')], FuzzyHistory);
+        bind(Insert, &[Ctrl('f')], FuzzyFile);
+        bind(Insert, &[Ctrl('_')], Undo);
+        bind(Insert, &[Ctrl('v')], QuotedInsert);
+
+        bind(Normal, &[Char('i')], ViInsert);
+        bind(Normal, &[Char('v')], ViVisual);
+        bind(Normal, &[Char('V')], ViVisualLine);
+        bind(Normal, &[KeyReturn], AcceptLine);
+        bind(Normal, &[KeyLeft, Char('h')], BackwardChar);
+        bind(Normal, &[KeyRight, Char('l')], ForwardChar);
+        bind(Normal, &[KeyUp, Char('k'), WheelUp], UpHistory);
+        bind(Normal, &[KeyDown, Char('j'), WheelDown], DownHistory);
+        bind(Normal, &[Char('w')], ViForwardWord);
+        bind(Normal, &[Char('W')], ViForwardBlankWord);
+        bind(Normal, &[Char('e')], ViForwardWordEnd);
+        bind(Normal, &[Char('E')], ViForwardBlankWordEnd);
+        bind(Normal, &[Char('b')], ViBackwardWord);
+        bind(Normal, &[Char('B')], ViBackwardBlankWord);
+        bind(Normal, &[Char('g')], ViGotoPrefix);
+        bind(Normal, &[Char('%')], ViMatchBracket);
+        bind(Normal, &[Char('f')], ViFindNextChar);
+        bind(Normal, &[Char('F')], ViFindPrevChar);
+        bind(Normal, &[Char('t')], ViTillNextChar);
+        bind(Normal, &[Char('T')], ViTillPrevChar);
+        bind(Normal, &[Char(';')], ViRepeatFind);
+        bind(Normal, &[Char(',')], ViRevRepeatFind);
+        bind(Normal, &[Char('$'), KeyEnd], EndOfLine);
+        bind(Normal, &[Char('^')], ViFirstNonBlank);
+        bind(Normal, &[Char('0'), KeyHome], BeginningOfLine);
+        bind(Normal, &[Char('A')], ViAppendEol);
+        bind(Normal, &[Char('I')], ViInsertBol);
+        bind(Normal, &[Char('a')], ViAppend);
+        bind(Normal, &[Char('s')], ViSubstitute);
+        bind(Normal, &[Char('x')], ViDeleteChar);
+        bind(Normal, &[Char('d')], ViDelete);
+        bind(Normal, &[Char('c')], ViChange);
+        bind(Normal, &[Char('D')], KillToEnd);
+        bind(Normal, &[Char('C')], ViChangeEol);
+        bind(Normal, &[Char('S')], ViChangeWholeLine);
+        bind(Normal, &[Char('y')], ViYank);
+        bind(Normal, &[Char('Y')], ViYankWholeLine);
+        bind(Normal, &[Char('P')], ViPutBefore);
+        bind(Normal, &[Char('p')], ViPutAfter);
+        bind(Normal, &[Char('.')], ViRepeatChange);
+        bind(Normal, &[Char('"')], ViSetRegister);
+        bind(Normal, &[Char('q')], ViRecordMacro);
+        bind(Normal, &[Char('@')], ViPlayMacro);
+        bind(Normal, &[Char('u')], Undo);
+        bind(Normal, &[Ctrl('r')], Redo);
+        bind(Normal, &[Ctrl('o')], CdUndo);
+        bind(Normal, &[KeyTab], CdRedo);
+        bind(Normal, &[Ctrl('p')], CdParent);
+        bind(Normal, &[Ctrl('n')], DuplicateWord);
+        bind(Normal, &[Alt('s')], ToggleSudo);
+        bind(Normal, &[Ctrl('l')], ClearScreen);
+
+        bind(Visual, &[KeyEscape, Char('v')], ViCmdMode);
+        bind(Visual, &[Char('i')], ViSelectInner);
+        bind(Visual, &[Char('a')], ViSelectAround);
+        bind(Visual, &[Char('"')], ViSetRegister);
+        bind(Visual, &[KeyReturn], AcceptLine);
+        bind(Visual, &[KeyLeft, Char('h')], BackwardChar);
+        bind(Visual, &[KeyRight, Char('l')], ForwardChar);
+        bind(Visual, &[Char('w')], ViForwardWord);
+        bind(Visual, &[Char('W')], ViForwardBlankWord);
+        bind(Visual, &[Char('e')], ViForwardWordEnd);
+        bind(Visual, &[Char('E')], ViForwardBlankWordEnd);
+        bind(Visual, &[Char('b')], ViBackwardWord);
+        bind(Visual, &[Char('B')], ViBackwardBlankWord);
+        bind(Visual, &[Char('g')], ViGotoPrefix);
+        bind(Visual, &[Char('%')], ViMatchBracket);
+        bind(Visual, &[Char('o')], ViExchangeEnds);
+        bind(Visual, &[Char('$')], EndOfLine);
+        bind(Visual, &[Char('^')], ViFirstNonBlank);
+        bind(Visual, &[Char('0')], BeginningOfLine);
+        bind(Visual, &[Char('D')], KillWholeLine);
+        bind(Visual, &[Char('C'), Char('S')], ViChangeWholeLine);
+        bind(Visual, &[Char('Y')], ViYankWholeLine);
+        bind(Visual, &[Char('d'), Char('x')], ViDelete);
+        bind(Visual, &[Char('c'), Char('s')], ViChange);
+        bind(Visual, &[Char('y')], ViYank);
+
+        bind(Search, &[KeyEscape, KeyTab, Ctrl('u'), Ctrl('d')], Abort);
+        bind(Search, &[KeyReturn], AcceptLine);
+        bind(Search, &[KeyLeft], BackwardChar);
+        bind(Search, &[KeyRight], ForwardChar);
+        bind(Search, &[KeyUp], UpHistory);
+        bind(Search, &[KeyDown], DownHistory);
+        bind(Search, &[KeyBackspace], BackwardDeleteChar);
+        bind(Search, &[Ctrl('w')], BackwardKillWord);
+        bind(Search, &[Ctrl('r')], HistorySearch);
+        bind(Search, &[Ctrl('s')], HistorySearchForward);
+
+        keymap
+    }
+}
+
+impl Keymap {
+    /// Returns the action bound to `event`.
+    /// Printable characters insert themselves in the insert and search modes unless bound.
+    pub fn lookup(&self, mode: KeymapMode, event: Event) -> Option<Action> {
+        if let Some(action) = self.bindings.get(&(mode, event)) {
+            return Some(*action);
+        }
+
+        match (mode, event) {
+            (KeymapMode::Insert | KeymapMode::Search, Event::Char(_)) => Some(Action::SelfInsert),
+            _ => None,
+        }
+    }
+
+    pub fn bind(&mut self, mode: KeymapMode, event: Event, action: Action) {
+        self.bindings.insert((mode, event), action);
+    }
+
+    pub fn unbind(&mut self, mode: KeymapMode, event: Event) -> bool {
+        self.bindings.remove(&(mode, event)).is_some()
+    }
+
+    /// Returns the bindings of `mode` sorted by key
+    pub fn bindings(&self, mode: KeymapMode) -> Vec<(Event, Action)> {
+        let mut bindings: Vec<(Event, Action)> = self
+            .bindings
+            .iter()
+            .filter(|((m, _), _)| *m == mode)
+            .map(|((_, event), action)| (*event, *action))
+            .collect();
+        bindings.sort_by_key(|(event, _)| event.to_string());
+        bindings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn key_notation() {
+        assert_eq!(Event::parse("a"), Some(Event::Char('a')));
+        assert_eq!(Event::parse("^"), Some(Event::Char('^')));
+        assert_eq!(Event::parse("^k"), Some(Event::Ctrl('k')));
+        assert_eq!(Event::parse("^K"), Some(Event::Ctrl('k')));
+        assert_eq!(Event::parse("^["), Some(Event::KeyEscape));
+        assert_eq!(Event::parse("tab"), Some(Event::KeyTab));
+        assert_eq!(Event::parse("up"), Some(Event::KeyUp));
+        assert_eq!(Event::parse("M-x"), Some(Event::Alt('x')));
+        assert_eq!(Event::parse("C-x"), Some(Event::Ctrl('x')));
+        assert_eq!(Event::parse("S-tab"), Some(Event::KeyBackTab));
+        assert_eq!(
+            Event::parse("C-enter"),
+            Some(Event::Modified(CTRL, Key::Enter))
+        );
+        assert_eq!(
+            Event::parse("C-S-left"),
+            Some(Event::Modified(CTRL | SHIFT, Key::Left))
+        );
+        assert_eq!(Event::parse("C-S-left").unwrap().to_string(), "C-S-left");
+        assert_eq!(Event::parse("C-"), None);
+        assert_eq!(Event::parse("ab"), None);
+        assert_eq!(Event::parse("é"), Some(Event::Char('é')));
+        assert_eq!(Event::parse("M-é"), Some(Event::Alt('é')));
+        assert_eq!(Event::parse("aé"), None);
+        assert_eq!(Event::parse(""), None);
+
+        let keymap = Keymap::default();
+        for mode in KeymapMode::ALL {
+            for (event, action) in keymap.bindings(*mode) {
+                assert_eq!(Event::parse(&event.to_string()), Some(event));
+                assert_eq!(Action::from_name(action.name()), Some(action));
+            }
+        }
+    }
+}
@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use unicode_segmentation::UnicodeSegmentation as _;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum CharClass {
     WhiteSpace,
@@ -31,16 +34,41 @@ impl From<char> for CharClass {
     }
 }
 
+/// Returns the display width of a grapheme cluster
+pub fn grapheme_width(grapheme: &str) -> usize {
+    use unicode_width::UnicodeWidthChar as _;
+    let base = match grapheme.chars().next() {
+        None => 0,
+        // shown in caret notation
+        Some(ch) if ch.is_ascii_control() => 2,
+        Some(ch) => ch.width().unwrap_or(1),
+    };
+    // combining characters and the rest of an emoji sequence are drawn over the first one,
+    // which the variation selector 16 turns into a wide emoji
+    if grapheme.contains('\u{fe0f}') {
+        base.max(2)
+    } else {
+        base
+    }
+}
+
+/// Splits `s` into grapheme clusters along with their widths
+pub fn graphemes(s: &str) -> impl Iterator<Item = (&str, usize)> {
+    s.graphemes(true).map(|g| (g, grapheme_width(g)))
+}
+
+/// A line being edited. Positions and the cursor are counted in grapheme clusters
+/// so that a character with combining marks or an emoji sequence is edited as a whole.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Line {
-    buf: Vec<(char, usize)>,
+    buf: Vec<(String, usize)>,
     cursor: usize,
 }
 
 impl std::fmt::Display for Line {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        for (ch, _) in self.buf.iter() {
-            write!(f, "{}", ch)?;
+        for (g, _) in self.buf.iter() {
+            write!(f, "{}", g)?;
         }
         Ok(())
     }
@@ -49,14 +77,7 @@ impl std::fmt::Display for Line {
 impl<'a> From<&'a str> for Line {
     fn from(s: &'a str) -> Self {
         Self {
-            buf: s
-                .chars()
-                .map(|ch| {
-                    use unicode_width::UnicodeWidthChar as _;
-                    let w = ch.width().unwrap_or(1);
-                    (ch, w)
-                })
-                .collect(),
+            buf: graphemes(s).map(|(g, w)| (g.to_owned(), w)).collect(),
             cursor: 0,
         }
     }
@@ -72,13 +93,23 @@ impl Line {
 
     pub fn iter(
         &self,
-        range: impl std::slice::SliceIndex<[(char, usize)], Output = [(char, usize)]>,
-    ) -> impl Iterator<Item = (char, usize)> + '_ {
-        self.buf.get(range).unwrap().iter().copied()
+        range: impl std::slice::SliceIndex<[(String, usize)], Output = [(String, usize)]>,
+    ) -> impl Iterator<Item = (&str, usize)> + '_ {
+        self.buf
+            .get(range)
+            .unwrap()
+            .iter()
+            .map(|(g, w)| (g.as_str(), *w))
     }
 
+    /// Returns the base character of the grapheme cluster at `at`
     pub fn char_at(&self, at: usize) -> Option<char> {
-        self.buf.get(at).map(|(ch, _)| ch).copied()
+        self.buf.get(at).and_then(|(g, _)| g.chars().next())
+    }
+
+    // the base character of the grapheme cluster at `at`, which must exist
+    fn base(&self, at: usize) -> char {
+        self.char_at(at).unwrap()
     }
 
     pub fn cursor(&self) -> usize {
@@ -89,29 +120,21 @@ impl Line {
         self.buf.len()
     }
 
-    pub fn last_word(&self, wide: bool) -> Option<String> {
-        let word_class = CharClass::from(self.buf.last()?.0);
-        if word_class == CharClass::WhiteSpace {
-            return None;
-        }
-
-        let mut i = self.buf.len() - 1;
-        while i > 0 {
-            let prev_class = CharClass::from(self.buf[i - 1].0);
-            if !CharClass::is_same(wide, prev_class, word_class) {
-                break;
+    pub fn insert(&mut self, ch: char) {
+        // a combining character or a joined emoji extends the cluster before the cursor
+        if let Some((prev, _)) = self.cursor.checked_sub(1).map(|i| &self.buf[i]) {
+            let mut joined = prev.clone();
+            joined.push(ch);
+            if joined.graphemes(true).count() == 1 {
+                let width = grapheme_width(&joined);
+                self.buf[self.cursor - 1] = (joined, width);
+                return;
             }
-            i -= 1;
         }
 
-        Some(self.buf[i..].iter().map(|(ch, _)| ch).collect::<String>())
-    }
-
-    pub fn insert(&mut self, ch: char) {
-        use unicode_width::UnicodeWidthChar as _;
-        let width = ch.width().unwrap_or(1);
-
-        self.buf.insert(self.cursor, (ch, width));
+        let g = ch.to_string();
+        let width = grapheme_width(&g);
+        self.buf.insert(self.cursor, (g, width));
         self.cursor += 1;
     }
 
@@ -129,28 +152,162 @@ impl Line {
     }
 
     pub fn delete_word(&mut self) {
-        // remove trailing whitespaces
-        while self.cursor > 0 {
-            let prev_class = CharClass::from(self.buf[self.cursor - 1].0);
-            if !prev_class.is_whitespace() {
+        self.delete_range(self.prev_word_start(), self.cursor);
+    }
+
+    /// Returns the position where the word before the cursor starts, including trailing whitespaces
+    pub fn prev_word_start(&self) -> usize {
+        let mut i = self.cursor;
+
+        // skip trailing whitespaces
+        while i > 0 && CharClass::from(self.base(i - 1)).is_whitespace() {
+            i -= 1;
+        }
+
+        if i == 0 {
+            return 0;
+        }
+
+        // skip a single word
+        let word_class = CharClass::from(self.base(i - 1));
+        while i > 0 {
+            let prev_class = CharClass::from(self.base(i - 1));
+            if !CharClass::is_same(false, prev_class, word_class) {
                 break;
             }
-            self.delete_prev();
+            i -= 1;
         }
+        i
+    }
 
-        if self.cursor == 0 {
+    /// Returns the position where the word after the cursor ends, including leading whitespaces
+    pub fn next_word_end(&self) -> usize {
+        let len = self.buf.len();
+        let mut i = self.cursor;
+
+        // skip leading whitespaces
+        while i < len && CharClass::from(self.base(i)).is_whitespace() {
+            i += 1;
+        }
+
+        if i == len {
+            return len;
+        }
+
+        // skip a single word
+        let word_class = CharClass::from(self.base(i));
+        while i < len && CharClass::from(self.base(i)) == word_class {
+            i += 1;
+        }
+        i
+    }
+
+    /// Swaps the characters before and at the cursor, or the last two at the end of the line
+    pub fn transpose_chars(&mut self) {
+        let len = self.buf.len();
+        if len < 2 || self.cursor == 0 {
             return;
         }
+        let i = self.cursor.min(len - 1);
+        self.buf.swap(i - 1, i);
+        self.cursor = i + 1;
+    }
 
-        // remove a single word
-        let word_class = CharClass::from(self.buf[self.cursor - 1].0);
-        while self.cursor > 0 {
-            let prev_class = CharClass::from(self.buf[self.cursor - 1].0);
-            if !CharClass::is_same(false, prev_class, word_class) {
-                break;
+    /// Swaps the word before the cursor and the one after it, or the last two words at the
+    /// end of the line. The cursor in a word is regarded as at the end of it.
+    pub fn transpose_words(&mut self) {
+        let len = self.buf.len();
+        let is_word = |i: usize| CharClass::from(self.base(i)) == CharClass::Keyword;
+
+        let mut i = self.cursor;
+        while 0 < i && i < len && is_word(i - 1) && is_word(i) {
+            i += 1;
+        }
+
+        // the ranges of the two words
+        let word_before = |end: usize| -> Option<(usize, usize)> {
+            let end = (0..end).rev().find(|&j| is_word(j))? + 1;
+            let start = (0..end).rev().find(|&j| !is_word(j)).map_or(0, |j| j + 1);
+            Some((start, end))
+        };
+        let second = match (i..len).find(|&j| is_word(j)) {
+            Some(start) => (start, (start..len).find(|&j| !is_word(j)).unwrap_or(len)),
+            None => match word_before(i) {
+                Some(second) => second,
+                None => return,
+            },
+        };
+        let Some(first) = word_before(second.0) else {
+            return;
+        };
+
+        let tail = self.buf.split_off(second.1);
+        let second_word = self.buf.split_off(second.0);
+        let between = self.buf.split_off(first.1);
+        let first_word = self.buf.split_off(first.0);
+        self.buf.extend(second_word);
+        self.buf.extend(between);
+        self.buf.extend(first_word);
+        self.cursor = self.buf.len();
+        self.buf.extend(tail);
+    }
+
+    /// Returns the ranges of the words in command position, i.e. the first word of the line
+    /// and the ones following `|`, `;`, `&` or `(`
+    pub fn command_ranges(&self) -> Vec<(usize, usize)> {
+        let mut ranges = Vec::new();
+        let mut command_pos = true;
+        let mut i = 0;
+        while i < self.buf.len() {
+            let ch = self.base(i);
+            if matches!(ch, '|' | ';' | '&' | '(') {
+                command_pos = true;
+                i += 1;
+            } else if ch.is_whitespace() {
+                i += 1;
+            } else {
+                let start = i;
+                while i < self.buf.len() && !self.base(i).is_whitespace() {
+                    if matches!(self.base(i), '|' | ';' | '&' | '(' | ')') {
+                        break;
+                    }
+                    i += 1;
+                }
+                if command_pos && start < i {
+                    ranges.push((start, i));
+                }
+                command_pos = false;
+                i = i.max(start + 1);
             }
-            self.delete_prev();
         }
+        ranges
+    }
+
+    /// Expands the word before the cursor if it is an abbreviation in command position
+    pub fn expand_abbreviation(&mut self, abbrs: &HashMap<String, String>) -> bool {
+        let mut start = self.cursor;
+        while start > 0 && !self.base(start - 1).is_whitespace() {
+            start -= 1;
+        }
+
+        // the word must be the first one of a command
+        let prev = (0..start)
+            .rev()
+            .map(|i| self.base(i))
+            .find(|ch| !ch.is_whitespace());
+        if !matches!(prev, None | Some(';' | '|' | '&' | '(')) {
+            return false;
+        }
+
+        let word: String = self.iter(start..self.cursor).map(|(ch, _)| ch).collect();
+        let Some(expansion) = abbrs.get(&word) else {
+            return false;
+        };
+        self.delete_range(start, self.cursor);
+        for ch in expansion.chars() {
+            self.insert(ch);
+        }
+        true
     }
 
     pub fn delete_line(&mut self) {
@@ -163,17 +320,32 @@ impl Line {
         assert!(from <= to);
 
         let mut new_buf = Vec::new();
-        for (i, (ch, sz)) in self.buf.drain(..).enumerate() {
+        for (i, (g, sz)) in self.buf.drain(..).enumerate() {
             if from <= i && i < to {
                 continue;
             }
-            new_buf.push((ch, sz));
+            new_buf.push((g, sz));
         }
         std::mem::swap(&mut self.buf, &mut new_buf);
 
         self.cursor = from;
     }
 
+    /// Converts the characters in [from, to) to uppercase or lowercase
+    pub fn change_case(&mut self, from: usize, to: usize, upper: bool) {
+        let text: String = self.iter(from..to).map(|(g, _)| g).collect();
+        let text = if upper {
+            text.to_uppercase()
+        } else {
+            text.to_lowercase()
+        };
+        let tail = self.buf.split_off(to);
+        self.buf.truncate(from);
+        self.buf
+            .extend(graphemes(&text).map(|(g, w)| (g.to_owned(), w)));
+        self.buf.extend(tail);
+    }
+
     pub fn duplicate_current_word(&mut self) {
         let cursor_pos = self.cursor();
 
@@ -214,8 +386,8 @@ impl Line {
         let back = (word_end as isize - cursor_pos as isize).max(0);
         self.cursor_exact(word_end);
         self.insert(' ');
-        let chars: Vec<char> = self.iter(word_begin..word_end).map(|(ch, _)| ch).collect();
-        for ch in chars {
+        let word: String = self.iter(word_begin..word_end).map(|(g, _)| g).collect();
+        for ch in word.chars() {
             self.insert(ch);
         }
 
@@ -238,8 +410,8 @@ impl Line {
 
     pub fn cursor_prev_char_match(&mut self, target: char) {
         let mut i = self.cursor as isize - 1;
-        while i > 0 {
-            if self.buf[i as usize].0 == target {
+        while i >= 0 {
+            if self.base(i as usize) == target {
                 self.cursor = i as usize;
                 break;
             }
@@ -251,7 +423,7 @@ impl Line {
         let len = self.buf.len() as isize;
         let mut i = self.cursor as isize + 1;
         while i < len {
-            if self.buf[i as usize].0 == target {
+            if self.base(i as usize) == target {
                 self.cursor = i as usize;
                 break;
             }
@@ -259,9 +431,30 @@ impl Line {
         }
     }
 
+    /// Moves the cursor to just after the previous `target`.
+    /// If `repeat` is true, `target` just before the cursor is skipped.
+    pub fn cursor_prev_char_till(&mut self, target: char, repeat: bool) {
+        let skip = usize::from(repeat);
+        if let Some(i) = (0..self.cursor.saturating_sub(skip))
+            .rev()
+            .find(|&i| self.base(i) == target)
+        {
+            self.cursor = i + 1;
+        }
+    }
+
+    /// Moves the cursor to just before the next `target`.
+    /// If `repeat` is true, `target` just after the cursor is skipped.
+    pub fn cursor_next_char_till(&mut self, target: char, repeat: bool) {
+        let skip = 1 + usize::from(repeat);
+        if let Some(i) = (self.cursor + skip..self.buf.len()).find(|&i| self.base(i) == target) {
+            self.cursor = i - 1;
+        }
+    }
+
     pub fn cursor_prev_word_head(&mut self, wide: bool) {
         while self.cursor > 0 {
-            let prev_class = CharClass::from(self.buf[self.cursor - 1].0);
+            let prev_class = CharClass::from(self.base(self.cursor - 1));
             if !prev_class.is_whitespace() {
                 break;
             }
@@ -272,9 +465,9 @@ impl Line {
             return;
         }
 
-        let word_class = CharClass::from(self.buf[self.cursor - 1].0);
+        let word_class = CharClass::from(self.base(self.cursor - 1));
         while self.cursor > 0 {
-            let prev_class = CharClass::from(self.buf[self.cursor - 1].0);
+            let prev_class = CharClass::from(self.base(self.cursor - 1));
             if !CharClass::is_same(wide, prev_class, word_class) {
                 break;
             }
@@ -282,6 +475,16 @@ impl Line {
         }
     }
 
+    /// Returns true if the cursor is on the first character of a word
+    pub fn at_word_head(&self) -> bool {
+        let Some(ch) = self.char_at(self.cursor) else {
+            return false;
+        };
+        let class = CharClass::from(ch);
+        !class.is_whitespace()
+            && (self.cursor == 0 || CharClass::from(self.base(self.cursor - 1)) != class)
+    }
+
     pub fn cursor_next_word_head(&mut self, wide: bool) {
         let len = self.buf.len();
 
@@ -289,9 +492,9 @@ impl Line {
             return;
         }
 
-        let word_class = CharClass::from(self.buf[self.cursor].0);
+        let word_class = CharClass::from(self.base(self.cursor));
         while self.cursor + 1 < len {
-            let class = CharClass::from(self.buf[self.cursor].0);
+            let class = CharClass::from(self.base(self.cursor));
             if !CharClass::is_same(wide, class, word_class) {
                 break;
             }
@@ -299,7 +502,7 @@ impl Line {
         }
 
         while self.cursor + 1 < len {
-            if !CharClass::from(self.buf[self.cursor].0).is_whitespace() {
+            if !CharClass::from(self.base(self.cursor)).is_whitespace() {
                 break;
             }
             self.cursor += 1;
@@ -312,7 +515,7 @@ impl Line {
         let len = self.buf.len();
 
         while self.cursor + 1 < len {
-            if !CharClass::from(self.buf[self.cursor].0).is_whitespace() {
+            if !CharClass::from(self.base(self.cursor)).is_whitespace() {
                 break;
             }
             self.cursor += 1;
@@ -322,9 +525,9 @@ impl Line {
             return;
         }
 
-        let word_class = CharClass::from(self.buf[self.cursor].0);
+        let word_class = CharClass::from(self.base(self.cursor));
         while self.cursor + 1 < len {
-            let next_class = CharClass::from(self.buf[self.cursor + 1].0);
+            let next_class = CharClass::from(self.base(self.cursor + 1));
             if !CharClass::is_same(wide, next_class, word_class) {
                 break;
             }
@@ -332,6 +535,33 @@ impl Line {
         }
     }
 
+    pub fn cursor_prev_word_end(&mut self, wide: bool) {
+        let mut i = self.cursor;
+
+        // leave the word under the cursor
+        if let Some(ch) = self.char_at(i) {
+            let word_class = CharClass::from(ch);
+            if !word_class.is_whitespace() {
+                while i > 0 {
+                    let prev_class = CharClass::from(self.base(i - 1));
+                    if !CharClass::is_same(wide, prev_class, word_class) {
+                        break;
+                    }
+                    i -= 1;
+                }
+            }
+        }
+
+        while i > 0 {
+            if !CharClass::from(self.base(i - 1)).is_whitespace() {
+                break;
+            }
+            i -= 1;
+        }
+
+        self.cursor = i.saturating_sub(1);
+    }
+
     pub fn cursor_end_of_line(&mut self) {
         self.cursor = self.buf.len();
     }
@@ -341,7 +571,7 @@ impl Line {
 
         self.cursor = 0;
         while self.cursor < len {
-            if !self.buf[self.cursor].0.is_whitespace() {
+            if !self.base(self.cursor).is_whitespace() {
                 break;
             }
             self.cursor += 1;
@@ -358,3 +588,173 @@ impl Line {
         }
     }
 }
+
+/// `UndoHistory` keeps snapshots of a line to undo and redo its changes
+#[derive(Debug, Clone, Default)]
+pub struct UndoHistory {
+    undo: Vec<Line>,
+    redo: Vec<Line>,
+}
+
+impl UndoHistory {
+    /// Saves `line` as a state which can be restored by `undo`
+    pub fn checkpoint(&mut self, line: &Line) {
+        // the cursor doesn't matter to a checkpoint
+        if self.undo.last().is_none_or(|last| last.buf != line.buf) {
+            self.undo.push(line.clone());
+        }
+        self.redo.clear();
+    }
+
+    pub fn undo(&mut self, current: &Line) -> Option<Line> {
+        let mut line = self.undo.pop()?;
+        if line.buf == current.buf {
+            // nothing has changed since the checkpoint
+            line = self.undo.pop().unwrap_or(line);
+        }
+        self.redo.push(current.clone());
+        Some(line)
+    }
+
+    pub fn redo(&mut self, current: &Line) -> Option<Line> {
+        let line = self.redo.pop()?;
+        self.undo.push(current.clone());
+        Some(line)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_redo() {
+        let mut history = UndoHistory::default();
+        let mut line = Line::new();
+        history.checkpoint(&line);
+
+        line.insert('a');
+        history.checkpoint(&line);
+        line.insert('b');
+
+        let line = history.undo(&line).unwrap();
+        assert_eq!(line.to_string(), "a");
+        let line = history.undo(&line).unwrap();
+        assert_eq!(line.to_string(), "");
+        assert!(history.undo(&line).is_none());
+
+        let line = history.redo(&line).unwrap();
+        assert_eq!(line.to_string(), "a");
+        let line = history.redo(&line).unwrap();
+        assert_eq!(line.to_string(), "ab");
+        assert!(history.redo(&line).is_none());
+    }
+
+    #[test]
+    fn abbreviation() {
+        let abbrs: HashMap<_, _> = [("gco".to_owned(), "git checkout".to_owned())]
+            .into_iter()
+            .collect();
+
+        let mut line = Line::from("gco");
+        line.cursor_end_of_line();
+        assert!(line.expand_abbreviation(&abbrs));
+        assert_eq!(line.to_string(), "git checkout");
+        assert_eq!(line.cursor(), 12);
+
+        let mut line = Line::from("ls && gco");
+        line.cursor_end_of_line();
+        assert!(line.expand_abbreviation(&abbrs));
+        assert_eq!(line.to_string(), "ls && git checkout");
+
+        // only commands are expanded
+        let mut line = Line::from("echo gco");
+        line.cursor_end_of_line();
+        assert!(!line.expand_abbreviation(&abbrs));
+        assert_eq!(line.to_string(), "echo gco");
+    }
+
+    #[test]
+    fn graphemes() {
+        // "e" + combining acute accent, and a family emoji joined with ZWJs
+        let mut line = Line::from("cafe\u{301} \u{1f468}\u{200d}\u{1f469}\u{200d}\u{1f467}");
+        assert_eq!(line.len(), 6);
+        assert_eq!(line.iter(..).map(|(_, w)| w).sum::<usize>(), 7);
+
+        line.cursor_end_of_line();
+        line.delete_prev();
+        assert_eq!(line.to_string(), "cafe\u{301} ");
+        line.cursor_exact(4);
+        line.delete_prev();
+        assert_eq!(line.to_string(), "caf ");
+
+        // typed characters join the cluster before the cursor
+        line.cursor_end_of_line();
+        for ch in "\u{2764}\u{fe0f}".chars() {
+            line.insert(ch);
+        }
+        assert_eq!(line.len(), 5);
+        assert_eq!(line.iter(4..).next(), Some(("\u{2764}\u{fe0f}", 2)));
+    }
+
+    #[test]
+    fn transpose() {
+        let mut line = Line::from("teh");
+        line.cursor_exact(2);
+        line.transpose_chars();
+        assert_eq!((line.to_string().as_str(), line.cursor()), ("the", 3));
+        line.transpose_chars();
+        assert_eq!((line.to_string().as_str(), line.cursor()), ("teh", 3));
+
+        let mut line = Line::from("cp foo, bar");
+        line.cursor_exact(5);
+        line.transpose_words();
+        assert_eq!(
+            (line.to_string().as_str(), line.cursor()),
+            ("cp bar, foo", 11)
+        );
+        line.transpose_words();
+        assert_eq!(
+            (line.to_string().as_str(), line.cursor()),
+            ("cp foo, bar", 11)
+        );
+        line.cursor_exact(2);
+        line.transpose_words();
+        assert_eq!(
+            (line.to_string().as_str(), line.cursor()),
+            ("foo cp, bar", 6)
+        );
+    }
+
+    #[test]
+    fn command_ranges() {
+        let line = Line::from("ls -l | grep foo;  cd ..&&(make)");
+        assert_eq!(line.command_ranges(), [(0, 2), (8, 12), (19, 21), (27, 31)]);
+        assert_eq!(Line::from("  ").command_ranges(), []);
+    }
+
+    #[test]
+    fn next_word_end() {
+        let mut line = Line::from("ls  foo.bar");
+        line.cursor_exact(2);
+        assert_eq!(line.next_word_end(), 7);
+        line.cursor_exact(7);
+        assert_eq!(line.next_word_end(), 8);
+        line.cursor_end_of_line();
+        assert_eq!(line.next_word_end(), 11);
+    }
+
+    #[test]
+    fn prev_word_end() {
+        let mut line = Line::from("ls foo.bar  baz");
+        line.cursor_exact(13);
+        line.cursor_prev_word_end(false);
+        assert_eq!(line.cursor(), 9);
+        line.cursor_prev_word_end(false);
+        assert_eq!(line.cursor(), 6);
+        line.cursor_prev_word_end(true);
+        assert_eq!(line.cursor(), 1);
+        line.cursor_prev_word_end(true);
+        assert_eq!(line.cursor(), 0);
+    }
+}
@@ -1,33 +1,36 @@
+mod clipboard;
+mod fuzzy_finder;
+mod history;
+mod input;
+mod keymap;
 mod line;
 mod modes;
+mod render;
 mod text_object;
 
 use nix::errno::Errno;
 use nix::libc::STDIN_FILENO;
 use nix::sys::termios;
 use nix::unistd;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::io::{stdout, Write as _};
+use std::os::unix::io::RawFd;
 
 use crate::completion;
 use crate::terminal_size;
+use history::*;
+use input::{
+    disable_kitty_keyboard, enable_kitty_keyboard, query_cursor_position, query_kitty_keyboard,
+    InputParser,
+};
+pub use keymap::{Action, Event, Keymap, KeymapMode};
 use line::*;
 use modes::*;
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Event {
-    KeyEscape,
-    KeyTab,
-    KeyBackspace,
-    KeyDelete,
-    KeyReturn,
-    KeyUp,
-    KeyDown,
-    KeyLeft,
-    KeyRight,
-    Ctrl(char),
-    Char(char),
-}
+const KILL_RING_SIZE: usize = 16;
+
+/// The placeholder in the prompt replaced with the name of the current mode on every redraw
+pub const MODE_INDICATOR: &str = "\0";
 
 #[derive(Debug, Clone, PartialEq)]
 enum Command {
@@ -35,43 +38,118 @@ enum Command {
     CursorPrevCharMatch(char),
     CursorNextChar,
     CursorNextCharMatch(char),
+    CursorPrevCharTill {
+        target: char,
+        repeat: bool,
+    },
+    CursorNextCharTill {
+        target: char,
+        repeat: bool,
+    },
     CursorPrevWordHead,
     CursorPrevWordHeadWide,
     CursorNextWordHead,
     CursorNextWordHeadWide,
     CursorNextWordEnd,
     CursorNextWordEndWide,
+    CursorPrevWordEnd,
+    CursorPrevWordEndWide,
+    CursorMatchingPair,
     CursorEnd,
     CursorBegin,
     CursorExact(usize),
     HistoryPrev,
     HistoryNext,
-    HistorySearch { query: String, reset: bool },
+    HistorySearch {
+        query: String,
+        backward: bool,
+        next: bool,
+    },
     DeletePrevChar,
     DeleteNextChar,
-    DeletePrevWord,
     DeleteLine,
-    DeleteRange { from: usize, to: usize },
+    DeleteRange {
+        from: usize,
+        to: usize,
+    },
+    ChangeCase {
+        from: usize,
+        to: usize,
+        upper: bool,
+    },
     DuplicateWord,
+    ToggleSudo,
+    TransposeChars,
+    TransposeWords,
     Commit,
+    GetNextHistory,
     ChangeModeToInsert,
     ChangeModeToNormal,
     ChangeModeToVisualChar,
     ChangeModeToVisualLine,
-    ChangeModeToSearch,
+    ChangeModeToSearch {
+        backward: bool,
+    },
     Insert(char),
-    RegisterStore { reg: char, text: String },
-    RegisterPastePrev { reg: char },
-    RegisterPasteNext { reg: char },
+    RegisterStore {
+        reg: char,
+        text: String,
+    },
+    RegisterPastePrev {
+        reg: char,
+    },
+    RegisterPasteNext {
+        reg: char,
+    },
+    Yank,
+    YankPop,
+    RepeatChange,
+    StartMacro(char),
+    PlayMacro(char),
     MakeCheckPoint,
     Undo,
     Redo,
     TryCompleteFilename,
     DisplayCompletionCandidate,
+    SelectCandidate(isize),
     CdToParent,
     CdUndo,
     CdRedo,
     ClearScreen,
+    FuzzyFind {
+        history: bool,
+    },
+}
+
+/// Shows messages above the prompt while a line is edited, e.g. about finished jobs
+pub struct Notifier<'a> {
+    /// becomes readable when there may be messages
+    pub fd: Option<RawFd>,
+    pub messages: &'a mut dyn FnMut() -> Vec<String>,
+}
+
+/// The styles of the line as SGR sequences
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Colors {
+    /// command names which are found
+    pub command: String,
+    /// command names which are not found
+    pub unknown_command: String,
+    /// the text matched by the history search
+    pub highlight: String,
+    /// the suggested rest of a previous command
+    pub suggestion: String,
+}
+
+impl Default for Colors {
+    fn default() -> Self {
+        Self {
+            command: "\x1b[32m".to_owned(),
+            unknown_command: "\x1b[31m".to_owned(),
+            highlight: "\x1b[100;97m".to_owned(),
+            suggestion: "\x1b[2m".to_owned(),
+        }
+    }
 }
 
 pub enum EditError {
@@ -82,21 +160,58 @@ pub enum EditError {
 pub struct LineEditor {
     mode: Mode,
     registers: HashMap<char, String>,
-    line_history: Vec<Line>,
+    /// recently killed texts, the latest first
+    kill_ring: VecDeque<String>,
+    /// keys of the last change made in the normal mode, repeated by `.`
+    last_change: Vec<Event>,
+    /// keys recorded by `q<reg>` and replayed by `@<reg>`
+    macros: HashMap<char, Vec<Event>>,
+    /// the register and keys of the macro being recorded
+    recording_macro: Option<(char, Vec<Event>)>,
+    last_macro: Option<char>,
+    line_history: History,
+    /// index of the history entry whose command is being executed
+    running_entry: Option<usize>,
+    suggestion_index: PrefixIndex,
     pub command_completion: Box<completion::CommandCompletion>,
+    pub keymap: Keymap,
+    pub abbreviations: HashMap<String, String>,
+    pub colors: Colors,
+    /// whether quotes and brackets are closed automatically
+    pub autopair: bool,
+    /// whether clicks and the wheel are reported by the terminal
+    pub mouse: bool,
+    /// the command run by the fuzzy finder widgets instead of fzf
+    pub fuzzy_finder: Option<String>,
+    /// whether the terminal supports the kitty keyboard protocol, once it is asked
+    kitty_keyboard: Option<bool>,
+    /// the line which the next `read_line` starts with
+    initial_line: Option<Line>,
+    /// index of the history entry which the next `read_line` starts with
+    next_entry: Option<usize>,
 }
 
 impl Drop for LineEditor {
     fn drop(&mut self) {
-        if let Err(err) = save_history(&self.line_history) {
+        if let Err(err) = self.line_history.save() {
             eprintln!("Failed to save history: {err}");
         }
     }
 }
 
+impl Default for LineEditor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LineEditor {
     pub fn new() -> Self {
-        let line_history = load_history().unwrap_or_default();
+        let line_history = match crate::application_dir() {
+            Some(dir) => History::open(dir.join("history"), RECENT_ENTRIES),
+            None => History::default(),
+        };
+        let suggestion_index = PrefixIndex::new(&line_history);
 
         use completion::{CommandCompletion, FileCompletion};
         let command_completion = Box::new(CommandCompletion::new(
@@ -107,48 +222,187 @@ impl LineEditor {
         Self {
             mode: Mode::Insert(InsertMode::default()),
             registers: HashMap::new(),
+            kill_ring: VecDeque::new(),
+            last_change: Vec::new(),
+            macros: HashMap::new(),
+            recording_macro: None,
+            last_macro: None,
             line_history,
+            running_entry: None,
+            suggestion_index,
             command_completion,
+            keymap: Keymap::default(),
+            abbreviations: HashMap::new(),
+            colors: Colors::default(),
+            autopair: false,
+            mouse: false,
+            fuzzy_finder: None,
+            kitty_keyboard: None,
+            initial_line: None,
+            next_entry: None,
         }
     }
 
-    pub fn read_line(&mut self, prompt_prefix: String) -> Result<String, EditError> {
+    pub fn read_line(
+        &mut self,
+        prompt_prefix: String,
+        notifier: Notifier,
+    ) -> Result<String, EditError> {
         let saved_termios = enable_raw_mode();
 
+        // the terminal is asked only once
+        let mut typeahead = Vec::new();
+        let kitty_keyboard = *self.kitty_keyboard.get_or_insert_with(|| {
+            let (supported, typed) = query_kitty_keyboard();
+            typeahead = typed;
+            supported
+        });
+        if kitty_keyboard {
+            enable_kitty_keyboard();
+        }
+        let mouse = self.mouse;
+        if mouse {
+            print!("\x1b[?1000h\x1b[?1006h"); // SGR mouse mode
+        }
+
+        // (row of the cursor, last row) counted from the first row of the prompt
+        let rows = std::cell::Cell::new((0_usize, 0_usize));
+        // (width of the prompt, first row of the completion menu) to locate clicks
+        let layout = std::cell::Cell::new((0_usize, 0_usize));
+        // what is shown now, to print only the difference; None to draw all again
+        let rendered: std::cell::RefCell<Option<render::Frame>> = Default::default();
+
         let _defer = crate::utils::Defer::new(|| {
             let now = termios::SetArg::TCSANOW;
             let _ = termios::tcsetattr(STDIN_FILENO, now, &saved_termios);
+            if kitty_keyboard {
+                disable_kitty_keyboard();
+            }
+            if mouse {
+                print!("\x1b[?1006l\x1b[?1000l");
+            }
 
             print!("\x1b[2 q"); // block cursor
+            let (cursor_row, last_row) = rows.get();
+            if last_row > cursor_row {
+                print!("\x1b[{}B", last_row - cursor_row);
+            }
             print!("\r\n\x1b[J");
             stdout().flush().unwrap();
         });
 
         self.new_line();
 
-        let mut temporal: Vec<Line> = Vec::new();
+        // lines being edited, each of which has its own undo history
+        let mut temporal: Vec<(Line, UndoHistory)> = Vec::new();
         let mut row: isize = 0;
         let mut history_search_start_idx: usize = 0;
 
-        let mut undo_stack: Vec<Line> = Vec::new();
-        let mut redo_stack: Vec<Line> = Vec::new();
+        // index of the history entry loaded by operate-and-get-next, unless prefilled
+        let mut recalled = self
+            .next_entry
+            .take()
+            .filter(|_| self.initial_line.is_none());
+        if let Some(i) = recalled {
+            let mut line = self.line_history[i].line.clone();
+            line.cursor_end_of_line();
+            self.initial_line = Some(line);
+        }
 
         {
-            let line = Line::new();
-            temporal.push(line.clone());
-
+            let line = self.initial_line.take().unwrap_or_else(Line::new);
+            let mut undo = UndoHistory::default();
             if self.mode.is_insert() {
-                undo_stack.push(line);
+                undo.checkpoint(&line);
             }
+            temporal.push((line, undo));
         }
 
-        let mut last_command = Command::Commit;
+        // index of the history entry found by the incremental search
+        let mut search_matched: Option<usize> = None;
+
+        let mut change_keys: Vec<Event> = Vec::new();
+        let mut recording_change = false;
+
+        // range of the text inserted by the last yank
+        let mut yanked: Option<(usize, usize)> = None;
+
+        // prefix which the history navigation with Up/Down is limited to
+        let mut history_prefix: Option<String> = None;
+
+        // the line is printed without any decoration once it is accepted
+        let mut accepted = false;
+
         let mut completion = CompletionEngine::new(&*self.command_completion);
 
         macro_rules! current_line {
             () => {{
                 let len = temporal.len() as isize;
-                temporal.get_mut((len - 1 + row) as usize).unwrap()
+                &mut temporal.get_mut((len - 1 + row) as usize).unwrap().0
+            }};
+        }
+
+        macro_rules! current_undo {
+            () => {{
+                let len = temporal.len() as isize;
+                &mut temporal.get_mut((len - 1 + row) as usize).unwrap().1
+            }};
+        }
+
+        // shifts the indices of the history entries after `n` older ones are read
+        macro_rules! older_history_read {
+            ($n:expr) => {{
+                let n: usize = $n;
+                history_search_start_idx += n;
+                for i in [&mut search_matched, &mut recalled].into_iter().flatten() {
+                    *i += n;
+                }
+            }};
+        }
+
+        // moves to the previous line in the history, returns false at the oldest one
+        macro_rules! history_prev {
+            () => {{
+                let new_row = row - 1;
+                let mut moved = false;
+                if temporal.len() as isize - 1 + new_row >= 0 {
+                    row = new_row;
+                    moved = true;
+                } else {
+                    // copy from line_history
+                    let mut i = self.line_history.len() as isize + new_row;
+                    if i < 0 {
+                        let read = self.line_history.load_older(CHUNK_ENTRIES);
+                        older_history_read!(read);
+                        i += read as isize;
+                    }
+                    if i >= 0 {
+                        let picked_line = self.line_history[i as usize].line.clone();
+                        let mut undo = UndoHistory::default();
+                        if self.mode.is_insert() {
+                            undo.checkpoint(&picked_line);
+                        }
+                        temporal.insert(0, (picked_line, undo));
+                        row = new_row;
+                        moved = true;
+                    }
+                }
+                if moved {
+                    current_line!().cursor_end_of_line();
+                }
+                moved
+            }};
+        }
+
+        // moves to the next line in the history, returns false at the line being edited
+        macro_rules! history_next {
+            () => {{
+                let moved = row < 0;
+                if moved {
+                    row += 1;
+                    current_line!().cursor_end_of_line();
+                }
+                moved
             }};
         }
 
@@ -170,14 +424,11 @@ impl LineEditor {
                     "%"
                 };
 
+                let prompt_prefix = prompt_prefix.replace(MODE_INDICATOR, self.mode.name());
                 let (prompt, prompt_length) = Self::unescape_prompt(&format!(
                     "{prompt_prefix}({color}){prompt_sign}(\x1b[m) "
                 ));
 
-                print!("\x1b8"); // Restore cursor
-                print!("\x1b[K"); // Erase lines
-                print!("{prompt}"); // Prompt
-
                 let hl_range = match &self.mode {
                     Mode::Visual(vis_mode) => {
                         if let Some(origin) = vis_mode.origin() {
@@ -196,8 +447,8 @@ impl LineEditor {
                         // FIXME
                         let s = line.to_string();
                         if let Some(i) = s.find(&query) {
-                            let from = s[..i].chars().count();
-                            let len = query.chars().count();
+                            let from = graphemes(&s[..i]).count();
+                            let len = graphemes(&query).count();
                             let to = from + len;
                             Some((from, to))
                         } else {
@@ -207,15 +458,31 @@ impl LineEditor {
                     _ => None,
                 };
 
-                let terminal_width = terminal_size::get_cols() as usize;
-                let mut line_length = prompt_length;
+                let terminal_width = terminal_width();
 
-                for (i, (ch, width)) in line.iter(..).enumerate() {
-                    line_length += width;
-                    if line_length > terminal_width {
-                        break;
-                    }
+                // command names are green if they are found, red otherwise
+                let command_colors: Vec<(usize, usize, &str)> = if accepted {
+                    Vec::new()
+                } else {
+                    line.command_ranges()
+                        .into_iter()
+                        .map(|(from, to)| {
+                            let name: String = line.iter(from..to).map(|(g, _)| g).collect();
+                            let found = self.command_completion.is_command(&name);
+                            let color = if found {
+                                &self.colors.command
+                            } else {
+                                &self.colors.unknown_command
+                            };
+                            (from, to, color.as_str())
+                        })
+                        .collect()
+                };
+
+                // all the printed characters except the prompt
+                let mut cells: Vec<render::Cell> = Vec::new();
 
+                for (i, (ch, width)) in line.iter(..).enumerate() {
                     let mut highlight = false;
                     if let Some(hl) = hl_range {
                         if hl.0 <= i && i < hl.1 {
@@ -223,125 +490,264 @@ impl LineEditor {
                         }
                     }
 
-                    if highlight {
-                        print!("\x1b[100;97m{ch}\x1b[m");
+                    // control characters in caret notation like "^I"
+                    let text = match ch.chars().next() {
+                        Some(c) if c.is_ascii_control() => format!("^{}", (c as u8 ^ 0x40) as char),
+                        _ => ch.to_owned(),
+                    };
+
+                    let color = command_colors
+                        .iter()
+                        .find(|(from, to, _)| *from <= i && i < *to)
+                        .map(|(_, _, color)| *color);
+                    let style = if highlight {
+                        self.colors.highlight.clone()
                     } else {
-                        print!("{ch}");
-                    }
+                        color.unwrap_or("").to_owned()
+                    };
+                    cells.push(render::Cell { style, text, width });
+                }
+
+                let styled = |text: &str, style: &str| -> Vec<render::Cell> {
+                    graphemes(text)
+                        .map(|(g, width)| render::Cell {
+                            style: style.to_owned(),
+                            text: g.to_owned(),
+                            width,
+                        })
+                        .collect()
+                };
+
+                // suggest the rest of a previous command
+                let suggestion = self.suggestion(&line);
+                if let Some(rest) = suggestion.filter(|_| !accepted && !completion.is_active()) {
+                    cells.extend(styled(rest, &self.colors.suggestion));
                 }
 
-                print!("\x1b8");
-                let cursor_step =
-                    prompt_length + line.iter(..).take(line.cursor()).fold(0, |a, (_, w)| a + w);
-                if cursor_step > 0 {
-                    print!("\x1b[{}C", cursor_step);
+                // show the position of the match and when the found command was run
+                if let Mode::Search(search_mode) = &self.mode {
+                    let query = search_mode.query();
+                    let direction = if search_mode.is_backward() {
+                        "reverse-i-search"
+                    } else {
+                        "i-search"
+                    };
+                    let meta = match search_matched {
+                        Some(i) => {
+                            let (nth, total) = match_position(&self.line_history, &query, i);
+                            match self.line_history[i].describe() {
+                                Some(desc) => format!("{direction} {nth}/{total}, {desc}"),
+                                None => format!("{direction} {nth}/{total}"),
+                            }
+                        }
+                        None if query.is_empty() => direction.to_owned(),
+                        None => format!("failing {direction}"),
+                    };
+                    cells.extend(styled(&format!("  ({meta})"), "\x1b[2m"));
                 }
 
+                let frame = render::Frame {
+                    prompt,
+                    prompt_width: prompt_length,
+                    cells,
+                    // the completion menu below the line
+                    menu: completion.render(terminal_width),
+                    cursor: line.cursor(),
+                    terminal_width,
+                };
+                let (cursor_row, _) = rows.get();
+                let (mut output, (cursor_row, last_row)) =
+                    render::render(rendered.borrow().as_ref(), &frame, cursor_row);
+                rows.set((cursor_row, last_row));
+                layout.set((prompt_length, last_row - frame.menu.len() + 1));
+                rendered.replace(Some(frame));
+
                 // change cursor shape
                 if self.mode.is_insert() {
-                    print!("\x1b[6 q"); // bar cursor
+                    output.push_str("\x1b[6 q"); // bar cursor
                 } else {
-                    print!("\x1b[2 q"); // block cursor
+                    output.push_str("\x1b[2 q"); // block cursor
                 }
 
-                stdout().flush().unwrap();
+                write_at_once(&output);
             }};
         }
 
-        // Save cursor
-        print!("\x1b7");
-        stdout().flush().unwrap();
+        // erases the completion menu before leaving
+        macro_rules! close_menu {
+            () => {{
+                if completion.is_active() {
+                    completion.clear();
+                    update_line!();
+                }
+            }};
+        }
 
         let mut read_buf = vec![0_u8; 32];
+        let mut input_parser = InputParser::new();
+        let mut events: VecDeque<Event> = VecDeque::new();
+        events.extend(input_parser.feed(&typeahead));
+        // number of the events at the front of `events` which are not typed but replayed
+        let mut replayed_events: usize = 0;
         'edit: loop {
-            update_line!();
+            if events.is_empty() {
+                update_line!();
+
+                let woken = wait_input(notifier.fd);
+                if woken == Wakeup::Notified {
+                    let messages = (notifier.messages)();
+                    if !messages.is_empty() {
+                        // the prompt is redrawn below the messages
+                        let (cursor_row, _) = rows.get();
+                        if cursor_row > 0 {
+                            print!("\x1b[{cursor_row}A");
+                        }
+                        print!("\r\x1b[J");
+                        for message in messages {
+                            print!("{message}\r\n");
+                        }
+                        rows.set((0, 0));
+                        rendered.replace(None);
+                    }
+                    continue;
+                }
+                if woken == Wakeup::Resized {
+                    // the rows printed with the old width are rewrapped when the terminal
+                    // shrinks, but they may not be joined when it grows
+                    let (prompt_width, _) = layout.get();
+                    let line = current_line!();
+                    let before_cursor = line.iter(..).take(line.cursor()).map(|(_, w)| w);
+                    let widths = std::iter::repeat_n(1, prompt_width).chain(before_cursor);
+                    let (rewrapped_row, _) = wrapped_position(widths, terminal_width());
+                    let (cursor_row, _) = rows.get();
+                    let cursor_row = cursor_row.max(rewrapped_row);
+                    rows.set((cursor_row, cursor_row));
+                    rendered.replace(None);
+                    continue;
+                }
 
-            let input = match unistd::read(STDIN_FILENO, &mut read_buf[..]) {
-                Ok(nb) => &read_buf[..nb],
-                Err(Errno::EINTR) => continue,
-                Err(err) => panic!("{err}"),
-            };
+                let input = match unistd::read(STDIN_FILENO, &mut read_buf[..]) {
+                    Ok(nb) => &read_buf[..nb],
+                    Err(Errno::EINTR) => continue,
+                    Err(err) => panic!("{err}"),
+                };
 
-            let mut event = Vec::new();
-
-            // TODO: implement a parser
-            if let Ok(input) = std::str::from_utf8(input) {
-                if input == "\x1b[D" {
-                    event.push(Event::KeyLeft);
-                } else if input == "\x1b[C" {
-                    event.push(Event::KeyRight);
-                } else if input == "\x1b[A" {
-                    event.push(Event::KeyUp);
-                } else if input == "\x1b[B" {
-                    event.push(Event::KeyDown);
-                } else if input == "\x1b[3~" {
-                    event.push(Event::KeyDelete);
-                } else {
-                    for ch in input.chars() {
-                        match ch {
-                            '\x00' => event.push(Event::Ctrl('@')),
-                            '\x01' => event.push(Event::Ctrl('a')),
-                            '\x02' => event.push(Event::Ctrl('b')),
-                            '\x03' => event.push(Event::Ctrl('c')),
-                            '\x04' => event.push(Event::Ctrl('d')),
-                            '\x05' => event.push(Event::Ctrl('e')),
-                            '\x06' => event.push(Event::Ctrl('f')),
-                            '\x07' => event.push(Event::Ctrl('g')),
-                            '\x08' => event.push(Event::Ctrl('h')),
-                            '\x09' => event.push(Event::KeyTab),
-                            '\x0a' => event.push(Event::Ctrl('j')),
-                            '\x0b' => event.push(Event::Ctrl('k')),
-                            '\x0c' => event.push(Event::Ctrl('l')),
-                            '\x0d' => event.push(Event::KeyReturn),
-                            '\x0e' => event.push(Event::Ctrl('n')),
-                            '\x0f' => event.push(Event::Ctrl('o')),
-                            '\x10' => event.push(Event::Ctrl('p')),
-                            '\x11' => event.push(Event::Ctrl('q')),
-                            '\x12' => event.push(Event::Ctrl('r')),
-                            '\x13' => event.push(Event::Ctrl('s')),
-                            '\x14' => event.push(Event::Ctrl('t')),
-                            '\x15' => event.push(Event::Ctrl('u')),
-                            '\x16' => event.push(Event::Ctrl('v')),
-                            '\x17' => event.push(Event::Ctrl('w')),
-                            '\x18' => event.push(Event::Ctrl('x')),
-                            '\x19' => event.push(Event::Ctrl('y')),
-                            '\x1A' => event.push(Event::Ctrl('z')),
-                            '\x1b' => event.push(Event::KeyEscape),
-                            '\x1c' => event.push(Event::Ctrl('\\')),
-                            '\x1d' => event.push(Event::Ctrl(']')),
-                            '\x1e' => event.push(Event::Ctrl('^')),
-                            '\x1f' => event.push(Event::Ctrl('_')),
-                            '\x7f' => event.push(Event::KeyBackspace),
-                            ch if ch.is_control() => {}
-                            _ => event.push(Event::Char(ch)),
-                        }
+                events.extend(input_parser.feed(input));
+
+                // a lone ESC is the Escape key unless the rest of a sequence follows soon
+                if input_parser.is_pending() && !input_ready(ESCAPE_TIMEOUT_MS) {
+                    events.extend(input_parser.flush());
+                }
+            }
+
+            let Some(ev) = events.pop_front() else {
+                continue;
+            };
+            let replayed = replayed_events > 0;
+            replayed_events = replayed_events.saturating_sub(1);
+
+            // record the typed keys into the macro
+            if !replayed {
+                if let Some((reg, keys)) = &mut self.recording_macro {
+                    let stop = self.mode.is_normal_idle()
+                        && self.keymap.lookup(KeymapMode::Normal, ev)
+                            == Some(Action::ViRecordMacro);
+                    if stop {
+                        self.macros.insert(*reg, std::mem::take(keys));
+                        self.recording_macro = None;
+                        continue;
                     }
+                    keys.push(ev);
                 }
             }
 
+            // record the keys typed for a change made in the normal mode
+            let in_normal = matches!(self.mode, Mode::Normal(..));
+            if self.mode.is_normal_idle() {
+                change_keys.clear();
+            }
+            if in_normal || recording_change {
+                change_keys.push(ev);
+            }
+
             let mut commands = Vec::new();
-            for ev in event {
-                match (&mut self.mode, ev) {
-                    (_, Event::Ctrl('c')) => {
-                        return Err(EditError::Aborted);
-                    }
-                    (_, Event::Ctrl('d')) if current_line!().len() == 0 => {
-                        return Err(EditError::Exitted);
-                    }
+            match (&mut self.mode, ev) {
+                // the key following Ctrl-V is inserted as is
+                (Mode::Insert(mode), ev) if mode.is_quoting() => {
+                    mode.process_event(ev, current_line!(), &self.keymap, &mut commands);
+                }
 
-                    (Mode::Insert(mode), ev) => {
-                        mode.process_event(ev, current_line!(), &mut commands);
-                    }
-                    (Mode::Normal(mode), ev) => {
-                        mode.process_event(ev, current_line!(), &mut commands);
-                    }
-                    (Mode::Visual(mode), ev) => {
-                        mode.process_event(ev, current_line!(), &mut commands);
-                    }
-                    (Mode::Search(mode), ev) => {
-                        mode.process_event(ev, current_line!(), &mut commands);
+                (_, Event::Ctrl('c')) => {
+                    close_menu!();
+                    return Err(EditError::Aborted);
+                }
+                (_, Event::Ctrl('d')) if current_line!().len() == 0 => {
+                    close_menu!();
+                    return Err(EditError::Exitted);
+                }
+
+                // arrow keys move the selection while the menu is shown
+                (
+                    Mode::Insert(..),
+                    Event::KeyLeft | Event::KeyRight | Event::KeyUp | Event::KeyDown,
+                ) if completion.is_active() => {
+                    let columns = completion.columns(terminal_width()) as isize;
+                    let offset = match ev {
+                        Event::KeyLeft => -1,
+                        Event::KeyRight => 1,
+                        Event::KeyUp => -columns,
+                        _ => columns,
+                    };
+                    commands.push(Command::SelectCandidate(offset));
+                }
+
+                // a click moves the cursor or selects a candidate
+                (_, Event::MouseClick(col, row)) => {
+                    let (position, typed) = query_cursor_position();
+                    events.extend(input_parser.feed(&typed));
+
+                    let (cursor_row, _) = rows.get();
+                    let (prompt_width, menu_top) = layout.get();
+                    let terminal_width = terminal_width();
+                    // counted from the first row of the prompt
+                    let row = position
+                        .and_then(|(abs_row, _)| (row as usize + cursor_row).checked_sub(abs_row));
+                    match row {
+                        Some(row) if row >= menu_top => {
+                            let item =
+                                completion.item_at(terminal_width, row - menu_top, col.into());
+                            if let Some(i) = item {
+                                commands.push(Command::SelectCandidate(completion.offset_to(i)));
+                            }
+                        }
+                        Some(row) => {
+                            let line = current_line!();
+                            let widths = std::iter::repeat_n(1, prompt_width)
+                                .chain(line.iter(..).map(|(_, w)| w));
+                            let i = char_index_at(widths, terminal_width, (row, col.into()));
+                            let pos = i.saturating_sub(prompt_width).min(line.len());
+                            commands.push(Command::CursorExact(pos));
+                        }
+                        None => {}
                     }
                 }
+
+                (Mode::Insert(mode), ev) => {
+                    mode.process_event(ev, current_line!(), &self.keymap, &mut commands);
+                }
+                (Mode::Normal(mode), ev) => {
+                    mode.process_event(ev, current_line!(), &self.keymap, &mut commands);
+                }
+                (Mode::Visual(mode), ev) => {
+                    mode.process_event(ev, current_line!(), &self.keymap, &mut commands);
+                }
+                (Mode::Search(mode), ev) => {
+                    mode.process_event(ev, current_line!(), &self.keymap, &mut commands);
+                }
+            }
+
+            if in_normal && commands.contains(&Command::MakeCheckPoint) {
+                recording_change = true;
             }
 
             for cmd in commands {
@@ -359,83 +765,80 @@ impl LineEditor {
                     Command::ChangeModeToVisualLine => {
                         self.mode = Mode::Visual(VisualMode::new_line());
                     }
-                    Command::ChangeModeToSearch => {
-                        self.mode = Mode::Search(SearchMode::new());
-                    }
-
-                    Command::HistoryPrev => {
-                        let new_row = row - 1;
-                        if temporal.len() as isize - 1 + new_row >= 0 {
-                            row = new_row;
-                            current_line!().cursor_end_of_line();
-                        } else {
-                            // copy from line_history
-                            let i = self.line_history.len() as isize + new_row;
-                            if i >= 0 {
-                                let picked_line = self.line_history[i as usize].clone();
-                                temporal.insert(0, picked_line);
-                                row = new_row;
-                                current_line!().cursor_end_of_line();
-                            }
-                        }
-                    }
-                    Command::HistoryNext => {
-                        if row < 0 {
-                            row += 1;
-                            current_line!().cursor_end_of_line();
-                        }
+                    Command::ChangeModeToSearch { backward } => {
+                        self.mode = Mode::Search(SearchMode::new(backward));
+                        history_search_start_idx = self.line_history.len();
+                        search_matched = None;
                     }
 
-                    Command::HistorySearch { query, reset } => {
-                        if reset {
-                            history_search_start_idx = self.line_history.len() - 1;
-                        }
-
-                        let mut matched = false;
-                        let idx = history_search_start_idx;
-
-                        for (i, h) in self.line_history[0..idx].iter().enumerate().rev() {
-                            let line = h.to_string();
-                            if let Some(pos) = line.find(&query) {
-                                row = 0;
-                                *current_line!() = h.clone();
-                                matched = true;
-                                history_search_start_idx = i;
-
-                                let pre = line[..pos].chars().count();
-                                let len = query.chars().count();
-                                current_line!().cursor_exact(pre + len);
-
-                                break;
+                    Command::HistoryPrev | Command::HistoryNext => {
+                        let backward = cmd == Command::HistoryPrev;
+                        // the prefix is fixed by the line when the navigation started
+                        let prefix = history_prefix
+                            .get_or_insert_with(|| current_line!().to_string())
+                            .clone();
+
+                        if prefix.is_empty() {
+                            if backward {
+                                history_prev!();
+                            } else {
+                                history_next!();
                             }
-                        }
-
-                        if !matched {
-                            for (i, h) in self.line_history[idx..].iter().enumerate().rev() {
-                                let line = h.to_string();
-                                if let Some(pos) = line.find(&query) {
-                                    row = 0;
-                                    *current_line!() = h.clone();
-                                    matched = true;
-                                    history_search_start_idx = i;
-
-                                    let pre = line[..pos].chars().count();
-                                    let len = query.chars().count();
-                                    current_line!().cursor_exact(pre + len);
+                        } else {
+                            let (orig_row, shown) = (row, current_line!().to_string());
+                            loop {
+                                let moved = if backward {
+                                    history_prev!()
+                                } else {
+                                    history_next!()
+                                };
+                                if !moved {
+                                    row = orig_row;
+                                    break;
+                                }
 
+                                // skip the entries same as the one shown
+                                let line = current_line!().to_string();
+                                if row == 0 || (line.starts_with(&prefix) && line != shown) {
                                     break;
                                 }
                             }
                         }
+                    }
 
-                        if !matched {
+                    Command::HistorySearch {
+                        query,
+                        backward,
+                        next,
+                    } => {
+                        let start = history_search_start_idx;
+                        let (found, read) =
+                            self.line_history.search(&query, start, backward, !next);
+                        older_history_read!(read);
+
+                        row = 0;
+                        if let Some((i, pos)) = found {
+                            history_search_start_idx = i;
+                            search_matched = Some(i);
+                            *current_line!() = self.line_history[i].line.clone();
+                            current_line!().cursor_exact(pos + graphemes(&query).count());
+                        } else {
+                            search_matched = None;
                             let mut line = Line::from(query.as_str());
                             line.cursor_end_of_line();
-                            row = 0;
                             *current_line!() = line;
                         }
                     }
 
+                    Command::CursorNextChar | Command::CursorEnd
+                        if self.suggestion(current_line!()).is_some() =>
+                    {
+                        let rest = self.suggestion(current_line!()).unwrap().to_owned();
+                        for ch in rest.chars() {
+                            current_line!().insert(ch);
+                        }
+                    }
+
                     Command::CursorPrevChar => current_line!().cursor_prev_char(),
                     Command::CursorNextChar => current_line!().cursor_next_char(),
                     Command::CursorPrevCharMatch(ch) => {
@@ -444,12 +847,26 @@ impl LineEditor {
                     Command::CursorNextCharMatch(ch) => {
                         current_line!().cursor_next_char_match(ch);
                     }
+                    Command::CursorPrevCharTill { target, repeat } => {
+                        current_line!().cursor_prev_char_till(target, repeat);
+                    }
+                    Command::CursorNextCharTill { target, repeat } => {
+                        current_line!().cursor_next_char_till(target, repeat);
+                    }
 
                     Command::CursorPrevWordHead => current_line!().cursor_prev_word_head(false),
                     Command::CursorPrevWordHeadWide => {
                         current_line!().cursor_prev_word_head(true);
                     }
-                    Command::CursorNextWordHead => current_line!().cursor_next_word_head(false),
+                    Command::CursorNextWordHead => {
+                        let insert = matches!(self.mode, Mode::Insert(..));
+                        let line = current_line!();
+                        line.cursor_next_word_head(false);
+                        // in the insert mode, the cursor goes past the last word
+                        if insert && !line.at_word_head() {
+                            line.cursor_end_of_line();
+                        }
+                    }
                     Command::CursorNextWordHeadWide => {
                         current_line!().cursor_next_word_head(true);
                     }
@@ -457,6 +874,16 @@ impl LineEditor {
                     Command::CursorNextWordEndWide => {
                         current_line!().cursor_next_word_end(true);
                     }
+                    Command::CursorPrevWordEnd => current_line!().cursor_prev_word_end(false),
+                    Command::CursorPrevWordEndWide => {
+                        current_line!().cursor_prev_word_end(true);
+                    }
+                    Command::CursorMatchingPair => {
+                        let line = current_line!();
+                        if let Some(pos) = text_object::find_matching_pair(line, line.cursor()) {
+                            line.cursor_exact(pos);
+                        }
+                    }
                     Command::CursorEnd => {
                         current_line!().cursor_end_of_line();
                     }
@@ -467,23 +894,180 @@ impl LineEditor {
                         current_line!().cursor_exact(pos);
                     }
 
-                    Command::Insert(ch) => current_line!().insert(ch),
+                    Command::Insert(ch) => {
+                        // a word typed in the insert mode can be undone one by one
+                        let line = current_line!();
+                        let word_end = line.cursor() > 0
+                            && ch.is_whitespace()
+                            && !line
+                                .char_at(line.cursor() - 1)
+                                .is_none_or(char::is_whitespace);
+                        if word_end && matches!(self.mode, Mode::Insert(..)) {
+                            let line = line.clone();
+                            current_undo!().checkpoint(&line);
+                            current_line!().expand_abbreviation(&self.abbreviations);
+                        }
+
+                        let autopair = self.autopair && matches!(self.mode, Mode::Insert(..));
+                        let line = current_line!();
+                        let prev = line.cursor().checked_sub(1).and_then(|i| line.char_at(i));
+                        let next = line.char_at(line.cursor());
+                        let closing = AUTO_PAIRS.iter().any(|&(_, close)| close == ch);
+                        if autopair && closing && next == Some(ch) {
+                            // type over the closing one inserted automatically
+                            line.cursor_next_char();
+                        } else {
+                            line.insert(ch);
+                            // no pair after a backslash or inside a word like "don't"
+                            let in_word = prev.is_some_and(|c| c == '\\' || c.is_alphanumeric());
+                            let pair = AUTO_PAIRS.iter().find(|&&(open, _)| open == ch);
+                            if let Some(&(_, close)) = pair.filter(|_| autopair && !in_word) {
+                                line.insert(close);
+                                line.cursor_prev_char();
+                            }
+                        }
+                    }
 
-                    Command::DeletePrevChar => current_line!().delete_prev(),
+                    Command::DeletePrevChar => {
+                        let autopair = self.autopair && matches!(self.mode, Mode::Insert(..));
+                        let line = current_line!();
+                        let prev = line.cursor().checked_sub(1).and_then(|i| line.char_at(i));
+                        let next = line.char_at(line.cursor());
+                        if autopair
+                            && AUTO_PAIRS
+                                .iter()
+                                .any(|&pair| (prev, next) == (Some(pair.0), Some(pair.1)))
+                        {
+                            line.delete_next();
+                        }
+                        line.delete_prev();
+                    }
                     Command::DeleteNextChar => current_line!().delete_next(),
-                    Command::DeletePrevWord => current_line!().delete_word(),
                     Command::DeleteLine => current_line!().delete_line(),
                     Command::DeleteRange { from, to } => current_line!().delete_range(from, to),
+                    Command::ChangeCase { from, to, upper } => {
+                        current_line!().change_case(from, to, upper);
+                    }
 
                     Command::DuplicateWord => current_line!().duplicate_current_word(),
+                    Command::TransposeChars => current_line!().transpose_chars(),
+                    Command::TransposeWords => current_line!().transpose_words(),
+                    Command::ToggleSudo => {
+                        // an empty line is filled with the last command
+                        let mut text = current_line!().to_string();
+                        if text.is_empty() {
+                            if let Some(entry) = self.line_history.last() {
+                                text = entry.line.to_string();
+                            }
+                        }
+                        let text = match text.strip_prefix("sudo ") {
+                            Some(rest) => rest.to_owned(),
+                            None => format!("sudo {text}"),
+                        };
+                        let mut line = Line::from(text.as_str());
+                        line.cursor_end_of_line();
+                        *current_line!() = line;
+                    }
 
-                    Command::Commit => break 'edit,
+                    Command::GetNextHistory => {
+                        let len = self.line_history.len();
+                        let shown = if row < 0 {
+                            Some((len as isize + row) as usize)
+                        } else {
+                            search_matched.or(recalled)
+                        };
+                        self.next_entry = shown.map(|i| i + 1).filter(|&i| i < len);
+                    }
+                    Command::Commit => {
+                        let line = current_line!();
+                        if matches!(self.mode, Mode::Insert(..)) && line.cursor() == line.len() {
+                            line.expand_abbreviation(&self.abbreviations);
+                        }
+                        break 'edit;
+                    }
 
                     Command::RegisterStore { reg, text } => {
-                        self.registers.insert(reg, text);
+                        let text = if reg.is_ascii_uppercase() {
+                            let reg = reg.to_ascii_lowercase();
+                            let appended = self.registers.entry(reg).or_default();
+                            appended.push_str(&text);
+                            appended.clone()
+                        } else {
+                            if reg == '+' {
+                                clipboard::copy(&text);
+                            } else if reg != '"' {
+                                self.registers.insert(reg, text.clone());
+                            }
+                            text
+                        };
+
+                        // the unnamed register holds the last stored text and is shared with the kill ring
+                        if !text.is_empty() {
+                            self.kill_ring.push_front(text.clone());
+                            self.kill_ring.truncate(KILL_RING_SIZE);
+                        }
+                        self.registers.insert('"', text);
+                    }
+                    Command::RepeatChange => {
+                        for ev in self.last_change.iter().rev() {
+                            events.push_front(*ev);
+                        }
+                        replayed_events += self.last_change.len();
+                    }
+                    Command::StartMacro(reg) => {
+                        // "qA" appends to the macro of "a"
+                        let keys = if reg.is_ascii_uppercase() {
+                            self.macros.remove(&reg.to_ascii_lowercase())
+                        } else {
+                            None
+                        };
+                        let reg = reg.to_ascii_lowercase();
+                        self.recording_macro = Some((reg, keys.unwrap_or_default()));
+                    }
+                    Command::PlayMacro(reg) => {
+                        let reg = if reg == '@' {
+                            self.last_macro
+                        } else {
+                            Some(reg.to_ascii_lowercase())
+                        };
+                        if let Some(keys) = reg.and_then(|reg| self.macros.get(&reg)) {
+                            for ev in keys.iter().rev() {
+                                events.push_front(*ev);
+                            }
+                            replayed_events += keys.len();
+                            self.last_macro = reg;
+                        }
+                    }
+                    Command::Yank => {
+                        if let Some(text) = self.registers.get(&'"') {
+                            let line = current_line!();
+                            let from = line.cursor();
+                            for ch in text.chars() {
+                                line.insert(ch);
+                            }
+                            yanked = Some((from, line.cursor()));
+                        }
+                    }
+                    Command::YankPop => {
+                        // replace the yanked text with the older one
+                        if let Some((from, to)) = yanked {
+                            if self.kill_ring.len() > 1 {
+                                self.kill_ring.rotate_left(1);
+                                let text = self.kill_ring[0].clone();
+
+                                let line = current_line!();
+                                line.delete_range(from, to);
+                                for ch in text.chars() {
+                                    line.insert(ch);
+                                }
+                                yanked = Some((from, line.cursor()));
+                                self.registers.insert('"', text);
+                            }
+                        }
                     }
                     Command::RegisterPastePrev { reg } => {
-                        if let Some(text) = self.registers.get(&reg) {
+                        let text = self.register_text(reg, &mut input_parser, &mut events);
+                        if let Some(text) = text {
                             let line = current_line!();
                             for ch in text.chars() {
                                 line.insert(ch);
@@ -491,7 +1075,8 @@ impl LineEditor {
                         }
                     }
                     Command::RegisterPasteNext { reg } => {
-                        if let Some(text) = self.registers.get(&reg) {
+                        let text = self.register_text(reg, &mut input_parser, &mut events);
+                        if let Some(text) = text {
                             let line = current_line!();
                             line.cursor_next_char();
                             for ch in text.chars() {
@@ -502,88 +1087,145 @@ impl LineEditor {
                     }
 
                     Command::MakeCheckPoint => {
-                        undo_stack.push(current_line!().clone());
-                        redo_stack.clear();
+                        let line = current_line!().clone();
+                        current_undo!().checkpoint(&line);
                     }
                     Command::Undo => {
-                        if let Some(line) = undo_stack.pop() {
-                            redo_stack.push(current_line!().clone());
+                        let current = current_line!().clone();
+                        if let Some(line) = current_undo!().undo(&current) {
                             *current_line!() = line;
                         }
                     }
                     Command::Redo => {
-                        if let Some(line) = redo_stack.pop() {
-                            undo_stack.push(current_line!().clone());
+                        let current = current_line!().clone();
+                        if let Some(line) = current_undo!().redo(&current) {
                             *current_line!() = line;
                         }
                     }
 
-                    Command::TryCompleteFilename => {
-                        let last_command_is_completion = last_command
-                            == Command::TryCompleteFilename
-                            || last_command == Command::DisplayCompletionCandidate;
-
-                        // contiguous TryCompleteFilename would not update the candidates
-                        if !last_command_is_completion || completion.cleared() {
-                            // update completion candidates
-                            completion.update(current_line!().to_string());
+                    Command::TryCompleteFilename | Command::SelectCandidate(_) => {
+                        let offset = match cmd {
+                            Command::SelectCandidate(offset) => offset,
+                            _ => 1,
+                        };
+
+                        let mut extended = false;
+                        if !completion.is_active() {
+                            completion.update(&current_line!().to_string());
+
+                            // the first Tab inserts the part common to all the candidates
+                            if cmd == Command::TryCompleteFilename {
+                                update_shown(current_line!(), &mut completion, |comp| {
+                                    extended = comp.extend_prefix();
+                                });
+                            }
                         }
-
-                        let line = current_line!();
-
-                        let last_completion_len =
-                            completion.prev().map(|l| l.chars().count()).unwrap_or(0);
-
-                        for _ in 0..last_completion_len {
-                            line.delete_prev();
+                        if !extended {
+                            update_shown(current_line!(), &mut completion, |comp| {
+                                comp.select(offset);
+                            });
                         }
 
-                        if let Some(cand) = completion.next() {
-                            for ch in cand.chars() {
-                                current_line!().insert(ch);
-                            }
-
-                            // commit it if there is only a single choice
-                            if completion.len() == 1 {
-                                completion.clear();
-                            }
+                        // commit it if there is only a single choice
+                        if completion.len() == 1 {
+                            completion.clear();
                         }
                     }
                     Command::DisplayCompletionCandidate => {
-                        // always update completion candidates
-                        completion.update(current_line!().to_string());
-
-                        if let Some(prefix) = current_line!().last_word(true) {
-                            print!("\r\n\x1b[J");
-                            for cand in completion.iter() {
-                                print!("{prefix}{cand}\t");
-                            }
-                            print!("\r\n");
-                            stdout().flush().unwrap();
-                        }
+                        // show the menu without selecting any candidate
+                        completion.update(&current_line!().to_string());
                     }
 
                     Command::CdToParent => {
                         // FIXME
-                        print!("\x1b[A");
-                        stdout().flush().unwrap();
+                        overwrite_prompt(&rows);
                         return Ok("cd ..".to_string());
                     }
                     Command::CdUndo => {
                         // FIXME
-                        print!("\x1b[A");
-                        stdout().flush().unwrap();
+                        overwrite_prompt(&rows);
                         return Ok("cd -".to_string());
                     }
                     Command::CdRedo => {
                         // FIXME
-                        print!("\x1b[A");
-                        stdout().flush().unwrap();
+                        overwrite_prompt(&rows);
                         return Ok("cd +".to_string());
                     }
 
                     Command::ClearScreen => {
-                        return Ok("clear".to_string());
+                        // the line is redrawn from the top-left corner
+                        print!("\x1b[H\x1b[2J");
+                        rows.set((0, 0));
+                        rendered.replace(None);
+                    }
+
+                    Command::FuzzyFind { history } => {
+                        let line = current_line!();
+                        let to = line.cursor();
+                        let (from, items) = if history {
+                            loop {
+                                match self.line_history.load_older(CHUNK_ENTRIES) {
+                                    0 => break,
+                                    read => older_history_read!(read),
+                                }
+                            }
+
+                            // the newest first without duplicates
+                            let mut seen = std::collections::HashSet::new();
+                            let items: Vec<String> = self
+                                .line_history
+                                .iter()
+                                .rev()
+                                .map(|entry| entry.line.to_string())
+                                .filter(|text| seen.insert(text.clone()))
+                                .collect();
+                            (0, items)
+                        } else {
+                            // the blank-separated word before the cursor
+                            let from = (0..to)
+                                .rev()
+                                .find(|&i| line.char_at(i).is_some_and(char::is_whitespace))
+                                .map_or(0, |i| i + 1);
+                            (from, fuzzy_finder::list_files(std::path::Path::new(".")))
+                        };
+                        let query: String = line.iter(from..to).map(|(g, _)| g).collect();
+
+                        // the finder runs in the cooked mode
+                        let now = termios::SetArg::TCSANOW;
+                        let _ = termios::tcsetattr(STDIN_FILENO, now, &saved_termios);
+                        if kitty_keyboard {
+                            disable_kitty_keyboard();
+                        }
+                        if mouse {
+                            print!("\x1b[?1006l\x1b[?1000l");
+                        }
+                        stdout().flush().unwrap();
+
+                        let finder = self.fuzzy_finder.as_deref();
+                        let finder = finder.unwrap_or(fuzzy_finder::DEFAULT_FINDER);
+                        let selected = fuzzy_finder::select(finder, &items, &query);
+                        rendered.replace(None);
+
+                        enable_raw_mode();
+                        if kitty_keyboard {
+                            enable_kitty_keyboard();
+                        }
+                        if mouse {
+                            print!("\x1b[?1000h\x1b[?1006h");
+                        }
+
+                        if let Some(selected) = selected {
+                            let line = current_line!();
+                            let text = if history {
+                                selected
+                            } else {
+                                completion::FileCompletion::escape_special_characters(&selected)
+                            };
+                            line.delete_range(from, to);
+                            for ch in text.chars() {
+                                line.insert(ch);
+                            }
+                        }
                     }
                 }
 
@@ -591,21 +1233,90 @@ impl LineEditor {
                     current_line!().normal_mode_fix_cursor();
                 }
 
-                last_command = cmd;
+                if !matches!(cmd, Command::Yank | Command::YankPop) {
+                    yanked = None;
+                }
+                if !matches!(cmd, Command::HistoryPrev | Command::HistoryNext) {
+                    history_prefix = None;
+                }
+                if !matches!(
+                    cmd,
+                    Command::TryCompleteFilename
+                        | Command::SelectCandidate(_)
+                        | Command::DisplayCompletionCandidate
+                ) {
+                    completion.clear();
+                }
+            }
+
+            // the change is completed when it gets back to the normal mode
+            if recording_change && self.mode.is_normal_idle() {
+                self.last_change = std::mem::take(&mut change_keys);
+                recording_change = false;
             }
         }
 
+        accepted = true;
+        completion.clear();
         update_line!();
 
         let line = current_line!().clone();
         let result = line.to_string();
         if !result.is_empty() {
-            self.line_history.push(line);
+            self.line_history.push(HistoryEntry::new(line));
+            self.running_entry = Some(self.line_history.len() - 1);
+            self.suggestion_index
+                .insert(result.clone(), self.line_history.len() - 1);
         }
 
         Ok(result)
     }
 
+    /// Returns the rest of the latest command in history which starts with `line`.
+    /// Suggestions are only made while typing at the end of the line.
+    fn suggestion(&self, line: &Line) -> Option<&str> {
+        if !matches!(self.mode, Mode::Insert(..)) || line.len() == 0 || line.cursor() != line.len()
+        {
+            return None;
+        }
+        let line = line.to_string();
+        let command = self.suggestion_index.find(&line)?;
+        Some(&command[line.len()..])
+    }
+
+    /// Makes the next `read_line` start with `text` instead of an empty line,
+    /// placing the cursor at the byte offset `cursor`
+    pub fn prefill(&mut self, text: &str, cursor: usize) {
+        let mut line = Line::from(text);
+        let cursor = text.get(..cursor).unwrap_or(text);
+        line.cursor_exact(graphemes(cursor).count());
+        self.initial_line = Some(line);
+    }
+
+    // returns the text of a register, reading the system clipboard for `+`
+    fn register_text(
+        &self,
+        reg: char,
+        input_parser: &mut InputParser,
+        events: &mut VecDeque<Event>,
+    ) -> Option<String> {
+        if reg == '+' {
+            let (text, typed) = clipboard::paste();
+            events.extend(input_parser.feed(&typed));
+            return text;
+        }
+        self.registers.get(&reg.to_ascii_lowercase()).cloned()
+    }
+
+    /// Records how long the command returned by the last `read_line` took
+    pub fn record_duration(&mut self, elapsed: std::time::Duration) {
+        if let Some(i) = self.running_entry.take() {
+            if let Some(entry) = self.line_history.get_mut(i) {
+                entry.duration = Some(elapsed.as_secs());
+            }
+        }
+    }
+
     // Returns a pair of (unescaped string, print length)
     fn unescape_prompt(prompt: &str) -> (String, usize) {
         let mut buf = String::new();
@@ -652,11 +1363,15 @@ impl LineEditor {
     }
 }
 
-pub struct CompletionEngine<'a> {
+/// `CompletionEngine` holds the candidates shown in the completion menu
+pub(crate) struct CompletionEngine<'a> {
     completion: &'a dyn completion::Complete,
-    candidates: Vec<String>,
-    line: String,
-    dirty: u8,
+    candidates: Vec<completion::Candidate>,
+    // the word being completed
+    prefix: String,
+    selected: Option<usize>,
+    // the number of the files not examined for the candidates
+    unexamined: usize,
 }
 
 impl<'a> CompletionEngine<'a> {
@@ -664,61 +1379,393 @@ impl<'a> CompletionEngine<'a> {
         Self {
             completion,
             candidates: Vec::new(),
-            line: String::new(),
-            dirty: 0,
+            prefix: String::new(),
+            selected: None,
+            unexamined: 0,
         }
     }
 
     pub fn clear(&mut self) {
         self.candidates.clear();
-        self.line.clear();
-        self.dirty = 0;
+        self.prefix.clear();
+        self.selected = None;
+        self.unexamined = 0;
     }
 
-    pub fn cleared(&mut self) -> bool {
-        self.dirty == 0
+    /// Returns whether the menu is shown
+    pub fn is_active(&self) -> bool {
+        !self.candidates.is_empty()
     }
 
-    pub fn update(&mut self, line: String) {
-        if self.line != line {
-            // FIXME: ignore "\ " pattern
-            let mut words: Vec<&str> = line.split_ascii_whitespace().collect();
-            if line.ends_with(' ') {
-                words.push("");
-            }
-
-            self.candidates = self.completion.candidates(&words);
-            self.line = line;
-            self.dirty = 1;
+    pub fn update(&mut self, line: &str) {
+        // FIXME: ignore "\ " pattern
+        let mut words: Vec<&str> = line.split_ascii_whitespace().collect();
+        if line.ends_with(' ') {
+            words.push("");
         }
+
+        completion::take_unexamined_files();
+        self.candidates = self.completion.candidates(&words);
+        self.unexamined = completion::take_unexamined_files();
+        self.prefix = words.last().unwrap_or(&"").to_string();
+        self.selected = None;
     }
 
-    pub fn next(&mut self) -> Option<&str> {
-        if self.candidates.is_empty() {
-            return None;
+    /// Returns the word shown in the line, i.e. the selected candidate or the typed word
+    pub fn shown(&self) -> &str {
+        match self.selected {
+            Some(i) => &self.candidates[i].text,
+            None => &self.prefix,
         }
-
-        self.dirty = 2;
-        let cand = self.candidates.remove(0);
-        self.candidates.push(cand);
-        self.candidates.last().map(String::as_str)
     }
 
-    pub fn prev(&self) -> Option<&str> {
-        if self.dirty == 2 {
-            self.candidates.last().map(String::as_str)
+    /// Replaces the typed word with the part common to the candidates if it is longer
+    pub fn extend_prefix(&mut self) -> bool {
+        if self.len() < 2 {
+            return false;
+        }
+        let common = completion::common_prefix(&self.candidates);
+        if common.len() > self.prefix.len() && common.starts_with(&self.prefix) {
+            self.prefix = common;
+            true
         } else {
-            None
+            false
+        }
+    }
+
+    /// Returns the offset to move the selection to the `i`-th candidate
+    pub fn offset_to(&self, i: usize) -> isize {
+        match self.selected {
+            Some(selected) => i as isize - selected as isize,
+            None => i as isize + 1,
         }
     }
 
-    pub fn iter(&self) -> impl Iterator<Item = &str> + '_ {
-        self.candidates.iter().map(String::as_str)
+    /// Moves the selection by `offset`, wrapping around at both ends
+    pub fn select(&mut self, offset: isize) {
+        let len = self.candidates.len() as isize;
+        if len == 0 {
+            return;
+        }
+        let next = match self.selected {
+            Some(i) => i as isize + offset,
+            None if offset > 0 => offset - 1,
+            None => len + offset,
+        };
+        self.selected = Some(next.rem_euclid(len) as usize);
     }
 
     pub fn len(&self) -> usize {
         self.candidates.len()
     }
+
+    fn is_described(&self) -> bool {
+        self.candidates
+            .iter()
+            .any(|cand| cand.description.is_some())
+    }
+
+    // returns the texts shown in the menu and the char positions where their descriptions start
+    fn items(&self) -> Vec<(String, usize)> {
+        // descriptions are aligned after the longest candidate
+        let text_width = self
+            .candidates
+            .iter()
+            .map(|cand| cand.text.chars().map(char_width).sum::<usize>())
+            .max()
+            .unwrap_or(0);
+
+        self.candidates
+            .iter()
+            .map(|cand| match &cand.description {
+                Some(desc) => {
+                    let width: usize = cand.text.chars().map(char_width).sum();
+                    let padding = " ".repeat(text_width - width);
+                    let text = format!("{}{padding}", cand.text);
+                    let desc_start = text.chars().count();
+                    (format!("{text}  -- {desc}"), desc_start)
+                }
+                None => (cand.text.clone(), usize::MAX),
+            })
+            .collect()
+    }
+
+    fn column_width(&self) -> usize {
+        let max_width = self
+            .items()
+            .iter()
+            .map(|(item, _)| item.chars().map(char_width).sum())
+            .max()
+            .unwrap_or(0);
+        max_width + 2
+    }
+
+    /// Returns the number of the candidates displayed in a row
+    pub fn columns(&self, terminal_width: usize) -> usize {
+        if self.is_described() {
+            return 1;
+        }
+        (terminal_width / self.column_width()).clamp(1, self.len().max(1))
+    }
+
+    // returns the first row and the number of the rows shown in the menu
+    fn visible_rows(&self, columns: usize) -> (usize, usize) {
+        let rows = self.len().div_ceil(columns);
+
+        // scroll to the selected candidate if all the rows don't fit in the half of the screen
+        let max_rows = match terminal_size::get_rows() as usize {
+            0 => MAX_MENU_ROWS,
+            screen_rows => (screen_rows / 2).max(2),
+        };
+        let visible = if rows > max_rows { max_rows - 1 } else { rows };
+        let selected_row = self.selected.map_or(0, |i| i / columns);
+        let first = (selected_row + 1).saturating_sub(visible);
+        (first, visible)
+    }
+
+    /// Returns the index of the candidate shown at (`row`, `col`) of the menu
+    pub fn item_at(&self, terminal_width: usize, row: usize, col: usize) -> Option<usize> {
+        if !self.is_active() {
+            return None;
+        }
+        let columns = self.columns(terminal_width);
+        let column_width = self.column_width().min(terminal_width);
+        let (first, visible) = self.visible_rows(columns);
+        if row >= visible || col / column_width >= columns {
+            return None;
+        }
+        let i = (first + row) * columns + col / column_width;
+        (i < self.len()).then_some(i)
+    }
+
+    /// Returns the rows of the menu laid out to fit in `terminal_width`
+    pub fn render(&self, terminal_width: usize) -> Vec<String> {
+        if !self.is_active() {
+            return Vec::new();
+        }
+
+        let columns = self.columns(terminal_width);
+        let column_width = self.column_width().min(terminal_width);
+        let rows = self.len().div_ceil(columns);
+        let (first, visible) = self.visible_rows(columns);
+
+        let items = self.items();
+        let mut menu = Vec::new();
+        for row in first..first + visible {
+            let mut printed = String::new();
+            for col in 0..columns {
+                let i = row * columns + col;
+                let Some((text, desc_start)) = items.get(i) else {
+                    break;
+                };
+
+                let mut item = String::new();
+                let mut width = 0;
+                for (j, ch) in text.chars().enumerate() {
+                    if width + char_width(ch) > column_width - 2 {
+                        break;
+                    }
+                    if j == *desc_start {
+                        item.push_str("\x1b[2m");
+                    }
+                    item.push(ch);
+                    width += char_width(ch);
+                }
+                if item.contains('\x1b') {
+                    item.push_str("\x1b[22m");
+                }
+
+                if col > 0 {
+                    printed.push_str("  ");
+                }
+                if self.selected == Some(i) {
+                    printed.push_str(&format!("\x1b[7m{item}\x1b[m"));
+                } else {
+                    printed.push_str(&item);
+                }
+                if col + 1 < columns && i + 1 < self.len() {
+                    printed.push_str(&" ".repeat(column_width - 2 - width));
+                }
+            }
+            menu.push(printed);
+        }
+        if visible < rows {
+            menu.push(format!(
+                "\x1b[2m(rows {}-{} of {rows})\x1b[m",
+                first + 1,
+                first + visible
+            ));
+        }
+        if self.unexamined > 0 {
+            menu.push(format!("\x1b[2m({} more\u{2026})\x1b[m", self.unexamined));
+        }
+
+        // the metadata of the selected file
+        if let Some(preview) = self
+            .selected
+            .and_then(|i| completion::file_preview(&self.candidates[i].text))
+        {
+            let mut width = 0;
+            let preview: String = preview
+                .chars()
+                .take_while(|ch| {
+                    width += char_width(*ch);
+                    width <= terminal_width
+                })
+                .collect();
+            menu.push(format!("\x1b[2m{preview}\x1b[m"));
+        }
+        menu
+    }
+}
+
+// the height of the menu when the terminal size is unknown
+const MAX_MENU_ROWS: usize = 10;
+
+// replaces the word shown in the line with the one after `update`, e.g. a newly selected one
+fn update_shown<F>(line: &mut Line, completion: &mut CompletionEngine, update: F)
+where
+    F: FnOnce(&mut CompletionEngine),
+{
+    for _ in 0..graphemes(completion.shown()).count() {
+        line.delete_prev();
+    }
+    update(completion);
+    for ch in completion.shown().chars() {
+        line.insert(ch);
+    }
+}
+
+// writes `output` to the terminal with as few system calls as possible, whereas stdout writes
+// each line separately
+fn write_at_once(output: &str) {
+    let mut stdout = stdout().lock();
+    stdout.flush().unwrap();
+
+    let mut bytes = output.as_bytes();
+    while !bytes.is_empty() {
+        match unistd::write(nix::libc::STDOUT_FILENO, bytes) {
+            Ok(written) => bytes = &bytes[written..],
+            Err(Errno::EINTR) => {}
+            Err(err) => panic!("{err}"),
+        }
+    }
+}
+
+// no wrapping if the width is unknown
+fn terminal_width() -> usize {
+    match terminal_size::get_cols() as usize {
+        0 => usize::MAX,
+        cols => cols,
+    }
+}
+
+// how long to wait for the rest of an escape sequence
+const ESCAPE_TIMEOUT_MS: i32 = 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wakeup {
+    Input,
+    Resized,
+    Notified,
+}
+
+// waits until a key is typed, the terminal is resized or `notify_fd` becomes readable
+fn wait_input(notify_fd: Option<RawFd>) -> Wakeup {
+    use nix::poll::{poll, PollFd, PollFlags};
+
+    let sources: Vec<(RawFd, Wakeup)> = [
+        (terminal_size::resize_fd(), Wakeup::Resized),
+        (notify_fd, Wakeup::Notified),
+    ]
+    .into_iter()
+    .filter_map(|(fd, wakeup)| Some((fd?, wakeup)))
+    .collect();
+    let mut fds = vec![PollFd::new(STDIN_FILENO, PollFlags::POLLIN)];
+    fds.extend(
+        sources
+            .iter()
+            .map(|(fd, _)| PollFd::new(*fd, PollFlags::POLLIN)),
+    );
+    while let Err(Errno::EINTR) = poll(&mut fds, -1) {}
+
+    // notifications are handled first
+    for (polled, (_, wakeup)) in fds[1..].iter().zip(sources).rev() {
+        if polled
+            .revents()
+            .is_some_and(|ev| ev.contains(PollFlags::POLLIN))
+        {
+            if wakeup == Wakeup::Resized {
+                terminal_size::clear_resized();
+            }
+            return wakeup;
+        }
+    }
+    Wakeup::Input
+}
+
+// returns whether stdin becomes readable within `timeout_ms`
+fn input_ready(timeout_ms: i32) -> bool {
+    use nix::poll::{poll, PollFd, PollFlags};
+    let mut fds = [PollFd::new(STDIN_FILENO, PollFlags::POLLIN)];
+    matches!(poll(&mut fds, timeout_ms), Ok(n) if n > 0)
+}
+
+// quotes and brackets closed automatically when the `autopair` option is set
+const AUTO_PAIRS: [(char, char); 4] = [('\'', '\''), ('"', '"'), ('(', ')'), ('[', ']')];
+
+// lets the next prompt be drawn over the current one
+fn overwrite_prompt(rows: &std::cell::Cell<(usize, usize)>) {
+    let (cursor_row, _) = rows.get();
+    print!("\x1b[{}A", cursor_row + 1);
+    stdout().flush().unwrap();
+    rows.set((0, 0));
+}
+
+fn char_width(ch: char) -> usize {
+    use unicode_width::UnicodeWidthChar as _;
+    ch.width().unwrap_or(1)
+}
+
+// Returns the (row, column) where the cursor is placed after printing characters of `widths`.
+// A wide character which doesn't fit in the rest of a row is put on the next row.
+fn wrapped_position(widths: impl Iterator<Item = usize>, terminal_width: usize) -> (usize, usize) {
+    let (mut row, mut col) = (0, 0);
+    for width in widths {
+        if col + width > terminal_width {
+            row += 1;
+            col = 0;
+        }
+        col += width;
+    }
+
+    if col >= terminal_width {
+        (row + 1, 0)
+    } else {
+        (row, col)
+    }
+}
+
+// returns the number of the characters laid out before (`row`, `col`)
+fn char_index_at(
+    widths: impl Iterator<Item = usize>,
+    terminal_width: usize,
+    (row, col): (usize, usize),
+) -> usize {
+    let (mut r, mut c) = (0, 0);
+    let mut count = 0;
+    for width in widths {
+        if c + width > terminal_width {
+            r += 1;
+            c = 0;
+        }
+        if (r, c + width) > (row, col) {
+            return count;
+        }
+        c += width;
+        count += 1;
+    }
+    count
 }
 
 fn enable_raw_mode() -> termios::Termios {
@@ -755,36 +1802,3 @@ fn enable_raw_mode() -> termios::Termios {
 
     saved
 }
-
-fn save_history(history: &[Line]) -> std::io::Result<()> {
-    if let Some(app_dir) = crate::application_dir() {
-        let mut path = app_dir;
-        path.push("history");
-
-        let mut file = std::fs::File::create(path)?;
-        for line in history.iter() {
-            writeln!(file, "{}", line)?;
-        }
-    }
-    Ok(())
-}
-
-fn load_history() -> std::io::Result<Vec<Line>> {
-    use std::io::{BufRead as _, BufReader};
-
-    let mut history = Vec::new();
-    if let Some(app_dir) = crate::application_dir() {
-        let mut path = app_dir;
-        path.push("history");
-
-        let file = std::fs::File::open(path)?;
-        for line in BufReader::new(file).lines().filter_map(|r| r.ok()) {
-            let line = line.trim();
-            if line.is_empty() {
-                continue;
-            }
-            history.push(Line::from(line));
-        }
-    }
-    Ok(history)
-}
@@ -12,10 +12,32 @@ impl Mode {
     pub fn is_insert(&self) -> bool {
         matches!(self, Mode::Insert(..) | Mode::Search(..))
     }
+
+    /// Returns the name shown by the mode indicator of the prompt
+    pub fn name(&self) -> &'static str {
+        match self {
+            Mode::Insert(..) => "INSERT",
+            Mode::Search(..) => "SEARCH",
+            Mode::Normal(..) => "NORMAL",
+            Mode::Visual(..) => "VISUAL",
+        }
+    }
+
+    /// Returns true if it's in the normal mode and no key sequence is pending
+    pub fn is_normal_idle(&self) -> bool {
+        matches!(self, Mode::Normal(mode)
+            if mode.combo.is_empty() && mode.count.is_none() && mode.register.is_none())
+    }
 }
 
 pub(super) trait EditorMode {
-    fn process_event(&mut self, event: Event, _line: &Line, cmds: &mut Vec<Command>);
+    fn process_event(
+        &mut self,
+        event: Event,
+        line: &Line,
+        keymap: &Keymap,
+        cmds: &mut Vec<Command>,
+    );
 }
 
 fn parse_vim_text_object(
@@ -71,6 +93,11 @@ fn parse_vim_text_object(
 pub(super) struct NormalMode {
     combo: Vec<char>,
     last_find: Option<(char, char)>,
+    count: Option<usize>,
+    // count given before the pending operator or find command
+    combo_count: Option<usize>,
+    // register selected by a prefix like `"a`
+    register: Option<char>,
 }
 
 impl NormalMode {
@@ -98,7 +125,7 @@ impl NormalMode {
                 'd' => {
                     cmds.push(Command::MakeCheckPoint);
                     cmds.push(Command::RegisterStore {
-                        reg: '"',
+                        reg: self.register(),
                         text: selected,
                     });
                     cmds.push(Command::DeleteRange { from, to });
@@ -106,7 +133,7 @@ impl NormalMode {
                 'c' => {
                     cmds.push(Command::MakeCheckPoint);
                     cmds.push(Command::RegisterStore {
-                        reg: '"',
+                        reg: self.register(),
                         text: selected,
                     });
                     cmds.push(Command::DeleteRange { from, to });
@@ -114,11 +141,12 @@ impl NormalMode {
                 }
                 'y' => {
                     cmds.push(Command::RegisterStore {
-                        reg: '"',
+                        reg: self.register(),
                         text: selected,
                     });
                     cmds.push(Command::CursorExact(from));
                 }
+                op @ ('u' | 'U') => self.apply_operator(op, from, to, line, cmds),
                 _ => unreachable!(),
             }
         }
@@ -127,315 +155,651 @@ impl NormalMode {
     }
 }
 
-impl EditorMode for NormalMode {
-    fn process_event(&mut self, event: Event, line: &Line, cmds: &mut Vec<Command>) {
-        match self.combo.first() {
-            None => match event {
-                Event::Char('i') => {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::ChangeModeToInsert);
-                }
+impl NormalMode {
+    fn perform(&mut self, action: Action, line: &Line, cmds: &mut Vec<Command>) {
+        match action {
+            Action::ViInsert => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::ChangeModeToInsert);
+            }
 
-                Event::Char('v') => {
-                    cmds.push(Command::ChangeModeToVisualChar);
-                }
-                Event::Char('V') => {
-                    cmds.push(Command::ChangeModeToVisualLine);
+            Action::ViVisual => {
+                cmds.push(Command::ChangeModeToVisualChar);
+            }
+            Action::ViVisualLine => {
+                cmds.push(Command::ChangeModeToVisualLine);
+            }
+
+            Action::AcceptLine => cmds.push(Command::Commit),
+
+            Action::BackwardChar => cmds.push(Command::CursorPrevChar),
+            Action::ForwardChar => cmds.push(Command::CursorNextChar),
+            Action::UpHistory => cmds.push(Command::HistoryPrev),
+            Action::DownHistory => cmds.push(Command::HistoryNext),
+
+            Action::ViForwardWord => cmds.push(Command::CursorNextWordHead),
+            Action::ViForwardBlankWord => cmds.push(Command::CursorNextWordHeadWide),
+            Action::ViForwardWordEnd => cmds.push(Command::CursorNextWordEnd),
+            Action::ViForwardBlankWordEnd => cmds.push(Command::CursorNextWordEndWide),
+            Action::ViBackwardWord => cmds.push(Command::CursorPrevWordHead),
+            Action::ViBackwardBlankWord => cmds.push(Command::CursorPrevWordHeadWide),
+            Action::ViBackwardWordEnd => cmds.push(Command::CursorPrevWordEnd),
+            Action::ViBackwardBlankWordEnd => cmds.push(Command::CursorPrevWordEndWide),
+            Action::ViGotoPrefix => self.combo.push('g'),
+            Action::ViMatchBracket => cmds.push(Command::CursorMatchingPair),
+
+            Action::ViFindNextChar => self.combo.push('f'),
+            Action::ViFindPrevChar => self.combo.push('F'),
+            Action::ViTillNextChar => self.combo.push('t'),
+            Action::ViTillPrevChar => self.combo.push('T'),
+            Action::ViRepeatFind | Action::ViRevRepeatFind => {
+                if let Some(motion) = self.repeat_find(action) {
+                    cmds.push(motion);
                 }
+            }
 
-                Event::KeyReturn => cmds.push(Command::Commit),
+            Action::EndOfLine => {
+                cmds.push(Command::CursorEnd);
+            }
+            Action::ViFirstNonBlank => {
+                cmds.push(Command::CursorBegin);
+            }
+            Action::BeginningOfLine => {
+                cmds.push(Command::CursorExact(0));
+            }
 
-                Event::KeyLeft | Event::Char('h') => cmds.push(Command::CursorPrevChar),
-                Event::KeyRight | Event::Char('l') => cmds.push(Command::CursorNextChar),
-                Event::KeyUp | Event::Char('k') => cmds.push(Command::HistoryPrev),
-                Event::KeyDown | Event::Char('j') => cmds.push(Command::HistoryNext),
+            Action::ViAppendEol => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::ChangeModeToInsert);
+                cmds.push(Command::CursorEnd);
+            }
+            Action::ViInsertBol => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::ChangeModeToInsert);
+                cmds.push(Command::CursorBegin);
+            }
 
-                Event::Char('w') => cmds.push(Command::CursorNextWordHead),
-                Event::Char('W') => cmds.push(Command::CursorNextWordHeadWide),
-                Event::Char('e') => cmds.push(Command::CursorNextWordEnd),
-                Event::Char('E') => cmds.push(Command::CursorNextWordEndWide),
-                Event::Char('b') => cmds.push(Command::CursorPrevWordHead),
-                Event::Char('B') => cmds.push(Command::CursorPrevWordHeadWide),
+            Action::ViAppend => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::ChangeModeToInsert);
+                cmds.push(Command::CursorNextChar);
+            }
+            Action::ViSubstitute => {
+                cmds.push(Command::MakeCheckPoint);
 
-                Event::Char('f') => {
-                    self.combo.push('f');
-                }
-                Event::Char('F') => {
-                    self.combo.push('F');
+                if let Some((g, _)) = line.iter(line.cursor()..).next() {
+                    cmds.push(Command::RegisterStore {
+                        reg: self.register(),
+                        text: g.to_owned(),
+                    });
                 }
-                Event::Char(';') => match self.last_find {
-                    Some(('f', ch)) => {
-                        cmds.push(Command::CursorNextCharMatch(ch));
-                    }
-                    Some(('F', ch)) => {
-                        cmds.push(Command::CursorPrevCharMatch(ch));
-                    }
-                    _ => {}
-                },
 
-                Event::Char('$') => {
-                    cmds.push(Command::CursorEnd);
-                }
-                Event::Char('^') => {
-                    cmds.push(Command::CursorBegin);
-                }
-                Event::Char('0') => {
-                    cmds.push(Command::CursorExact(0));
-                }
+                cmds.push(Command::ChangeModeToInsert);
+                cmds.push(Command::DeleteNextChar);
+            }
+            Action::ViDeleteChar | Action::DeleteChar => {
+                cmds.push(Command::MakeCheckPoint);
 
-                Event::Char('A') => {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::ChangeModeToInsert);
-                    cmds.push(Command::CursorEnd);
-                }
-                Event::Char('I') => {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::ChangeModeToInsert);
-                    cmds.push(Command::CursorBegin);
+                if let Some((g, _)) = line.iter(line.cursor()..).next() {
+                    cmds.push(Command::RegisterStore {
+                        reg: self.register(),
+                        text: g.to_owned(),
+                    });
                 }
 
-                Event::Char('a') => {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::ChangeModeToInsert);
-                    cmds.push(Command::CursorNextChar);
-                }
-                Event::Char('s') => {
-                    cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::DeleteNextChar);
+            }
 
-                    if let Some(ch) = line.char_at(line.cursor()) {
-                        cmds.push(Command::RegisterStore {
-                            reg: '"',
-                            text: ch.to_string(),
-                        });
-                    }
+            Action::ViDelete => {
+                self.combo.push('d');
+            }
+            Action::ViChange => {
+                self.combo.push('c');
+            }
 
-                    cmds.push(Command::ChangeModeToInsert);
-                    cmds.push(Command::DeleteNextChar);
-                }
-                Event::Char('x') => {
-                    cmds.push(Command::MakeCheckPoint);
+            Action::KillToEnd => {
+                cmds.push(Command::MakeCheckPoint);
+                kill_to_end(self.register(), line, cmds);
+            }
+            Action::ViChangeEol => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::ChangeModeToInsert);
+                kill_to_end(self.register(), line, cmds);
+            }
+            Action::ViChangeWholeLine => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::DeleteLine);
+                cmds.push(Command::ChangeModeToInsert);
+            }
+            Action::KillWholeLine => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::RegisterStore {
+                    reg: self.register(),
+                    text: line.to_string(),
+                });
+                cmds.push(Command::DeleteLine);
+            }
 
-                    if let Some(ch) = line.char_at(line.cursor()) {
-                        cmds.push(Command::RegisterStore {
-                            reg: '"',
-                            text: ch.to_string(),
-                        });
-                    }
+            Action::ViYank => {
+                self.combo.push('y');
+            }
+            Action::ViYankWholeLine => {
+                cmds.push(Command::RegisterStore {
+                    reg: self.register(),
+                    text: line.to_string(),
+                });
+            }
 
-                    cmds.push(Command::DeleteNextChar);
-                }
+            Action::ViPutBefore => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::RegisterPastePrev {
+                    reg: self.register(),
+                });
+            }
+            Action::ViPutAfter => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::RegisterPasteNext {
+                    reg: self.register(),
+                });
+            }
 
-                Event::Char('d') => {
-                    self.combo.push('d');
-                }
-                Event::Char('c') => {
-                    self.combo.push('c');
-                }
+            Action::ViRepeatChange => cmds.push(Command::RepeatChange),
+            Action::ViSetRegister => self.combo.push('"'),
+            Action::ViRecordMacro => self.combo.push('q'),
+            Action::ViPlayMacro => self.combo.push('@'),
 
-                Event::Char('D') => {
-                    cmds.push(Command::MakeCheckPoint);
+            Action::Undo => {
+                cmds.push(Command::Undo);
+            }
+            Action::Redo => {
+                cmds.push(Command::Redo);
+            }
 
-                    let from = line.cursor();
-                    let to = line.len();
-                    let cursor_to_end: String = line.iter(from..to).map(|(c, _)| c).collect();
-                    cmds.push(Command::RegisterStore {
-                        reg: '"',
-                        text: cursor_to_end,
-                    });
-                    cmds.push(Command::DeleteRange { from, to });
-                }
-                Event::Char('C') => {
-                    cmds.push(Command::MakeCheckPoint);
+            Action::CdUndo => cmds.push(Command::CdUndo),
+            Action::CdRedo => cmds.push(Command::CdRedo),
+            Action::CdParent => cmds.push(Command::CdToParent),
 
-                    let from = line.cursor();
-                    let to = line.len();
-                    let cursor_to_end: String = line.iter(from..to).map(|(c, _)| c).collect();
-                    cmds.push(Command::RegisterStore {
-                        reg: '"',
-                        text: cursor_to_end,
-                    });
+            Action::DuplicateWord => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::DuplicateWord);
+            }
+            Action::ToggleSudo => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::ToggleSudo);
+            }
 
-                    cmds.push(Command::ChangeModeToInsert);
-                    cmds.push(Command::DeleteRange { from, to });
-                }
-                Event::Char('S') => {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::DeleteLine);
-                    cmds.push(Command::ChangeModeToInsert);
+            Action::ClearScreen => cmds.push(Command::ClearScreen),
+
+            _ => {}
+        }
+    }
+}
+
+impl NormalMode {
+    fn register(&self) -> char {
+        self.register.unwrap_or('"')
+    }
+
+    // accumulates a count prefix like "3" of "3w" or "2" of "d2f)"
+    fn push_count(&mut self, event: Event) -> bool {
+        let accepts_count = match self.combo.as_slice() {
+            [] => true,
+            [op] => matches!(op, 'd' | 'c' | 'y' | 'u' | 'U'),
+            _ => false,
+        };
+        match event {
+            Event::Char(ch @ '0'..='9') if accepts_count && (ch != '0' || self.count.is_some()) => {
+                append_digit(&mut self.count, ch);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // takes the count of the pending command, both of the one before and after the operator
+    fn take_count(&mut self) -> usize {
+        let count = self.combo_count.take().unwrap_or(1) * self.count.take().unwrap_or(1);
+        count.min(MAX_COUNT)
+    }
+
+    fn perform_repeatedly(&mut self, action: Action, line: &Line, cmds: &mut Vec<Command>) {
+        let count = self.take_count();
+        match action {
+            _ if is_motion(action) || matches!(action, Action::Undo | Action::Redo) => {
+                let mut once = Vec::new();
+                self.perform(action, line, &mut once);
+                for _ in 0..count {
+                    cmds.extend(once.iter().cloned());
                 }
+            }
+
+            Action::ViDeleteChar | Action::DeleteChar if count > 1 => {
+                let from = line.cursor();
+                let to = (from + count).min(line.len());
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::RegisterStore {
+                    reg: self.register(),
+                    text: line.iter(from..to).map(|(c, _)| c).collect(),
+                });
+                cmds.push(Command::DeleteRange { from, to });
+            }
 
-                Event::Char('y') => {
-                    self.combo.push('y');
+            Action::ViPutBefore | Action::ViPutAfter if count > 1 => {
+                cmds.push(Command::MakeCheckPoint);
+                if action == Action::ViPutAfter {
+                    cmds.push(Command::RegisterPasteNext {
+                        reg: self.register(),
+                    });
+                } else {
+                    cmds.push(Command::RegisterPastePrev {
+                        reg: self.register(),
+                    });
                 }
-                Event::Char('Y') => {
-                    cmds.push(Command::RegisterStore {
-                        reg: '"',
-                        text: line.to_string(),
+                for _ in 1..count {
+                    cmds.push(Command::RegisterPastePrev {
+                        reg: self.register(),
                     });
                 }
+            }
 
-                Event::Char('P') => {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::RegisterPastePrev { reg: '"' });
+            _ => {
+                self.perform(action, line, cmds);
+                // the count applies to the operator or find command which has just started
+                if !self.combo.is_empty() {
+                    self.combo_count = Some(count);
                 }
-                Event::Char('p') => {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::RegisterPasteNext { reg: '"' });
+            }
+        }
+    }
+
+    fn process_operator(
+        &mut self,
+        event: Event,
+        line: &Line,
+        keymap: &Keymap,
+        cmds: &mut Vec<Command>,
+    ) {
+        let op = self.combo[0];
+        match (self.combo.len(), event) {
+            (1, Event::Char('y')) if op == 'y' => {
+                cmds.push(Command::RegisterStore {
+                    reg: self.register(),
+                    text: line.to_string(),
+                });
+            }
+            (1, Event::Char(ch)) if ch == op => {
+                self.apply_operator(op, 0, line.len(), line, cmds);
+            }
+
+            (1, Event::Char('i' | 'a')) => {
+                self.process_text_object(event, line, cmds);
+                return;
+            }
+            (2, _) if matches!(self.combo[1], 'i' | 'a') => {
+                self.process_text_object(event, line, cmds);
+                return;
+            }
+
+            // "gugu" and "gUgU" as well as "guu" and "gUU"
+            (2, Event::Char(ch)) if self.combo[1] == 'g' && ch == op => {
+                self.apply_operator(op, 0, line.len(), line, cmds);
+            }
+            (2, ev) if self.combo[1] == 'g' => {
+                if let Some(motion) = goto_action(ev).and_then(|a| self.motion_of(op, a, line)) {
+                    self.operate_with_motion(op, motion, line, cmds);
                 }
+            }
+
+            (2, Event::Char(ch)) => {
+                self.last_find = Some((self.combo[1], ch));
+                let motion = find_motion(self.combo[1], ch, false);
+                self.operate_with_motion(op, motion, line, cmds);
+            }
 
-                Event::Char('u') => {
-                    cmds.push(Command::Undo);
+            (1, ev) => match keymap.lookup(KeymapMode::Normal, ev) {
+                Some(
+                    action @ (Action::ViFindNextChar
+                    | Action::ViFindPrevChar
+                    | Action::ViTillNextChar
+                    | Action::ViTillPrevChar),
+                ) => {
+                    self.perform(action, line, cmds);
+                    return;
                 }
-                Event::Ctrl('r') => {
-                    cmds.push(Command::Redo);
+                Some(Action::ViGotoPrefix) => {
+                    self.combo.push('g');
+                    return;
                 }
+                Some(action) => {
+                    if let Some(motion) = self.motion_of(op, action, line) {
+                        self.operate_with_motion(op, motion, line, cmds);
+                    }
+                }
+                None => {}
+            },
 
-                Event::Ctrl('o') => cmds.push(Command::CdUndo),
-                Event::KeyTab => cmds.push(Command::CdRedo),
-                Event::Ctrl('p') => cmds.push(Command::CdToParent),
+            _ => {}
+        }
 
-                Event::Ctrl('n') => {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::DuplicateWord);
-                }
+        self.combo.clear();
+        self.count = None;
+        self.combo_count = None;
+    }
+
+    // returns the cursor movement of `action` used as the target of an operator
+    fn motion_of(&self, op: char, action: Action, line: &Line) -> Option<Command> {
+        let on_blank = line.char_at(line.cursor()).is_none_or(char::is_whitespace);
+        let motion = match action {
+            Action::BackwardChar => Command::CursorPrevChar,
+            Action::ForwardChar => Command::CursorNextChar,
+            // "cw" changes to the end of the word like "ce"
+            Action::ViForwardWord if op == 'c' && !on_blank => Command::CursorNextWordEnd,
+            Action::ViForwardBlankWord if op == 'c' && !on_blank => Command::CursorNextWordEndWide,
+            Action::ViForwardWord => Command::CursorNextWordHead,
+            Action::ViForwardBlankWord => Command::CursorNextWordHeadWide,
+            Action::ViForwardWordEnd => Command::CursorNextWordEnd,
+            Action::ViForwardBlankWordEnd => Command::CursorNextWordEndWide,
+            Action::ViBackwardWord => Command::CursorPrevWordHead,
+            Action::ViBackwardBlankWord => Command::CursorPrevWordHeadWide,
+            Action::ViBackwardWordEnd => Command::CursorPrevWordEnd,
+            Action::ViBackwardBlankWordEnd => Command::CursorPrevWordEndWide,
+            Action::ViMatchBracket => Command::CursorMatchingPair,
+            Action::EndOfLine => Command::CursorEnd,
+            Action::ViFirstNonBlank => Command::CursorBegin,
+            Action::BeginningOfLine => Command::CursorExact(0),
+            Action::ViRepeatFind | Action::ViRevRepeatFind => self.repeat_find(action)?,
+            _ => return None,
+        };
+        Some(motion)
+    }
+
+    // returns the motion repeating the last find, or reversing it for `,`
+    fn repeat_find(&self, action: Action) -> Option<Command> {
+        let (kind, ch) = self.last_find?;
+        let kind = if action == Action::ViRevRepeatFind {
+            match kind {
+                'f' => 'F',
+                'F' => 'f',
+                't' => 'T',
+                _ => 't',
+            }
+        } else {
+            kind
+        };
+        Some(find_motion(kind, ch, true))
+    }
+
+    fn operate_with_motion(
+        &mut self,
+        op: char,
+        motion: Command,
+        line: &Line,
+        cmds: &mut Vec<Command>,
+    ) {
+        let mut moved = line.clone();
+        for i in 0..self.take_count() {
+            if i == 0 {
+                move_cursor(&mut moved, &motion);
+            } else {
+                move_cursor(&mut moved, &repeated(&motion));
+            }
+        }
+
+        let cursor = line.cursor();
+        let target = moved.cursor();
+        let (from, to) = if target < cursor && is_inclusive(&motion) {
+            (target, (cursor + 1).min(line.len()))
+        } else if target < cursor {
+            (target, cursor)
+        } else if is_inclusive(&motion) {
+            (cursor, (target + 1).min(line.len()))
+        } else {
+            (cursor, target)
+        };
+
+        if from < to {
+            self.apply_operator(op, from, to, line, cmds);
+        }
+    }
 
-                _ => {}
+    fn apply_operator(
+        &self,
+        op: char,
+        from: usize,
+        to: usize,
+        line: &Line,
+        cmds: &mut Vec<Command>,
+    ) {
+        let text: String = line.iter(from..to).map(|(c, _)| c).collect();
+        match op {
+            'd' => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::RegisterStore {
+                    reg: self.register(),
+                    text,
+                });
+                cmds.push(Command::DeleteRange { from, to });
+            }
+            'c' => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::RegisterStore {
+                    reg: self.register(),
+                    text,
+                });
+                cmds.push(Command::DeleteRange { from, to });
+                cmds.push(Command::ChangeModeToInsert);
+            }
+            'y' => {
+                cmds.push(Command::RegisterStore {
+                    reg: self.register(),
+                    text,
+                });
+                cmds.push(Command::CursorExact(from));
+            }
+            'u' | 'U' => {
+                cmds.push(Command::MakeCheckPoint);
+                cmds.push(Command::ChangeCase {
+                    from,
+                    to,
+                    upper: op == 'U',
+                });
+                cmds.push(Command::CursorExact(from));
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl EditorMode for NormalMode {
+    fn process_event(
+        &mut self,
+        event: Event,
+        line: &Line,
+        keymap: &Keymap,
+        cmds: &mut Vec<Command>,
+    ) {
+        if self.combo.first() == Some(&'"') {
+            if let Some(reg) = register_name(event) {
+                self.register = Some(reg);
+            }
+            self.combo.clear();
+            return;
+        }
+
+        if self.push_count(event) {
+            return;
+        }
+
+        match self.combo.first() {
+            None => match keymap.lookup(KeymapMode::Normal, event) {
+                Some(action) => self.perform_repeatedly(action, line, cmds),
+                None => self.count = None,
             },
 
-            Some('d') => {
-                if self.combo.len() == 1 && event == Event::Char('d') {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::RegisterStore {
-                        reg: '"',
-                        text: line.to_string(),
-                    });
-                    cmds.push(Command::DeleteLine);
-                    self.combo.clear();
-                } else {
-                    self.process_text_object(event, line, cmds);
-                }
+            Some('d' | 'c' | 'y' | 'u' | 'U') => {
+                self.process_operator(event, line, keymap, cmds);
             }
 
-            Some('c') => {
-                if self.combo.len() == 1 && event == Event::Char('c') {
-                    cmds.push(Command::MakeCheckPoint);
-                    cmds.push(Command::RegisterStore {
-                        reg: '"',
-                        text: line.to_string(),
-                    });
-                    cmds.push(Command::DeleteLine);
-                    cmds.push(Command::ChangeModeToInsert);
-                    self.combo.clear();
-                } else {
-                    self.process_text_object(event, line, cmds);
+            Some('q') => {
+                if let Some(reg) = register_name(event) {
+                    cmds.push(Command::StartMacro(reg));
                 }
+                self.combo.clear();
             }
-
-            Some('y') => {
-                if self.combo.len() == 1 && event == Event::Char('y') {
-                    cmds.push(Command::RegisterStore {
-                        reg: '"',
-                        text: line.to_string(),
-                    });
-                    self.combo.clear();
-                } else {
-                    self.process_text_object(event, line, cmds);
+            Some('@') => {
+                // "@@" plays the last played macro again
+                let reg = match event {
+                    Event::Char('@') => Some('@'),
+                    ev => register_name(ev),
+                };
+                let count = self.take_count();
+                if let Some(reg) = reg {
+                    for _ in 0..count {
+                        cmds.push(Command::PlayMacro(reg));
+                    }
                 }
+                self.combo.clear();
             }
 
-            Some('f') => {
-                if let Event::Char(ch) = event {
-                    self.last_find = Some(('f', ch));
-                    cmds.push(Command::CursorNextCharMatch(ch));
-                } else {
-                    self.last_find = None;
-                }
+            Some('g') => {
                 self.combo.clear();
+                // "gu" and "gU" are operators
+                if let Event::Char(op @ ('u' | 'U')) = event {
+                    self.combo.push(op);
+                    return;
+                }
+                match goto_action(event) {
+                    Some(action) => self.perform_repeatedly(action, line, cmds),
+                    None => {
+                        self.count = None;
+                        self.combo_count = None;
+                    }
+                }
             }
-            Some('F') => {
+
+            Some(&kind @ ('f' | 'F' | 't' | 'T')) => {
                 if let Event::Char(ch) = event {
-                    self.last_find = Some(('F', ch));
-                    cmds.push(Command::CursorPrevCharMatch(ch));
+                    self.last_find = Some((kind, ch));
+                    let motion = find_motion(kind, ch, false);
+                    for i in 0..self.take_count() {
+                        if i == 0 {
+                            cmds.push(motion.clone());
+                        } else {
+                            cmds.push(repeated(&motion));
+                        }
+                    }
                 } else {
                     self.last_find = None;
                 }
                 self.combo.clear();
+                self.count = None;
+                self.combo_count = None;
             }
 
             _ => unreachable!(),
         }
+
+        // the register is used only by the command which has just completed
+        if self.combo.is_empty() && self.count.is_none() {
+            self.register = None;
+        }
     }
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub(super) struct SearchMode {
     query: Line,
+    backward: bool,
 }
 
 impl SearchMode {
-    pub fn new() -> Self {
-        Self { query: Line::new() }
+    pub fn new(backward: bool) -> Self {
+        Self {
+            query: Line::new(),
+            backward,
+        }
     }
 
     pub fn query(&self) -> String {
         self.query.to_string()
     }
+
+    pub fn is_backward(&self) -> bool {
+        self.backward
+    }
 }
 
 impl EditorMode for SearchMode {
-    fn process_event(&mut self, event: Event, _line: &Line, cmds: &mut Vec<Command>) {
-        match event {
-            Event::KeyEscape | Event::KeyTab | Event::Ctrl('u') | Event::Ctrl('d') => {
+    fn process_event(
+        &mut self,
+        event: Event,
+        _line: &Line,
+        keymap: &Keymap,
+        cmds: &mut Vec<Command>,
+    ) {
+        let Some(action) = keymap.lookup(KeymapMode::Search, event) else {
+            return;
+        };
+
+        match action {
+            Action::Abort | Action::ViCmdMode => {
                 cmds.push(Command::ChangeModeToInsert);
             }
 
-            Event::KeyReturn => {
+            Action::AcceptLine => {
                 cmds.push(Command::ChangeModeToInsert);
                 cmds.push(Command::Commit);
             }
-            Event::KeyLeft => {
+            Action::BackwardChar => {
                 cmds.push(Command::ChangeModeToInsert);
                 cmds.push(Command::CursorPrevChar);
             }
-            Event::KeyRight => {
+            Action::ForwardChar => {
                 cmds.push(Command::ChangeModeToInsert);
                 cmds.push(Command::CursorNextChar);
             }
-            Event::KeyUp => {
+            Action::UpHistory => {
                 cmds.push(Command::ChangeModeToInsert);
                 cmds.push(Command::HistoryPrev);
             }
-            Event::KeyDown => {
+            Action::DownHistory => {
                 cmds.push(Command::ChangeModeToInsert);
                 cmds.push(Command::HistoryNext);
             }
 
-            Event::Char(ch) => {
-                self.query.insert(ch);
-                cmds.push(Command::HistorySearch {
-                    query: self.query.to_string(),
-                    reset: true,
-                });
+            Action::SelfInsert => {
+                if let Event::Char(ch) = event {
+                    self.query.insert(ch);
+                    cmds.push(Command::HistorySearch {
+                        query: self.query.to_string(),
+                        backward: self.backward,
+                        next: false,
+                    });
+                }
             }
-            Event::KeyBackspace => {
+            Action::BackwardDeleteChar => {
                 self.query.delete_prev();
                 cmds.push(Command::HistorySearch {
                     query: self.query.to_string(),
-                    reset: true,
+                    backward: self.backward,
+                    next: false,
                 });
             }
-            Event::Ctrl('w') => {
+            Action::BackwardKillWord => {
                 self.query.delete_word();
                 cmds.push(Command::HistorySearch {
                     query: self.query.to_string(),
-                    reset: true,
+                    backward: self.backward,
+                    next: false,
                 });
             }
 
-            Event::Ctrl('r') => {
+            Action::HistorySearch | Action::HistorySearchForward => {
+                self.backward = action == Action::HistorySearch;
                 cmds.push(Command::HistorySearch {
                     query: self.query.to_string(),
-                    reset: false,
+                    backward: self.backward,
+                    next: true,
                 });
             }
 
@@ -445,41 +809,121 @@ impl EditorMode for SearchMode {
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
-pub(super) struct InsertMode;
+pub(super) struct InsertMode {
+    // the next key is inserted as is
+    quoting: bool,
+}
+
+impl InsertMode {
+    /// Returns true if the next key is inserted literally
+    pub fn is_quoting(&self) -> bool {
+        self.quoting
+    }
+}
 
 impl EditorMode for InsertMode {
-    fn process_event(&mut self, event: Event, _line: &Line, cmds: &mut Vec<Command>) {
-        match event {
-            Event::KeyEscape => {
+    fn process_event(
+        &mut self,
+        event: Event,
+        line: &Line,
+        keymap: &Keymap,
+        cmds: &mut Vec<Command>,
+    ) {
+        if self.quoting {
+            self.quoting = false;
+            if let Some(ch) = literal_char(event) {
+                cmds.push(Command::Insert(ch));
+            }
+            return;
+        }
+
+        let Some(action) = keymap.lookup(KeymapMode::Insert, event) else {
+            return;
+        };
+
+        match action {
+            Action::ViCmdMode => {
                 cmds.push(Command::CursorPrevChar);
                 cmds.push(Command::ChangeModeToNormal);
             }
 
-            Event::KeyReturn => cmds.push(Command::Commit),
-            Event::KeyLeft => cmds.push(Command::CursorPrevChar),
-            Event::KeyRight => cmds.push(Command::CursorNextChar),
-            Event::KeyUp => cmds.push(Command::HistoryPrev),
-            Event::KeyDown => cmds.push(Command::HistoryNext),
-
-            Event::Char(ch) => cmds.push(Command::Insert(ch)),
-            Event::KeyBackspace => cmds.push(Command::DeletePrevChar),
-            Event::KeyDelete => cmds.push(Command::DeleteNextChar),
-            Event::Ctrl('w') => cmds.push(Command::DeletePrevWord),
-            Event::Ctrl('u') => cmds.push(Command::DeleteLine),
+            Action::AcceptLine => cmds.push(Command::Commit),
+            Action::OperateAndGetNext => {
+                cmds.push(Command::GetNextHistory);
+                cmds.push(Command::Commit);
+            }
+            Action::BackwardChar => cmds.push(Command::CursorPrevChar),
+            Action::ForwardChar => cmds.push(Command::CursorNextChar),
+            Action::BackwardWord => cmds.push(Command::CursorPrevWordHead),
+            Action::ForwardWord => cmds.push(Command::CursorNextWordHead),
+            Action::EndOfLine => cmds.push(Command::CursorEnd),
+            Action::BeginningOfLine => cmds.push(Command::CursorExact(0)),
+            Action::UpHistory => cmds.push(Command::HistoryPrev),
+            Action::DownHistory => cmds.push(Command::HistoryNext),
+
+            Action::SelfInsert => {
+                if let Event::Char(ch) = event {
+                    cmds.push(Command::Insert(ch));
+                }
+            }
+            Action::QuotedInsert => self.quoting = true,
+            Action::BackwardDeleteChar => cmds.push(Command::DeletePrevChar),
+            Action::DeleteChar => cmds.push(Command::DeleteNextChar),
+            Action::BackwardKillWord => {
+                let from = line.prev_word_start();
+                let to = line.cursor();
+                cmds.push(Command::RegisterStore {
+                    reg: '"',
+                    text: line.iter(from..to).map(|(c, _)| c).collect(),
+                });
+                cmds.push(Command::DeleteRange { from, to });
+            }
+            Action::KillWord => {
+                let from = line.cursor();
+                let to = line.next_word_end();
+                cmds.push(Command::RegisterStore {
+                    reg: '"',
+                    text: line.iter(from..to).map(|(c, _)| c).collect(),
+                });
+                cmds.push(Command::DeleteRange { from, to });
+            }
+            Action::KillWholeLine => {
+                cmds.push(Command::RegisterStore {
+                    reg: '"',
+                    text: line.to_string(),
+                });
+                cmds.push(Command::DeleteLine);
+            }
+            Action::KillToEnd => kill_to_end('"', line, cmds),
+            Action::Yank => cmds.push(Command::Yank),
+            Action::YankPop => cmds.push(Command::YankPop),
 
-            Event::KeyTab => cmds.push(Command::TryCompleteFilename),
-            Event::Ctrl('d') => cmds.push(Command::DisplayCompletionCandidate),
+            Action::Complete => cmds.push(Command::TryCompleteFilename),
+            Action::ListChoices => cmds.push(Command::DisplayCompletionCandidate),
+            Action::ReverseMenuComplete => cmds.push(Command::SelectCandidate(-1)),
 
-            Event::Ctrl('p') => cmds.push(Command::CdToParent),
-            Event::Ctrl('o') => cmds.push(Command::CdUndo),
+            Action::CdParent => cmds.push(Command::CdToParent),
+            Action::CdUndo => cmds.push(Command::CdUndo),
+            Action::CdRedo => cmds.push(Command::CdRedo),
 
-            Event::Ctrl('r') => {
-                cmds.push(Command::ChangeModeToSearch);
+            Action::HistorySearch => {
+                cmds.push(Command::ChangeModeToSearch { backward: true });
+            }
+            Action::HistorySearchForward => {
+                cmds.push(Command::ChangeModeToSearch { backward: false });
             }
 
-            Event::Ctrl('n') => cmds.push(Command::DuplicateWord),
+            Action::DuplicateWord => cmds.push(Command::DuplicateWord),
+            Action::ToggleSudo => cmds.push(Command::ToggleSudo),
+            Action::TransposeChars => cmds.push(Command::TransposeChars),
+            Action::TransposeWords => cmds.push(Command::TransposeWords),
+
+            Action::ClearScreen => cmds.push(Command::ClearScreen),
+            Action::FuzzyHistory => cmds.push(Command::FuzzyFind { history: true }),
+            Action::FuzzyFile => cmds.push(Command::FuzzyFind { history: false }),
 
-            Event::Ctrl('l') => cmds.push(Command::ClearScreen),
+            Action::Undo => cmds.push(Command::Undo),
+            Action::Redo => cmds.push(Command::Redo),
 
             _ => {}
         }
@@ -490,6 +934,8 @@ impl EditorMode for InsertMode {
 pub(super) struct VisualMode {
     origin: isize,
     combo: Vec<char>,
+    count: Option<usize>,
+    register: Option<char>,
 }
 
 impl VisualMode {
@@ -497,6 +943,8 @@ impl VisualMode {
         Self {
             origin: origin as isize,
             combo: Vec::new(),
+            count: None,
+            register: None,
         }
     }
 
@@ -504,6 +952,8 @@ impl VisualMode {
         Self {
             origin: isize::MIN,
             combo: Vec::new(),
+            count: None,
+            register: None,
         }
     }
 
@@ -519,6 +969,10 @@ impl VisualMode {
         self.origin == isize::MIN
     }
 
+    fn register(&self) -> char {
+        self.register.unwrap_or('"')
+    }
+
     fn process_text_object(&mut self, event: Event, line: &Line, cmds: &mut Vec<Command>) {
         if self.combo.len() < 2 {
             match event {
@@ -548,77 +1002,132 @@ impl VisualMode {
 }
 
 impl EditorMode for VisualMode {
-    fn process_event(&mut self, event: Event, line: &Line, cmds: &mut Vec<Command>) {
+    fn process_event(
+        &mut self,
+        event: Event,
+        line: &Line,
+        keymap: &Keymap,
+        cmds: &mut Vec<Command>,
+    ) {
+        match (self.combo.first(), event) {
+            (Some('"'), ev) => {
+                if let Some(reg) = register_name(ev) {
+                    self.register = Some(reg);
+                }
+                self.combo.clear();
+                return;
+            }
+            (Some('g'), ev) => {
+                let count = self.count.take().unwrap_or(1);
+                if let Some(action) = goto_action(ev) {
+                    let motion = match action {
+                        Action::ViBackwardWordEnd => Command::CursorPrevWordEnd,
+                        _ => Command::CursorPrevWordEndWide,
+                    };
+                    for _ in 0..count {
+                        cmds.push(motion.clone());
+                    }
+                }
+                self.combo.clear();
+                return;
+            }
+            (None, Event::Char(ch @ '0'..='9')) if ch != '0' || self.count.is_some() => {
+                append_digit(&mut self.count, ch);
+                return;
+            }
+            _ => {}
+        }
+
         match self.combo.first() {
             None => {
-                match event {
-                    Event::KeyEscape | Event::Char('v') => {
+                let count = self.count.take().unwrap_or(1);
+                let Some(action) = keymap.lookup(KeymapMode::Visual, event) else {
+                    return;
+                };
+
+                let start = cmds.len();
+                match action {
+                    Action::ViCmdMode | Action::Abort => {
                         cmds.push(Command::ChangeModeToNormal);
                     }
 
-                    Event::Char(sel @ ('i' | 'a')) => {
-                        self.combo.push(sel);
+                    Action::ViSelectInner => {
+                        self.combo.push('i');
+                    }
+                    Action::ViSelectAround => {
+                        self.combo.push('a');
+                    }
+                    Action::ViSetRegister => {
+                        self.combo.push('"');
+                    }
+
+                    Action::AcceptLine => cmds.push(Command::Commit),
+                    Action::BackwardChar => cmds.push(Command::CursorPrevChar),
+                    Action::ForwardChar => cmds.push(Command::CursorNextChar),
+                    Action::ViForwardWord => cmds.push(Command::CursorNextWordHead),
+                    Action::ViForwardBlankWord => cmds.push(Command::CursorNextWordHeadWide),
+                    Action::ViForwardWordEnd => cmds.push(Command::CursorNextWordEnd),
+                    Action::ViForwardBlankWordEnd => cmds.push(Command::CursorNextWordEndWide),
+                    Action::ViBackwardWord => cmds.push(Command::CursorPrevWordHead),
+                    Action::ViBackwardBlankWord => cmds.push(Command::CursorPrevWordHeadWide),
+                    Action::ViBackwardWordEnd => cmds.push(Command::CursorPrevWordEnd),
+                    Action::ViBackwardBlankWordEnd => cmds.push(Command::CursorPrevWordEndWide),
+                    Action::ViMatchBracket => cmds.push(Command::CursorMatchingPair),
+                    Action::ViGotoPrefix => {
+                        // the count applies to the motion after `g`
+                        self.count = Some(count);
+                        self.combo.push('g');
                     }
 
-                    Event::KeyReturn => cmds.push(Command::Commit),
-                    Event::KeyLeft | Event::Char('h') => cmds.push(Command::CursorPrevChar),
-                    Event::KeyRight | Event::Char('l') => cmds.push(Command::CursorNextChar),
-                    Event::Char('w') => cmds.push(Command::CursorNextWordHead),
-                    Event::Char('W') => cmds.push(Command::CursorNextWordHeadWide),
-                    Event::Char('e') => cmds.push(Command::CursorNextWordEnd),
-                    Event::Char('E') => cmds.push(Command::CursorNextWordEndWide),
-                    Event::Char('b') => cmds.push(Command::CursorPrevWordHead),
-                    Event::Char('B') => cmds.push(Command::CursorPrevWordHeadWide),
-
-                    Event::Char('o') => {
+                    Action::ViExchangeEnds => {
                         if !self.is_line_mode() {
                             cmds.push(Command::CursorExact(self.origin as usize));
                             self.origin = line.cursor() as isize;
                         }
                     }
 
-                    Event::Char('$') => {
+                    Action::EndOfLine => {
                         cmds.push(Command::CursorEnd);
                     }
-                    Event::Char('^') => {
+                    Action::ViFirstNonBlank => {
                         cmds.push(Command::CursorBegin);
                     }
-                    Event::Char('0') => {
+                    Action::BeginningOfLine => {
                         cmds.push(Command::CursorExact(0));
                     }
 
-                    Event::Char('D') => {
+                    Action::KillWholeLine | Action::KillToEnd => {
                         cmds.push(Command::MakeCheckPoint);
                         cmds.push(Command::RegisterStore {
-                            reg: '"',
+                            reg: self.register(),
                             text: line.to_string(),
                         });
                         cmds.push(Command::DeleteLine);
                         cmds.push(Command::ChangeModeToNormal);
                     }
-                    Event::Char('C') | Event::Char('S') => {
+                    Action::ViChangeWholeLine | Action::ViChangeEol => {
                         cmds.push(Command::MakeCheckPoint);
                         cmds.push(Command::RegisterStore {
-                            reg: '"',
+                            reg: self.register(),
                             text: line.to_string(),
                         });
                         cmds.push(Command::ChangeModeToInsert);
                         cmds.push(Command::DeleteLine);
                     }
-                    Event::Char('Y') => {
+                    Action::ViYankWholeLine => {
                         cmds.push(Command::RegisterStore {
-                            reg: '"',
+                            reg: self.register(),
                             text: line.to_string(),
                         });
                         cmds.push(Command::ChangeModeToNormal);
                     }
 
-                    Event::Char('d') | Event::Char('x') => {
+                    Action::ViDelete | Action::ViDeleteChar => {
                         cmds.push(Command::MakeCheckPoint);
 
                         if self.is_line_mode() {
                             cmds.push(Command::RegisterStore {
-                                reg: '"',
+                                reg: self.register(),
                                 text: line.to_string(),
                             });
 
@@ -633,7 +1142,7 @@ impl EditorMode for VisualMode {
 
                             let part: String = line.iter(from..to).map(|(ch, _)| ch).collect();
                             cmds.push(Command::RegisterStore {
-                                reg: '"',
+                                reg: self.register(),
                                 text: part,
                             });
 
@@ -641,13 +1150,13 @@ impl EditorMode for VisualMode {
                         }
                         cmds.push(Command::ChangeModeToNormal);
                     }
-                    Event::Char('c') | Event::Char('s') => {
+                    Action::ViChange | Action::ViSubstitute => {
                         cmds.push(Command::MakeCheckPoint);
 
                         cmds.push(Command::ChangeModeToInsert);
                         if self.is_line_mode() {
                             cmds.push(Command::RegisterStore {
-                                reg: '"',
+                                reg: self.register(),
                                 text: line.to_string(),
                             });
 
@@ -662,17 +1171,17 @@ impl EditorMode for VisualMode {
 
                             let part: String = line.iter(from..to).map(|(ch, _)| ch).collect();
                             cmds.push(Command::RegisterStore {
-                                reg: '"',
+                                reg: self.register(),
                                 text: part,
                             });
 
                             cmds.push(Command::DeleteRange { from, to });
                         }
                     }
-                    Event::Char('y') => {
+                    Action::ViYank => {
                         if self.is_line_mode() {
                             cmds.push(Command::RegisterStore {
-                                reg: '"',
+                                reg: self.register(),
                                 text: line.to_string(),
                             });
 
@@ -687,7 +1196,7 @@ impl EditorMode for VisualMode {
 
                             let part: String = line.iter(from..to).map(|(ch, _)| ch).collect();
                             cmds.push(Command::RegisterStore {
-                                reg: '"',
+                                reg: self.register(),
                                 text: part,
                             });
                         }
@@ -696,6 +1205,13 @@ impl EditorMode for VisualMode {
 
                     _ => {}
                 }
+
+                if is_motion(action) {
+                    let once = cmds[start..].to_vec();
+                    for _ in 1..count {
+                        cmds.extend(once.iter().cloned());
+                    }
+                }
             }
             Some(_) => {
                 self.process_text_object(event, line, cmds);
@@ -703,3 +1219,146 @@ impl EditorMode for VisualMode {
         }
     }
 }
+
+// counts larger than this are truncated
+const MAX_COUNT: usize = 999;
+
+// moves the cursor of `line` in the same way as the cursor commands are executed
+fn move_cursor(line: &mut Line, motion: &Command) {
+    match motion {
+        Command::CursorPrevChar => line.cursor_prev_char(),
+        Command::CursorNextChar => line.cursor_next_char(),
+        Command::CursorPrevCharMatch(ch) => line.cursor_prev_char_match(*ch),
+        Command::CursorNextCharMatch(ch) => line.cursor_next_char_match(*ch),
+        Command::CursorPrevCharTill { target, repeat } => {
+            line.cursor_prev_char_till(*target, *repeat)
+        }
+        Command::CursorNextCharTill { target, repeat } => {
+            line.cursor_next_char_till(*target, *repeat)
+        }
+        Command::CursorPrevWordHead => line.cursor_prev_word_head(false),
+        Command::CursorPrevWordHeadWide => line.cursor_prev_word_head(true),
+        Command::CursorNextWordHead => line.cursor_next_word_head(false),
+        Command::CursorNextWordHeadWide => line.cursor_next_word_head(true),
+        Command::CursorNextWordEnd => line.cursor_next_word_end(false),
+        Command::CursorNextWordEndWide => line.cursor_next_word_end(true),
+        Command::CursorPrevWordEnd => line.cursor_prev_word_end(false),
+        Command::CursorPrevWordEndWide => line.cursor_prev_word_end(true),
+        Command::CursorMatchingPair => {
+            if let Some(pos) = text_object::find_matching_pair(line, line.cursor()) {
+                line.cursor_exact(pos);
+            }
+        }
+        Command::CursorEnd => line.cursor_end_of_line(),
+        Command::CursorBegin => line.cursor_begin_of_line(),
+        Command::CursorExact(pos) => line.cursor_exact(*pos),
+        _ => {}
+    }
+}
+
+// returns the character which `event` inputs, including control characters
+fn literal_char(event: Event) -> Option<char> {
+    match event {
+        Event::Char(ch) => Some(ch),
+        Event::Ctrl(ch) if ch.is_ascii() => Some((ch.to_ascii_uppercase() as u8 ^ 0x40) as char),
+        Event::KeyTab => Some('\t'),
+        Event::KeyReturn => Some('\r'),
+        Event::KeyEscape => Some('\x1b'),
+        Event::KeyBackspace => Some('\x7f'),
+        _ => None,
+    }
+}
+
+// returns the register named by `"x`; uppercase letters append to the lowercase ones
+// and `+` is the system clipboard
+fn register_name(event: Event) -> Option<char> {
+    match event {
+        Event::Char(ch @ ('"' | '+' | 'a'..='z' | 'A'..='Z')) => Some(ch),
+        _ => None,
+    }
+}
+
+// returns the action of the key following `g`, i.e. "ge" and "gE"
+fn goto_action(event: Event) -> Option<Action> {
+    match event {
+        Event::Char('e') => Some(Action::ViBackwardWordEnd),
+        Event::Char('E') => Some(Action::ViBackwardBlankWordEnd),
+        _ => None,
+    }
+}
+
+// `kind` is one of 'f', 'F', 't' and 'T'
+fn find_motion(kind: char, target: char, repeat: bool) -> Command {
+    match kind {
+        'f' => Command::CursorNextCharMatch(target),
+        'F' => Command::CursorPrevCharMatch(target),
+        't' => Command::CursorNextCharTill { target, repeat },
+        _ => Command::CursorPrevCharTill { target, repeat },
+    }
+}
+
+// a till motion repeated by `;` or a count doesn't stop just before the same character
+fn repeated(motion: &Command) -> Command {
+    match *motion {
+        Command::CursorNextCharTill { target, .. } => Command::CursorNextCharTill {
+            target,
+            repeat: true,
+        },
+        Command::CursorPrevCharTill { target, .. } => Command::CursorPrevCharTill {
+            target,
+            repeat: true,
+        },
+        ref motion => motion.clone(),
+    }
+}
+
+fn append_digit(count: &mut Option<usize>, digit: char) {
+    let digit = digit.to_digit(10).unwrap() as usize;
+    let n = count.unwrap_or(0).saturating_mul(10).saturating_add(digit);
+    *count = Some(n.min(MAX_COUNT));
+}
+
+fn is_motion(action: Action) -> bool {
+    matches!(
+        action,
+        Action::BackwardChar
+            | Action::ForwardChar
+            | Action::UpHistory
+            | Action::DownHistory
+            | Action::ViForwardWord
+            | Action::ViForwardBlankWord
+            | Action::ViForwardWordEnd
+            | Action::ViForwardBlankWordEnd
+            | Action::ViBackwardWord
+            | Action::ViBackwardBlankWord
+            | Action::ViBackwardWordEnd
+            | Action::ViBackwardBlankWordEnd
+            | Action::ViRepeatFind
+            | Action::ViRevRepeatFind
+    )
+}
+
+// whether the characters under both ends of a motion are included in its range
+fn is_inclusive(motion: &Command) -> bool {
+    matches!(
+        motion,
+        Command::CursorNextCharMatch(_)
+            | Command::CursorNextCharTill { .. }
+            | Command::CursorNextWordEnd
+            | Command::CursorNextWordEndWide
+            | Command::CursorPrevWordEnd
+            | Command::CursorPrevWordEndWide
+            | Command::CursorMatchingPair
+    )
+}
+
+fn kill_to_end(reg: char, line: &Line, cmds: &mut Vec<Command>) {
+    let from = line.cursor();
+    let to = line.len();
+    let cursor_to_end: String = line.iter(from..to).map(|(c, _)| c).collect();
+    cmds.push(Command::RegisterStore {
+        reg,
+        text: cursor_to_end,
+    });
+    cmds.push(Command::DeleteRange { from, to });
+}
@@ -0,0 +1,259 @@
+//! Draws the prompt and the line, printing only what has changed since the last time
+
+use std::cmp::Ordering;
+use std::fmt::Write as _;
+
+/// A grapheme printed with a style
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cell {
+    /// an SGR sequence, or empty for the default style
+    pub style: String,
+    pub text: String,
+    pub width: usize,
+}
+
+/// What is shown from the first row of the prompt
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub prompt: String,
+    pub prompt_width: usize,
+    pub cells: Vec<Cell>,
+    /// the rows shown below the line like the completion menu
+    pub menu: Vec<String>,
+    /// the number of the cells before the cursor
+    pub cursor: usize,
+    pub terminal_width: usize,
+}
+
+impl Frame {
+    // the positions where the prompt ends and where the cells start, and where the last one ends
+    fn layout(&self) -> ((usize, usize), Vec<(usize, usize)>) {
+        let width = self.terminal_width;
+        let wrapped = |(row, col): (usize, usize)| {
+            if col >= width {
+                (row + 1, 0)
+            } else {
+                (row, col)
+            }
+        };
+
+        // returns where a cell starts, wrapping it if it doesn't fit in the row
+        let advance = |(row, col): &mut (usize, usize), cell_width: usize| {
+            if *col + cell_width > width {
+                *row += 1;
+                *col = 0;
+            }
+            let start = (*row, *col);
+            *col += cell_width;
+            start
+        };
+
+        let mut at = (0, 0);
+        for _ in 0..self.prompt_width {
+            advance(&mut at, 1);
+        }
+        let prompt_end = wrapped(at);
+
+        let mut positions: Vec<_> = (self.cells.iter())
+            .map(|cell| advance(&mut at, cell.width))
+            .collect();
+        positions.push(wrapped(at));
+        (prompt_end, positions)
+    }
+}
+
+/// Draws `next` over `prev`, or from scratch if `prev` is None, in which case the cursor is at
+/// `cursor_row` counted from the first row of the prompt.
+/// Returns the output and (row of the cursor, last row).
+pub fn render(prev: Option<&Frame>, next: &Frame, cursor_row: usize) -> (String, (usize, usize)) {
+    let mut out = String::new();
+    let (prompt_end, positions) = next.layout();
+    let end = positions[next.cells.len()];
+    let cursor = positions[next.cursor];
+    let last_row = end.0 + next.menu.len();
+
+    // the prompt is drawn again if anything but the line is changed
+    let prev = prev.filter(|prev| {
+        (&prev.prompt, prev.prompt_width, prev.terminal_width)
+            == (&next.prompt, next.prompt_width, next.terminal_width)
+    });
+
+    let redraw_from = match prev {
+        None => {
+            if cursor_row > 0 {
+                let _ = write!(out, "\x1b[{cursor_row}A");
+            }
+            out.push_str("\r\x1b[J");
+            out.push_str(&next.prompt);
+            0
+        }
+
+        Some(prev) => {
+            let (_, prev_positions) = prev.layout();
+            let prev_cursor = prev_positions[prev.cursor];
+            let changed = |(a, b): (&Cell, &Cell)| a != b;
+            let first = prev
+                .cells
+                .iter()
+                .zip(&next.cells)
+                .position(changed)
+                .unwrap_or_else(|| prev.cells.len().min(next.cells.len()));
+
+            let same_layout = prev.cells.len() == next.cells.len()
+                && (prev.cells.iter().zip(&next.cells)).all(|(a, b)| a.width == b.width);
+            if same_layout {
+                // only the changed cells are printed over
+                let mut at = prev_cursor;
+                if first < next.cells.len() {
+                    let last = (prev.cells.iter().zip(&next.cells))
+                        .rposition(changed)
+                        .unwrap_or(first);
+                    move_cursor(&mut out, at, positions[first]);
+                    print_cells(&mut out, &next.cells[first..=last]);
+                    let (row, col) = positions[last];
+                    at = (row, col + next.cells[last].width);
+                }
+                if prev.menu != next.menu {
+                    move_cursor(&mut out, at, end);
+                    out.push_str("\x1b[J");
+                    print_menu(&mut out, &next.menu);
+                    at = (last_row, usize::MAX);
+                }
+                move_cursor(&mut out, at, cursor);
+                return (out, (cursor.0, last_row));
+            }
+
+            // the rest of the line is shifted
+            let start = if first == 0 {
+                prompt_end
+            } else {
+                let (row, col) = positions[first - 1];
+                let col = col + next.cells[first - 1].width;
+                if col >= next.terminal_width {
+                    (row + 1, 0)
+                } else {
+                    (row, col)
+                }
+            };
+            move_cursor(&mut out, prev_cursor, start);
+            if positions[first].0 > start.0 {
+                // a wide character is wrapped leaving the end of the row
+                out.push_str("\x1b[K");
+            }
+            first
+        }
+    };
+
+    print_cells(&mut out, &next.cells[redraw_from..]);
+    if end.0 > 0 && end.1 == 0 {
+        // move the cursor to the next row which the terminal defers
+        out.push_str(" \r");
+    }
+    out.push_str("\x1b[J");
+    let mut at = end;
+    if !next.menu.is_empty() {
+        print_menu(&mut out, &next.menu);
+        at = (last_row, usize::MAX);
+    }
+    move_cursor(&mut out, at, cursor);
+    (out, (cursor.0, last_row))
+}
+
+// prints the cells, changing the style only where it differs from the previous cell
+fn print_cells(out: &mut String, cells: &[Cell]) {
+    let mut style = "";
+    for cell in cells {
+        if cell.style != style {
+            if !style.is_empty() {
+                out.push_str("\x1b[m");
+            }
+            out.push_str(&cell.style);
+            style = &cell.style;
+        }
+        out.push_str(&cell.text);
+    }
+    if !style.is_empty() {
+        out.push_str("\x1b[m");
+    }
+}
+
+fn print_menu(out: &mut String, menu: &[String]) {
+    for menu_row in menu {
+        let _ = write!(out, "\r\n{menu_row}");
+    }
+}
+
+// the column of `from` may be unknown, or at the end of a row where the terminal defers wrapping
+fn move_cursor(out: &mut String, from: (usize, usize), to: (usize, usize)) {
+    if from == to {
+        return;
+    }
+    let _ = match from.0.cmp(&to.0) {
+        Ordering::Greater => write!(out, "\x1b[{}A", from.0 - to.0),
+        Ordering::Less => write!(out, "\x1b[{}B", to.0 - from.0),
+        Ordering::Equal => Ok(()),
+    };
+    out.push('\r');
+    if to.1 > 0 {
+        let _ = write!(out, "\x1b[{}C", to.1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(text: &str, cursor: usize, menu: &[&str]) -> Frame {
+        Frame {
+            prompt: "$ ".to_owned(),
+            prompt_width: 2,
+            cells: text
+                .chars()
+                .map(|ch| Cell {
+                    style: if ch == 'x' { "\x1b[31m" } else { "" }.to_owned(),
+                    text: ch.to_string(),
+                    width: 1,
+                })
+                .collect(),
+            menu: menu.iter().map(|row| row.to_string()).collect(),
+            cursor,
+            terminal_width: 5,
+        }
+    }
+
+    #[test]
+    fn render_difference() {
+        let first = frame("ab", 2, &[]);
+        let (out, rows) = render(None, &first, 1);
+        assert_eq!(out, "\x1b[1A\r\x1b[J$ ab\x1b[J");
+        assert_eq!(rows, (0, 0));
+
+        // typing at the end prints only the new characters, wrapped at the fifth column
+        let typed = frame("abcd", 4, &[]);
+        let (out, rows) = render(Some(&first), &typed, 0);
+        assert_eq!(out, "cd\x1b[J");
+        assert_eq!(rows, (1, 1));
+
+        // deleting them erases the rest
+        let (out, _) = render(Some(&typed), &first, 1);
+        assert_eq!(out, "\x1b[1A\r\x1b[4C\x1b[J");
+
+        // only the changed cell is printed over if the others stay
+        let colored = frame("xb", 1, &[]);
+        let (out, _) = render(Some(&first), &colored, 0);
+        assert_eq!(out, "\r\x1b[2C\x1b[31mx\x1b[m");
+
+        let with_menu = frame("ab", 2, &["m1"]);
+        let (out, rows) = render(Some(&first), &with_menu, 0);
+        assert_eq!(out, "\x1b[J\r\nm1\x1b[1A\r\x1b[4C");
+        assert_eq!(rows, (0, 1));
+
+        // the prompt is changed
+        let other_prompt = Frame {
+            prompt: "> ".to_owned(),
+            ..first.clone()
+        };
+        let (out, _) = render(Some(&first), &other_prompt, 0);
+        assert_eq!(out, "\r\x1b[J> ab\x1b[J");
+    }
+}
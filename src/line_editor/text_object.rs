@@ -142,3 +142,59 @@ pub fn find_range(line: &Line, selector: Selector, object: TextObject) -> (usize
         }
     }
 }
+
+const PAIRS: [(char, char); 3] = [('(', ')'), ('[', ']'), ('{', '}')];
+
+/// Returns the position of the bracket matching the first one at or after `pos`
+pub fn find_matching_pair(line: &Line, pos: usize) -> Option<usize> {
+    let (start, ch) = (pos..line.len())
+        .map(|i| (i, line.char_at(i).unwrap()))
+        .find(|(_, ch)| PAIRS.iter().any(|(b, e)| ch == b || ch == e))?;
+
+    let mut depth = 0;
+    if let Some(&(begin, end)) = PAIRS.iter().find(|(b, _)| *b == ch) {
+        for i in start..line.len() {
+            let ch = line.char_at(i).unwrap();
+            if ch == begin {
+                depth += 1;
+            } else if ch == end {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+        }
+    } else {
+        let &(begin, end) = PAIRS.iter().find(|(_, e)| *e == ch).unwrap();
+        for i in (0..=start).rev() {
+            let ch = line.char_at(i).unwrap();
+            if ch == end {
+                depth += 1;
+            } else if ch == begin {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matching_pair() {
+        let line = Line::from("f(a[0], (b)) {}");
+        assert_eq!(find_matching_pair(&line, 0), Some(11));
+        assert_eq!(find_matching_pair(&line, 3), Some(5));
+        assert_eq!(find_matching_pair(&line, 5), Some(3));
+        assert_eq!(find_matching_pair(&line, 11), Some(1));
+        assert_eq!(find_matching_pair(&line, 12), Some(14));
+
+        assert_eq!(find_matching_pair(&Line::from("(a"), 0), None);
+        assert_eq!(find_matching_pair(&Line::from("abc"), 0), None);
+    }
+}
@@ -1,106 +1,340 @@
-mod completion;
-mod core;
-mod line_editor;
-mod terminal_size;
-mod utils;
+use shell::config_dir;
+use shell::{completion, config, core, line_editor, plugin, prompt, terminal_size};
+
+const USAGE: &str = "\
+Usage: shell [OPTION]...
+
+  -i                run interactively, which is the only supported mode
+  -l, --login       run as a login shell, which evaluates ~/.myshell/login first
+      --norc        don't load ~/.config/myshell/config.toml nor evaluate ~/.myshell/startup
+      --parse-only  print the syntax tree of each line read from stdin without running it
+  -h, --help        show this help and exit
+  -V, --version     show the version and exit";
+
+/// Options given on the command line
+#[derive(Debug, Default)]
+struct Args {
+    login: bool,
+    norc: bool,
+    parse_only: bool,
+}
+
+// parses the command line, or exits for --help, --version and invalid options
+fn parse_args() -> Args {
+    let mut args = Args::default();
+    let mut argv = std::env::args();
+
+    // invoked as a login shell by login(1) like "-shell"
+    if argv.next().is_some_and(|arg0| arg0.starts_with('-')) {
+        args.login = true;
+    }
+
+    for arg in argv {
+        match arg.as_str() {
+            "-i" => {}
+            "-l" | "--login" => args.login = true,
+            "--norc" => args.norc = true,
+            "--parse-only" => args.parse_only = true,
+            "-h" | "--help" => {
+                println!("{USAGE}");
+                std::process::exit(0);
+            }
+            "-V" | "--version" => {
+                println!("shell {}", env!("CARGO_PKG_VERSION"));
+                std::process::exit(0);
+            }
+            _ => {
+                eprintln!("shell: {arg}: invalid option\n{USAGE}");
+                std::process::exit(2);
+            }
+        }
+    }
+    args
+}
 
 fn main() {
+    let args = parse_args();
+    if args.parse_only {
+        std::process::exit(parse_only());
+    }
+    if !nix::unistd::isatty(nix::libc::STDIN_FILENO).unwrap_or(false) {
+        eprintln!("shell: stdin is not a terminal; only interactive use is supported");
+        std::process::exit(2);
+    }
+
     terminal_size::install_sigwinch_handler();
 
     let mut line_editor = line_editor::LineEditor::new();
+    if let Some(mut dir) = config_dir() {
+        dir.push("completions");
+        line_editor.command_completion.load_specs(&dir);
+    }
     let mut shell = core::Shell::new();
-    let mut last_status = eval_startup(&mut shell).unwrap_or(0);
 
-    loop {
+    // plugin name for each prompt segment
+    let mut prompt_segments = std::collections::HashMap::new();
+    if let Some(mut dir) = config_dir() {
+        dir.push("plugins");
+        for plugin in plugin::load(&dir) {
+            for name in plugin.builtins {
+                shell.register_plugin_command(&name, &plugin.path);
+            }
+            for command in plugin.completions {
+                let program = plugin.path.to_string_lossy().into_owned();
+                let completion =
+                    completion::ExternalCompletion::new(vec![program, "complete".to_owned()]);
+                line_editor
+                    .command_completion
+                    .add_completion(command, Box::new(completion));
+            }
+            for name in plugin.prompt_segments {
+                prompt_segments.insert(name, plugin.path.clone());
+            }
+        }
+    }
+    let mut last_status = 0;
+    if args.login {
+        last_status = config::eval_file(&mut shell, "login").unwrap_or(last_status);
+    }
+    if !args.norc && shell.exit_status().is_none() {
+        last_status = config::load_rc(&mut shell).unwrap_or(last_status);
+    }
+    // how long the command of the last line took
+    let mut last_duration: Option<std::time::Duration> = None;
+    // whether the title before the `title` option was enabled is saved
+    let mut title_saved = false;
+
+    let exit_status = loop {
+        if let Some(status) = shell.exit_status() {
+            break status;
+        }
+
+        if shell.option("direnv") {
+            shell.load_direnv();
+        }
+        shell.run_hooks("precmd");
+        if let Some(status) = shell.exit_status() {
+            break status;
+        }
+
         terminal_size::update();
+        prompt::start_on_new_line(terminal_size::get_cols() as usize);
         shell.update_variables();
+        shell.sync_commands();
 
         line_editor
             .command_completion
             .update_commands(shell.list_commands());
+        line_editor
+            .command_completion
+            .update_jobs(shell.list_jobs());
+        line_editor
+            .command_completion
+            .update_rules(shell.completion_rules());
+        line_editor.keymap.clone_from(shell.keymap());
+        line_editor.abbreviations.clone_from(shell.abbreviations());
+        line_editor.colors = colors(&shell);
+        line_editor.autopair = shell.option("autopair");
+        line_editor.mouse = shell.option("mouse");
+        line_editor.fuzzy_finder = shell.var("FUZZY_FINDER");
+        completion::sort_files_by_mtime(shell.option("mtimesort"));
+
+        let title = shell.option("title");
+        if title && !title_saved {
+            prompt::save_title();
+        } else if !title && title_saved {
+            prompt::restore_title();
+        }
+        title_saved = title;
+        if title {
+            match shell.cwd() {
+                Ok(cwd) => prompt::set_title(&prompt::abbreviate_home(&cwd)),
+                Err(_) => prompt::set_title("unknown"),
+            }
+        }
 
         let prompt_prefix = {
-            let status_style = if last_status == 0 {
-                // successful
-                "\x1b[32m"
-            } else if last_status < 128 {
-                // error
-                "\x1b[31m"
-            } else {
-                // signaled
-                "\x1b[33m"
+            // durations shorter than $CMD_DURATION_MIN seconds are not shown
+            let min_duration = shell
+                .var("CMD_DURATION_MIN")
+                .and_then(|secs| secs.parse::<f64>().ok())
+                .unwrap_or(0.0);
+            let ctx = prompt::PromptContext {
+                status: last_status,
+                cwd: shell.cwd().ok(),
+                jobs: shell.jobs(),
+                duration: last_duration.filter(|d| d.as_secs_f64() >= min_duration),
+                segments: prompt_segments.clone(),
             };
-
-            let cwd_style = "\x1b[1;35m";
-            let cwd = match std::env::current_dir() {
-                Err(_) => "unknown".to_owned(),
-                Ok(cwd) => std::env::var("HOME")
-                    .ok()
-                    .and_then(|home| cwd.strip_prefix(&home).ok())
-                    .map(|p| format!("~/{}", p.display()))
-                    .unwrap_or_else(|| cwd.display().to_string()),
+            let prompt = match shell.var("PROMPT_COMMAND") {
+                Some(command) => {
+                    // the external command decides whether to show the duration
+                    let ctx = prompt::PromptContext {
+                        duration: last_duration,
+                        ..ctx
+                    };
+                    let output = prompt::run_command(&command, &ctx, shell.env_vars());
+                    let (above, last_row) = prompt::from_output(&output.unwrap_or_default());
+                    // only the last row is redrawn by the line editor
+                    print!("\x1b[m{above}");
+                    last_row
+                }
+                None => {
+                    let template = shell.var("PROMPT");
+                    let template = template.as_deref().unwrap_or(prompt::DEFAULT_PROMPT);
+                    // substitutions taking longer than $PROMPT_TIMEOUT seconds are killed
+                    let timeout = shell
+                        .var("PROMPT_TIMEOUT")
+                        .and_then(|secs| secs.parse::<f64>().ok())
+                        .filter(|secs| secs.is_finite() && *secs >= 0.0)
+                        .unwrap_or(1.0);
+                    let template =
+                        shell.expand_prompt(template, std::time::Duration::from_secs_f64(timeout));
+                    prompt::expand(&template, &ctx)
+                }
             };
+            // styles left by the last command are cleared first
+            format!("(\x1b[m){prompt}")
+        };
 
-            let job_indicator = match shell.jobs() {
-                0 => "".to_owned(),
-                1 => "*".to_owned(),
-                num => format!("*{num}"),
-            };
+        last_duration = None;
+        // jobs which finished while a command was running
+        for message in job_notifications(&mut shell) {
+            println!("{message}");
+        }
 
-            format!(
-                "(\x1b[m)[({status_style}){:3}(\x1b[m)] ({cwd_style}){}(\x1b[m) {}",
-                last_status, cwd, job_indicator
-            )
+        let edited = {
+            let fd = shell.child_event_fd();
+            let mut messages = || job_notifications(&mut shell);
+            let notifier = line_editor::Notifier {
+                fd,
+                messages: &mut messages,
+            };
+            line_editor.read_line(prompt_prefix, notifier)
         };
-
-        match line_editor.read_line(prompt_prefix) {
+        match edited {
             Ok(line) => {
                 let line = line.trim();
                 if !line.is_empty() {
+                    if title {
+                        prompt::set_title(line);
+                    }
+                    shell.set_var("CMD_LINE", line.to_owned());
+                    shell.run_hooks("preexec");
+
+                    let started = std::time::Instant::now();
                     last_status = shell.eval(line);
+                    let elapsed = started.elapsed();
+                    line_editor.record_duration(elapsed);
+                    shell.set_var("CMD_DURATION", elapsed.as_millis().to_string());
+                    last_duration = Some(elapsed);
+
+                    // let the user fix the line with a syntax error
+                    if let Some(pos) = core::Shell::syntax_error_at(line) {
+                        line_editor.prefill(line, pos);
+                    }
                 }
             }
 
             Err(line_editor::EditError::Aborted) => {}
 
             Err(line_editor::EditError::Exitted) => {
-                if shell.jobs() == 0 {
-                    break;
-                } else {
-                    println!("You have suspended jobs.");
+                // exit with the status of the last command as `exit` does
+                match shell.request_exit(last_status) {
+                    Ok(()) => break last_status,
+                    Err(_) => {
+                        println!("You have suspended jobs. Press Ctrl-D again to terminate them.")
+                    }
                 }
             }
         }
+    };
+
+    if title_saved {
+        prompt::restore_title();
+    }
+
+    // save the history before exiting
+    drop(line_editor);
+    drop(shell);
+    std::process::exit(exit_status);
+}
+
+// the styles of the line, which are changed by $COLOR_COMMAND and so on
+fn colors(shell: &core::Shell) -> line_editor::Colors {
+    let color = |name: &str, default: String| match shell.var(name) {
+        Some(names) => prompt::sgr(&names),
+        None => default,
+    };
+    let default = line_editor::Colors::default();
+    line_editor::Colors {
+        command: color("COLOR_COMMAND", default.command),
+        unknown_command: color("COLOR_UNKNOWN_COMMAND", default.unknown_command),
+        highlight: color("COLOR_HIGHLIGHT", default.highlight),
+        suggestion: color("COLOR_SUGGESTION", default.suggestion),
     }
 }
 
-fn eval_startup(shell: &mut core::Shell) -> Option<i32> {
-    use std::io::{BufRead as _, BufReader};
+// notifies of the jobs finished in the background which took $JOB_NOTIFY_MIN seconds or longer
+// in the ways listed in $JOB_NOTIFY: "bell", "osc9" and "message", which is returned
+fn job_notifications(shell: &mut core::Shell) -> Vec<String> {
+    use std::io::Write as _;
 
-    let app_dir = application_dir()?;
-    let mut file_path = app_dir;
-    file_path.push("startup");
+    let finished = shell.reap_jobs();
+    let ways = shell.var("JOB_NOTIFY").unwrap_or_default();
+    let min_duration = shell
+        .var("JOB_NOTIFY_MIN")
+        .and_then(|secs| secs.parse::<f64>().ok())
+        .unwrap_or(0.0);
 
-    let file = match std::fs::File::open(&file_path) {
-        Ok(file) => file,
-        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return None,
-        _ => return Some(1),
-    };
+    let mut messages = Vec::new();
+    for job in finished {
+        // jobs killed by a signal are always told as bash does
+        if let Some(signal) = job.killed_by {
+            messages.push(format!("[{}]  {signal}  {}", job.id, job.command));
+            continue;
+        }
+        if job.elapsed.as_secs_f64() < min_duration {
+            continue;
+        }
+        let state = match job.status {
+            0 => "Done".to_owned(),
+            status => format!("Exit {status}"),
+        };
+        let elapsed = prompt::format_duration(job.elapsed);
+        for way in ways.split(|ch: char| ch == ',' || ch.is_whitespace()) {
+            match way {
+                "bell" => print!("\x07"),
+                "osc9" => print!("\x1b]9;[{}] {state}: {}\x07", job.id, job.command),
+                "message" => messages.push(format!(
+                    "[{}]  {state} ({elapsed})  {}",
+                    job.id, job.command
+                )),
+                _ => {}
+            }
+        }
+    }
+    let _ = std::io::stdout().flush();
+    messages
+}
+
+// prints the syntax tree of each line from stdin, and returns 2 if any of them has an error
+fn parse_only() -> i32 {
+    use std::io::BufRead as _;
 
     let mut status = 0;
-    for line in BufReader::new(file).lines().filter_map(|r| r.ok()) {
+    for line in std::io::stdin().lock().lines().map_while(Result::ok) {
         let line = line.trim();
-        if !line.is_empty() {
-            status = shell.eval(line);
+        if line.is_empty() {
+            continue;
+        }
+        match core::Shell::dump_syntax_tree(line) {
+            Ok(tree) => println!("{tree}"),
+            Err(err) => {
+                eprintln!("shell: {err}");
+                status = 2;
+            }
         }
     }
-    Some(status)
-}
-
-// TODO: consider being XDG complient
-fn application_dir() -> Option<std::path::PathBuf> {
-    let home = std::env::var_os("HOME")?;
-    let mut p = std::path::PathBuf::from(home);
-    p.push(".myshell");
-    Some(p)
+    status
 }
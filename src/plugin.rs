@@ -0,0 +1,91 @@
+//! Plugins are executables in the `plugins` directory under the config directory.
+//! Each of them is asked what it provides with `PLUGIN register`, which prints lines of:
+//!
+//! - `builtin NAME`: the command NAME runs `PLUGIN builtin NAME ARGS...`
+//! - `complete COMMAND`: the arguments of COMMAND are completed with the output of
+//!   `PLUGIN complete COMMAND ARGS...`, a candidate per line optionally followed by a tab and
+//!   its description
+//! - `prompt NAME`: `\p{NAME}` in `PROMPT` is replaced with the output of `PLUGIN prompt NAME`
+
+use std::path::{Path, PathBuf};
+
+/// What a plugin provides
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Plugin {
+    pub path: PathBuf,
+    pub builtins: Vec<String>,
+    pub completions: Vec<String>,
+    pub prompt_segments: Vec<String>,
+}
+
+/// Finds the plugins in `dir`, ordered by name
+pub fn load(dir: &Path) -> Vec<Plugin> {
+    use std::os::unix::fs::PermissionsExt as _;
+
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|ent| ent.ok())
+        .map(|ent| ent.path())
+        .filter(|path| {
+            path.metadata()
+                .is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+        })
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| {
+            let mut command = std::process::Command::new(&path);
+            command.arg("register");
+            match crate::completion::run_helper(&mut command) {
+                Some(output) if output.status.success() => {
+                    let output = String::from_utf8_lossy(&output.stdout);
+                    Some(parse_registration(path, &output))
+                }
+                _ => {
+                    eprintln!("plugin: {}: failed to register", path.display());
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn parse_registration(path: PathBuf, output: &str) -> Plugin {
+    let mut plugin = Plugin {
+        path,
+        ..Plugin::default()
+    };
+    for line in output.lines() {
+        match line.split_whitespace().collect::<Vec<_>>().as_slice() {
+            ["builtin", name] => plugin.builtins.push(name.to_string()),
+            ["complete", command] => plugin.completions.push(command.to_string()),
+            ["prompt", name] => plugin.prompt_segments.push(name.to_string()),
+            [] => {}
+            _ => eprintln!("plugin: {}: unknown entry: {line}", plugin.path.display()),
+        }
+    }
+    plugin
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plugin_registration() {
+        let output = "builtin greet\ncomplete greet\n\nprompt git\n";
+        assert_eq!(
+            parse_registration("/plugins/git".into(), output),
+            Plugin {
+                path: "/plugins/git".into(),
+                builtins: vec!["greet".to_owned()],
+                completions: vec!["greet".to_owned()],
+                prompt_segments: vec!["git".to_owned()],
+            }
+        );
+    }
+}
@@ -0,0 +1,380 @@
+//! The prompt template set by the `PROMPT` variable, which may contain these escapes:
+//!
+//! - `\?`: the exit status of the last command, in 3 columns
+//! - `\w`, `\W`: the current directory, and its last component
+//! - `\j`: `*` if there are jobs, followed by the number of them if two or more
+//! - `\u`, `\h`: the user name and the host name
+//! - `\t`: the current time as `HH:MM:SS`
+//! - `\d`: how long the last command took like `12.4s`, if it took `$CMD_DURATION_MIN` seconds
+//!   or longer
+//! - `\m`: the mode of the line editor, i.e. INSERT, NORMAL, VISUAL or SEARCH
+//! - `\c{...}`: colors and styles separated by spaces, e.g. `\c{bold red}`
+//! - `\p{...}`: the segment provided by a plugin, e.g. `\p{git}`
+//! - `\\`: a backslash
+//!
+//! `\c{status}` is green, red or yellow when the last command succeeded, failed or was
+//! signaled, and `\c{reset}` clears them.
+//!
+//! Before the escapes, variables like `$VAR` and command substitutions like `$(...)` are
+//! expanded by the shell as in double quotes, and `\$` is a dollar sign. Substitutions which
+//! take longer than `PROMPT_TIMEOUT` seconds, 1 by default, are killed.
+//!
+//! Instead, the output of `PROMPT_COMMAND` run by `sh -c` is shown if it is set, e.g. for
+//! starship. It is given `PROMPT_STATUS`, `PROMPT_DURATION` in milliseconds and `PROMPT_JOBS`
+//! as environment variables.
+
+/// The template used if `PROMPT` is not set
+pub const DEFAULT_PROMPT: &str = r"[\c{status}\?\c{reset}] \c{bold magenta}\w\c{reset} \j";
+
+/// What the prompt shows
+pub struct PromptContext {
+    pub status: i32,
+    pub cwd: Option<std::path::PathBuf>,
+    pub jobs: usize,
+    /// how long the last command took, if it is shown
+    pub duration: Option<std::time::Duration>,
+    /// plugins providing the segments by name
+    pub segments: std::collections::HashMap<String, std::path::PathBuf>,
+}
+
+/// Expands the escapes in `template`. The escape sequences of the colors are enclosed in
+/// parentheses so that the line editor doesn't count their width.
+pub fn expand(template: &str, ctx: &PromptContext) -> String {
+    let mut prompt = String::new();
+    let mut chars = template.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            push_text(&mut prompt, &ch.to_string());
+            continue;
+        }
+        match chars.next() {
+            Some('?') => push_text(&mut prompt, &format!("{:3}", ctx.status)),
+            Some('w') => push_text(&mut prompt, &cwd(ctx)),
+            Some('W') => {
+                let dir = match &ctx.cwd {
+                    Some(cwd) if cwd.parent().is_some() => cwd
+                        .file_name()
+                        .map_or_else(String::new, |name| name.to_string_lossy().into_owned()),
+                    _ => cwd(ctx),
+                };
+                push_text(&mut prompt, &dir);
+            }
+            Some('j') => {
+                let jobs = match ctx.jobs {
+                    0 => "".to_owned(),
+                    1 => "*".to_owned(),
+                    num => format!("*{num}"),
+                };
+                push_text(&mut prompt, &jobs);
+            }
+            Some('u') => push_text(&mut prompt, &user_name()),
+            Some('h') => push_text(&mut prompt, &host_name()),
+            Some('t') => push_text(&mut prompt, &current_time()),
+            Some('m') => prompt.push_str(crate::line_editor::MODE_INDICATOR),
+            Some('d') => {
+                if let Some(duration) = ctx.duration {
+                    push_text(&mut prompt, &format_duration(duration));
+                }
+            }
+            Some('c') => {
+                let rest = chars.as_str();
+                match rest.strip_prefix('{').and_then(|rest| rest.split_once('}')) {
+                    Some((names, rest)) => {
+                        prompt.push_str(&format!("(\x1b[{}m)", sgr_params(names, ctx.status)));
+                        chars = rest.chars();
+                    }
+                    None => push_text(&mut prompt, "\\c"),
+                }
+            }
+            Some('p') => {
+                let rest = chars.as_str();
+                match rest.strip_prefix('{').and_then(|rest| rest.split_once('}')) {
+                    Some((name, rest)) => {
+                        if let Some(plugin) = ctx.segments.get(name) {
+                            prompt.push_str(&plugin_segment(plugin, name, ctx));
+                        }
+                        chars = rest.chars();
+                    }
+                    None => push_text(&mut prompt, "\\p"),
+                }
+            }
+            Some('\\') => push_text(&mut prompt, "\\"),
+            Some(ch) => push_text(&mut prompt, &format!("\\{ch}")),
+            None => push_text(&mut prompt, "\\"),
+        }
+    }
+    prompt
+}
+
+// runs the plugin for the segment `name`, and returns the first row of its output
+fn plugin_segment(plugin: &std::path::Path, name: &str, ctx: &PromptContext) -> String {
+    let duration = ctx.duration.map_or(0, |d| d.as_millis());
+    let mut command = std::process::Command::new(plugin);
+    command
+        .args(["prompt", name])
+        .env("PROMPT_STATUS", ctx.status.to_string())
+        .env("PROMPT_DURATION", duration.to_string())
+        .env("PROMPT_JOBS", ctx.jobs.to_string());
+    match crate::completion::run_helper(&mut command) {
+        Some(output) if output.status.success() => {
+            let output = String::from_utf8_lossy(&output.stdout);
+            let first_row = output.trim_matches('\n').lines().next().unwrap_or_default();
+            from_output(first_row).1
+        }
+        _ => String::new(),
+    }
+}
+
+/// Runs `command` for the prompt with the environment `env`, and returns its output
+pub fn run_command(
+    command: &str,
+    ctx: &PromptContext,
+    env: &std::collections::HashMap<std::ffi::OsString, std::ffi::OsString>,
+) -> Option<String> {
+    use std::process::{Command, Stdio};
+
+    let duration = ctx.duration.map_or(0, |d| d.as_millis());
+    let output = Command::new("sh")
+        .args(["-c", command])
+        .env_clear()
+        .envs(env)
+        .env("PROMPT_STATUS", ctx.status.to_string())
+        .env("PROMPT_DURATION", duration.to_string())
+        .env("PROMPT_JOBS", ctx.jobs.to_string())
+        .stdin(Stdio::null())
+        .stderr(Stdio::inherit())
+        .output()
+        .ok()?;
+    String::from_utf8(output.stdout).ok()
+}
+
+/// Converts the text printed by an external prompt command to the prompt of the line editor,
+/// which doesn't count the width of escape sequences enclosed in parentheses.
+/// Returns the rows before the last one, which are printed as they are, and the last one.
+pub fn from_output(output: &str) -> (String, String) {
+    let output = output.trim_matches('\n');
+    let (above, last) = match output.rsplit_once('\n') {
+        Some((above, last)) => (format!("{above}\n"), last),
+        None => (String::new(), output),
+    };
+
+    let mut prompt = String::new();
+    let mut chars = last.chars().peekable();
+    while let Some(ch) = chars.next() {
+        if ch != '\x1b' {
+            push_text(&mut prompt, &ch.to_string());
+            continue;
+        }
+
+        // CSI ends with a byte in 0x40..=0x7e, OSC with BEL or ST, others with the next char
+        let mut seq = String::from(ch);
+        match chars.next() {
+            Some('[') => {
+                seq.push('[');
+                for ch in chars.by_ref() {
+                    seq.push(ch);
+                    if ('\x40'..='\x7e').contains(&ch) {
+                        break;
+                    }
+                }
+            }
+            Some(']') => {
+                seq.push(']');
+                while let Some(ch) = chars.next() {
+                    seq.push(ch);
+                    if ch == '\x07' || (ch == '\x1b' && chars.next_if_eq(&'\\').is_some()) {
+                        if ch == '\x1b' {
+                            seq.push('\\');
+                        }
+                        break;
+                    }
+                }
+            }
+            Some(ch) => seq.push(ch),
+            None => {}
+        }
+        prompt.push('(');
+        push_text(&mut prompt, &seq);
+        prompt.push(')');
+    }
+    (above, prompt)
+}
+
+// appends `text`, escaping the characters special to the line editor
+fn push_text(prompt: &mut String, text: &str) {
+    for ch in text.chars() {
+        if matches!(ch, '(' | ')' | '\\') {
+            prompt.push('\\');
+        }
+        prompt.push(ch);
+    }
+}
+
+/// Returns the SGR sequence for space-separated names like "bold red"
+pub fn sgr(names: &str) -> String {
+    format!("\x1b[{}m", sgr_params(names, 0))
+}
+
+// returns the parameters of SGR for space-separated names like "bold red", or numbers like "100"
+fn sgr_params(names: &str, status: i32) -> String {
+    let params: Vec<&str> = names
+        .split_whitespace()
+        .filter_map(|name| {
+            let param = match name {
+                "reset" => "0",
+                "bold" => "1",
+                "dim" => "2",
+                "italic" => "3",
+                "underline" => "4",
+                "black" => "30",
+                "red" => "31",
+                "green" => "32",
+                "yellow" => "33",
+                "blue" => "34",
+                "magenta" => "35",
+                "cyan" => "36",
+                "white" => "37",
+                "status" if status == 0 => "32",
+                "status" if status < 128 => "31",
+                "status" => "33",
+                _ if name.parse::<u8>().is_ok() => name,
+                _ => return None,
+            };
+            Some(param)
+        })
+        .collect();
+    params.join(";")
+}
+
+// the current directory where the home directory is abbreviated to `~`
+fn cwd(ctx: &PromptContext) -> String {
+    match &ctx.cwd {
+        None => "unknown".to_owned(),
+        Some(cwd) => abbreviate_home(cwd),
+    }
+}
+
+/// Returns `path` where the home directory is abbreviated to `~`
+pub fn abbreviate_home(path: &std::path::Path) -> String {
+    std::env::var("HOME")
+        .ok()
+        .and_then(|home| path.strip_prefix(&home).ok())
+        .map(|p| format!("~/{}", p.display()))
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Sets the title of the terminal window with OSC 2
+pub fn set_title(title: &str) {
+    use std::io::Write as _;
+    let title: String = title.chars().filter(|ch| !ch.is_control()).collect();
+    print!("\x1b]2;{title}\x07");
+    let _ = std::io::stdout().flush();
+}
+
+/// Pushes the title of the terminal on its stack
+pub fn save_title() {
+    print!("\x1b[22;0t");
+}
+
+/// Restores the title saved by `save_title`
+pub fn restore_title() {
+    use std::io::Write as _;
+    print!("\x1b[23;0t");
+    let _ = std::io::stdout().flush();
+}
+
+/// Moves the cursor to the start of a new line if the output of the last command doesn't end
+/// with a newline, leaving an inverse `%` after it.
+/// The spaces wrap the line only if the cursor isn't at the first column.
+pub fn start_on_new_line(columns: usize) {
+    use std::io::Write as _;
+    let spaces = " ".repeat(columns.saturating_sub(1));
+    print!("\x1b[m\x1b[7m%\x1b[m{spaces}\r \r");
+    let _ = std::io::stdout().flush();
+}
+
+fn user_name() -> String {
+    std::env::var("USER").unwrap_or_else(|_| {
+        nix::unistd::User::from_uid(nix::unistd::getuid())
+            .ok()
+            .flatten()
+            .map_or_else(String::new, |user| user.name)
+    })
+}
+
+fn host_name() -> String {
+    let host = nix::unistd::gethostname().unwrap_or_default();
+    // only the first component of the domain name
+    let host = host.to_string_lossy();
+    host.split('.').next().unwrap_or_default().to_owned()
+}
+
+/// Formats a duration like "12.4s", "3m5s" or "1h20m"
+pub fn format_duration(duration: std::time::Duration) -> String {
+    let secs = duration.as_secs();
+    match secs {
+        0..=59 => format!("{:.1}s", duration.as_secs_f64()),
+        60..=3599 => format!("{}m{}s", secs / 60, secs % 60),
+        _ => format!("{}h{}m", secs / 3600, secs % 3600 / 60),
+    }
+}
+
+fn current_time() -> String {
+    let mut tm = unsafe { std::mem::zeroed::<nix::libc::tm>() };
+    unsafe {
+        let now = nix::libc::time(std::ptr::null_mut());
+        nix::libc::localtime_r(&now, &mut tm);
+    }
+    format!("{:02}:{:02}:{:02}", tm.tm_hour, tm.tm_min, tm.tm_sec)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prompt_template() {
+        let ctx = PromptContext {
+            status: 1,
+            cwd: Some("/tmp/(foo)".into()),
+            jobs: 2,
+            duration: Some(std::time::Duration::from_millis(12_345)),
+            segments: [("none".to_owned(), "/nonexistent".into())].into(),
+        };
+        assert_eq!(
+            expand(DEFAULT_PROMPT, &ctx),
+            "[(\x1b[31m)  1(\x1b[0m)] (\x1b[1;35m)/tmp/\\(foo\\)(\x1b[0m) *2"
+        );
+        assert_eq!(
+            expand(r"\W \c{unknown}\\\q", &ctx),
+            "\\(foo\\) (\x1b[m)\\\\\\\\q"
+        );
+        assert_eq!(expand(r"\c{red", &ctx), "\\\\c{red");
+        assert_eq!(expand(r"<\p{none}\p{unknown}>", &ctx), "<>");
+        assert_eq!(expand(r"took \d", &ctx), "took 12.3s");
+
+        let ctx = PromptContext {
+            duration: Some(std::time::Duration::from_secs(3725)),
+            ..ctx
+        };
+        assert_eq!(expand(r"\d", &ctx), "1h2m");
+    }
+
+    #[test]
+    fn external_prompt() {
+        assert_eq!(
+            from_output("\n~/src (main)\n\x1b[1;32m❯\x1b[0m "),
+            (
+                "~/src (main)\n".to_owned(),
+                "(\x1b[1;32m)❯(\x1b[0m) ".to_owned()
+            )
+        );
+        assert_eq!(
+            from_output("\x1b]8;;file:///(a)\x1b\\a\x1b]8;;\x07$ "),
+            (
+                String::new(),
+                "(\x1b]8;;file:///\\(a\\)\x1b\\\\)a(\x1b]8;;\x07)$ ".to_owned()
+            )
+        );
+    }
+}
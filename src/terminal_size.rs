@@ -1,9 +1,14 @@
 #![allow(unused)]
 
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::os::unix::io::RawFd;
+use std::sync::atomic::{AtomicI32, AtomicU16, Ordering};
 static ROWS: AtomicU16 = AtomicU16::new(0);
 static COLS: AtomicU16 = AtomicU16::new(0);
 
+// the pipe written by the signal handler on resize to wake up the line editor
+static RESIZE_PIPE_READ: AtomicI32 = AtomicI32::new(-1);
+static RESIZE_PIPE_WRITE: AtomicI32 = AtomicI32::new(-1);
+
 /// Returns the number of terminal rows
 pub fn get_rows() -> u16 {
     ROWS.load(Ordering::SeqCst)
@@ -30,10 +35,30 @@ pub fn update() {
     COLS.store(winsize.ws_col, Ordering::SeqCst);
 }
 
+/// Returns the file descriptor which becomes readable when the terminal is resized
+pub fn resize_fd() -> Option<RawFd> {
+    let fd = RESIZE_PIPE_READ.load(Ordering::SeqCst);
+    (fd >= 0).then_some(fd)
+}
+
+/// Drains the notifications of resize written to `resize_fd`
+pub fn clear_resized() {
+    if let Some(fd) = resize_fd() {
+        let mut buf = [0; 16];
+        while matches!(nix::unistd::read(fd, &mut buf), Ok(n) if n > 0) {}
+    }
+}
+
 /// Installs a signal handler for SIGWINCH
 pub fn install_sigwinch_handler() {
     update();
 
+    use nix::fcntl::OFlag;
+    if let Ok((read_fd, write_fd)) = nix::unistd::pipe2(OFlag::O_CLOEXEC | OFlag::O_NONBLOCK) {
+        RESIZE_PIPE_READ.store(read_fd, Ordering::SeqCst);
+        RESIZE_PIPE_WRITE.store(write_fd, Ordering::SeqCst);
+    }
+
     use nix::sys::signal::{sigaction, SaFlags, SigAction, SigHandler, SigSet, Signal};
     let handler = SigHandler::Handler(sigwinch_handler);
     let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
@@ -42,4 +67,10 @@ pub fn install_sigwinch_handler() {
 
 extern "C" fn sigwinch_handler(_: i32) {
     update();
+
+    let fd = RESIZE_PIPE_WRITE.load(Ordering::SeqCst);
+    if fd >= 0 {
+        // the pipe may be full if nobody reads it, which is fine
+        let _ = nix::unistd::write(fd, &[0]);
+    }
 }
@@ -0,0 +1,267 @@
+//! The subset of TOML read from `config.toml` and the completion definitions: tables with
+//! dotted names, bare or quoted keys, strings, integers, booleans, and arrays which may span
+//! multiple lines and end with a trailing comma.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    String(String),
+    Integer(i64),
+    Boolean(bool),
+    Array(Vec<Value>),
+}
+
+/// A table with its entries in the order written
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Table {
+    /// the dotted name in the header, or empty for the entries before any header
+    pub name: String,
+    pub entries: Vec<(String, Value)>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    /// a bare key, or a boolean or an integer
+    Bare(String),
+    Str(String),
+    Punct(char),
+    Newline,
+}
+
+fn is_bare(ch: char) -> bool {
+    ch.is_ascii_alphanumeric() || "+-_".contains(ch)
+}
+
+// splits `text` into tokens with their line numbers
+fn tokenize(text: &str) -> Result<Vec<(usize, Token)>, String> {
+    let mut tokens = Vec::new();
+    let mut lineno = 1;
+    let mut chars = text.chars().peekable();
+
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\n' => {
+                tokens.push((lineno, Token::Newline));
+                lineno += 1;
+            }
+            '#' => while chars.next_if(|c| *c != '\n').is_some() {},
+            '=' | '[' | ']' | ',' | '.' => tokens.push((lineno, Token::Punct(ch))),
+            '"' | '\'' => {
+                let string =
+                    parse_string(ch, &mut chars).map_err(|err| format!("line {lineno}: {err}"))?;
+                tokens.push((lineno, Token::Str(string)));
+            }
+            _ if ch.is_whitespace() => {}
+            _ if is_bare(ch) => {
+                let mut word = ch.to_string();
+                while let Some(c) = chars.next_if(|c| is_bare(*c)) {
+                    word.push(c);
+                }
+                tokens.push((lineno, Token::Bare(word)));
+            }
+            _ => return Err(format!("line {lineno}: unexpected `{ch}`")),
+        }
+    }
+    tokens.push((lineno, Token::Newline));
+    Ok(tokens)
+}
+
+// a basic string in double quotes with escapes, or a literal one in single quotes
+fn parse_string(
+    quote: char,
+    chars: &mut std::iter::Peekable<std::str::Chars>,
+) -> Result<String, String> {
+    let mut string = String::new();
+    loop {
+        match chars.next() {
+            None | Some('\n') => return Err("unterminated string".to_owned()),
+            Some(ch) if ch == quote => return Ok(string),
+            Some('\\') if quote == '"' => {
+                let ch = match chars.next() {
+                    Some('b') => '\x08',
+                    Some('t') => '\t',
+                    Some('n') => '\n',
+                    Some('f') => '\x0c',
+                    Some('r') => '\r',
+                    Some('e') => '\x1b',
+                    Some('"') => '"',
+                    Some('\\') => '\\',
+                    Some(u @ ('u' | 'U')) => {
+                        let len = if u == 'u' { 4 } else { 8 };
+                        let hex: String = chars.take(len).collect();
+                        u32::from_str_radix(&hex, 16)
+                            .ok()
+                            .filter(|_| hex.len() == len)
+                            .and_then(char::from_u32)
+                            .ok_or_else(|| format!("invalid escape `\\{u}{hex}`"))?
+                    }
+                    _ => return Err("invalid escape".to_owned()),
+                };
+                string.push(ch);
+            }
+            Some(ch) => string.push(ch),
+        }
+    }
+}
+
+struct Parser {
+    tokens: std::iter::Peekable<std::vec::IntoIter<(usize, Token)>>,
+    lineno: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<Token> {
+        let (lineno, tok) = self.tokens.next()?;
+        self.lineno = lineno;
+        Some(tok)
+    }
+
+    // consumes the next token if it is `expected`
+    fn eat(&mut self, expected: &Token) -> bool {
+        if self.tokens.peek().map(|(_, tok)| tok) == Some(expected) {
+            self.next();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, msg: &str) -> String {
+        format!("line {}: {msg}", self.lineno)
+    }
+
+    fn key_of(&self, tok: Option<Token>) -> Result<String, String> {
+        match tok {
+            Some(Token::Str(key)) => Ok(key),
+            Some(Token::Bare(key)) if !key.contains('+') => Ok(key),
+            _ => Err(self.error("expected a key")),
+        }
+    }
+
+    fn key(&mut self) -> Result<String, String> {
+        let tok = self.next();
+        self.key_of(tok)
+    }
+
+    fn value(&mut self) -> Result<Value, String> {
+        match self.next() {
+            Some(Token::Str(string)) => Ok(Value::String(string)),
+            Some(Token::Bare(word)) => match word.as_str() {
+                "true" => Ok(Value::Boolean(true)),
+                "false" => Ok(Value::Boolean(false)),
+                _ => match word.replace('_', "").parse() {
+                    Ok(n) => Ok(Value::Integer(n)),
+                    Err(_) => Err(self.error(&format!("invalid value `{word}`"))),
+                },
+            },
+            Some(Token::Punct('[')) => {
+                let mut items = Vec::new();
+                loop {
+                    while self.eat(&Token::Newline) {}
+                    if self.eat(&Token::Punct(']')) {
+                        break;
+                    }
+                    items.push(self.value()?);
+                    while self.eat(&Token::Newline) {}
+                    match self.next() {
+                        Some(Token::Punct(',')) => continue,
+                        Some(Token::Punct(']')) => break,
+                        _ => return Err(self.error("expected `,` or `]`")),
+                    }
+                }
+                Ok(Value::Array(items))
+            }
+            _ => Err(self.error("expected a value")),
+        }
+    }
+}
+
+/// Parses the subset of TOML, and returns the error with its line number if any
+pub fn parse(text: &str) -> Result<Vec<Table>, String> {
+    let mut parser = Parser {
+        tokens: tokenize(text)?.into_iter().peekable(),
+        lineno: 1,
+    };
+
+    let mut tables = vec![Table::default()];
+    while let Some(tok) = parser.next() {
+        match tok {
+            Token::Newline => continue,
+            Token::Punct('[') => {
+                let mut keys = vec![parser.key()?];
+                loop {
+                    match parser.next() {
+                        Some(Token::Punct('.')) => keys.push(parser.key()?),
+                        Some(Token::Punct(']')) => break,
+                        _ => return Err(parser.error("expected `]`")),
+                    }
+                }
+                let name = keys.join(".");
+                if tables.iter().any(|table| table.name == name) {
+                    return Err(parser.error(&format!("table [{name}] is defined twice")));
+                }
+                tables.push(Table {
+                    name,
+                    entries: Vec::new(),
+                });
+            }
+            tok => {
+                let key = parser.key_of(Some(tok))?;
+                if !parser.eat(&Token::Punct('=')) {
+                    return Err(parser.error("expected `=`"));
+                }
+                let value = parser.value()?;
+
+                let table = tables.last_mut().unwrap();
+                if table.entries.iter().any(|(k, _)| *k == key) {
+                    return Err(parser.error(&format!("{key} is defined twice")));
+                }
+                table.entries.push((key, value));
+            }
+        }
+
+        if !parser.eat(&Token::Newline) {
+            parser.next();
+            return Err(parser.error("expected the end of the line"));
+        }
+    }
+
+    tables.retain(|table| !(table.name.is_empty() && table.entries.is_empty()));
+    Ok(tables)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn multiline_array() {
+        let tables = parse("x = [\n  'a', # comment\n  [1, true],\n]\ny = []").unwrap();
+        assert_eq!(
+            tables[0].entries,
+            vec![
+                (
+                    "x".to_owned(),
+                    Value::Array(vec![
+                        Value::String("a".to_owned()),
+                        Value::Array(vec![Value::Integer(1), Value::Boolean(true)]),
+                    ])
+                ),
+                ("y".to_owned(), Value::Array(Vec::new())),
+            ]
+        );
+
+        assert_eq!(parse("x = [1,,]").unwrap_err(), "line 1: expected a value");
+        assert_eq!(
+            parse("x = [1 2]").unwrap_err(),
+            "line 1: expected `,` or `]`"
+        );
+        assert_eq!(
+            parse("x = 1 2").unwrap_err(),
+            "line 1: expected the end of the line"
+        );
+        assert_eq!(
+            parse("x = \"a\nb\"").unwrap_err(),
+            "line 1: unterminated string"
+        );
+    }
+}
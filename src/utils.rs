@@ -17,3 +17,51 @@ impl<F: FnOnce()> Drop for Defer<F> {
         }
     }
 }
+
+/// Computes the edit distance between `a` and `b`,
+/// counting a transposition of two adjacent characters as a single edit
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+
+    // dp[i][j]: the distance between a[..i] and b[..j]
+    let mut dp = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for (i, row) in dp.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in dp[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = if a[i - 1] == b[j - 1] { 0 } else { 1 };
+            dp[i][j] = (dp[i - 1][j] + 1)
+                .min(dp[i][j - 1] + 1)
+                .min(dp[i - 1][j - 1] + cost);
+
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                dp[i][j] = dp[i][j].min(dp[i - 2][j - 2] + 1);
+            }
+        }
+    }
+
+    dp[a.len()][b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_distance_basic() {
+        assert_eq!(edit_distance("", ""), 0);
+        assert_eq!(edit_distance("git", "git"), 0);
+        assert_eq!(edit_distance("", "git"), 3);
+        assert_eq!(edit_distance("gti", "git"), 1);
+        assert_eq!(edit_distance("gi", "git"), 1);
+        assert_eq!(edit_distance("gitt", "git"), 1);
+        assert_eq!(edit_distance("got", "git"), 1);
+        assert_eq!(edit_distance("kitten", "sitting"), 3);
+    }
+}